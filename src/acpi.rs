@@ -0,0 +1,566 @@
+//! ACPI table discovery.
+//!
+//! The tables are found once at boot through the bootloader's mapping of
+//! physical memory; tables whose checksum does not add up are listed but never
+//! handed out to drivers.
+
+use crate::paging;
+use alloc::vec::Vec;
+use spin::Once;
+use x86_64::PhysAddr;
+
+const RSDP_SIGNATURE: [u8; 8] = *b"RSD PTR ";
+
+/// Bytes of the RSDP covered by the ACPI 1.0 checksum.
+const RSDP_V1_LENGTH: usize = 20;
+
+/// Physical address of the BIOS data area word holding the EBDA segment.
+const EBDA_SEGMENT_POINTER: u64 = 0x40e;
+const BIOS_AREA_START: u64 = 0xe0000;
+const BIOS_AREA_END: u64 = 0x100000;
+
+/// Reads a `T` from physical memory.
+///
+/// # Safety
+///
+/// `addr` must be backed by memory that is valid to read as a `T`.
+unsafe fn read_phys<T: Copy>(addr: u64) -> T {
+    let ptr = paging::phys_to_virt(PhysAddr::new(addr)).as_ptr::<T>();
+    unsafe { ptr.read_unaligned() }
+}
+
+/// Copies `len` bytes of physical memory starting at `addr`.
+///
+/// # Safety
+///
+/// The whole range must be backed by readable memory.
+unsafe fn copy_phys(addr: u64, len: usize) -> Vec<u8> {
+    let ptr = paging::phys_to_virt(PhysAddr::new(addr)).as_ptr::<u8>();
+    unsafe { core::slice::from_raw_parts(ptr, len) }.to_vec()
+}
+
+/// Copies the table at `address` out of physical memory, unless the length in
+/// its header is too short for the header or longer than any real table.
+///
+/// # Safety
+///
+/// `address` must be backed by readable memory, and so must the table if its
+/// length is plausible.
+unsafe fn copy_table(address: u64) -> Option<Vec<u8>> {
+    let header: SdtHeader = unsafe { read_phys(address) };
+    let len = header.length as usize;
+    (HEADER_SIZE..=MAX_TABLE_LENGTH)
+        .contains(&len)
+        .then(|| unsafe { copy_phys(address, len) })
+}
+
+/// ACPI checksums make all bytes of a structure add up to zero.
+fn checksum_ok(data: &[u8]) -> bool {
+    data.iter().fold(0u8, |sum, &byte| sum.wrapping_add(byte)) == 0
+}
+
+#[derive(Debug, Clone, Copy)]
+#[repr(C, packed)]
+struct Rsdp {
+    signature: [u8; 8],
+    checksum: u8,
+    oem_id: [u8; 6],
+    revision: u8,
+    rsdt_address: u32,
+    // the rest only exists from revision 2 on
+    length: u32,
+    xsdt_address: u64,
+    extended_checksum: u8,
+    reserved: [u8; 3],
+}
+
+/// Header shared by every system description table.
+#[derive(Debug, Clone, Copy)]
+#[repr(C, packed)]
+pub struct SdtHeader {
+    pub signature: [u8; 4],
+    pub length: u32,
+    pub revision: u8,
+    pub checksum: u8,
+    pub oem_id: [u8; 6],
+    pub oem_table_id: [u8; 8],
+    pub oem_revision: u32,
+    pub creator_id: u32,
+    pub creator_revision: u32,
+}
+
+const HEADER_SIZE: usize = core::mem::size_of::<SdtHeader>();
+
+/// Longest table copied; a header claiming more is corrupt.
+const MAX_TABLE_LENGTH: usize = 1 << 20;
+
+impl SdtHeader {
+    pub fn signature(&self) -> &str {
+        core::str::from_utf8(&self.signature).unwrap_or("????")
+    }
+
+    pub fn oem_id(&self) -> &str {
+        core::str::from_utf8(&self.oem_id).unwrap_or("").trim_end()
+    }
+}
+
+/// A table listed by the RSDT or XSDT.
+#[derive(Debug, Clone, Copy)]
+pub struct TableInfo {
+    pub address: u64,
+    pub header: SdtHeader,
+    pub checksum_ok: bool,
+}
+
+static TABLES: Once<Vec<TableInfo>> = Once::new();
+
+/// Looks for the RSDP in the first KiB of the EBDA, then in the BIOS read-only area.
+fn find_rsdp() -> Option<Rsdp> {
+    let ebda = u64::from(unsafe { read_phys::<u16>(EBDA_SEGMENT_POINTER) }) << 4;
+    let areas = [(ebda, ebda + 1024), (BIOS_AREA_START, BIOS_AREA_END)];
+    areas
+        .into_iter()
+        .filter(|&(start, _)| start != 0)
+        .flat_map(|(start, end)| (start..end).step_by(16))
+        .filter(|&addr| unsafe { read_phys::<[u8; 8]>(addr) } == RSDP_SIGNATURE)
+        .find_map(|addr| {
+            if !checksum_ok(&unsafe { copy_phys(addr, RSDP_V1_LENGTH) }) {
+                return None;
+            }
+            let rsdp: Rsdp = unsafe { read_phys(addr) };
+            if rsdp.revision >= 2 {
+                let len = rsdp.length as usize;
+                if !(core::mem::size_of::<Rsdp>()..=MAX_TABLE_LENGTH).contains(&len)
+                    || !checksum_ok(&unsafe { copy_phys(addr, len) })
+                {
+                    return None;
+                }
+            }
+            Some(rsdp)
+        })
+}
+
+/// Walks the XSDT, or the RSDT on ACPI 1.0 systems, and records every table it
+/// lists. Does nothing if no valid RSDP or root table is found.
+pub fn init() {
+    TABLES.call_once(|| {
+        let Some(rsdp) = find_rsdp() else {
+            return Vec::new();
+        };
+        let (root, entry_size) = if rsdp.revision >= 2 && rsdp.xsdt_address != 0 {
+            (rsdp.xsdt_address, 8)
+        } else {
+            (u64::from(rsdp.rsdt_address), 4)
+        };
+
+        let Some(data) = unsafe { copy_table(root) }.filter(|data| checksum_ok(data)) else {
+            return Vec::new();
+        };
+        data.get(HEADER_SIZE..)
+            .unwrap_or_default()
+            .chunks_exact(entry_size)
+            .map(|entry| match *entry {
+                [a, b, c, d] => u64::from(u32::from_le_bytes([a, b, c, d])),
+                _ => u64::from_le_bytes(entry.try_into().unwrap()),
+            })
+            .filter(|&address| address != 0)
+            .map(|address| {
+                let header: SdtHeader = unsafe { read_phys(address) };
+                let data = unsafe { copy_table(address) };
+                TableInfo {
+                    address,
+                    header,
+                    checksum_ok: data.is_some_and(|data| checksum_ok(&data)),
+                }
+            })
+            .collect()
+    });
+}
+
+/// Every table found by `init`, including ones with a bad checksum.
+pub fn tables() -> &'static [TableInfo] {
+    TABLES.get().map_or(&[], Vec::as_slice)
+}
+
+/// Copies the first valid table with the given signature out of physical memory.
+pub fn find_table(signature: &[u8; 4]) -> Option<Vec<u8>> {
+    tables()
+        .iter()
+        .find(|table| table.checksum_ok && table.header.signature == *signature)
+        .and_then(|table| unsafe { copy_table(table.address) })
+}
+
+/// Copies the table at `address`, which need not be listed in the RSDT, if
+/// its checksum is valid.
+pub fn read_table(address: u64) -> Option<Vec<u8>> {
+    unsafe { copy_table(address) }.filter(|data| checksum_ok(data))
+}
+
+fn read_u16(data: &[u8], offset: usize) -> Option<u16> {
+    Some(u16::from_le_bytes(
+        data.get(offset..offset + 2)?.try_into().ok()?,
+    ))
+}
+
+fn read_u32(data: &[u8], offset: usize) -> Option<u32> {
+    Some(u32::from_le_bytes(
+        data.get(offset..offset + 4)?.try_into().ok()?,
+    ))
+}
+
+fn read_u64(data: &[u8], offset: usize) -> Option<u64> {
+    Some(u64::from_le_bytes(
+        data.get(offset..offset + 8)?.try_into().ok()?,
+    ))
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AddressSpace {
+    Memory,
+    Io,
+    PciConfig,
+    Other(u8),
+}
+
+/// Generic Address Structure: a register in memory, I/O or PCI config space.
+#[derive(Debug, Clone, Copy)]
+pub struct GenericAddress {
+    pub space: AddressSpace,
+    pub bit_width: u8,
+    pub bit_offset: u8,
+    pub access_size: u8,
+    pub address: u64,
+}
+
+impl GenericAddress {
+    fn parse(data: &[u8], offset: usize) -> Option<Self> {
+        let bytes = data.get(offset..offset + 12)?;
+        Some(Self {
+            space: match bytes[0] {
+                0 => AddressSpace::Memory,
+                1 => AddressSpace::Io,
+                2 => AddressSpace::PciConfig,
+                other => AddressSpace::Other(other),
+            },
+            bit_width: bytes[1],
+            bit_offset: bytes[2],
+            access_size: bytes[3],
+            address: read_u64(bytes, 4)?,
+        })
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct LocalApicInfo {
+    pub processor_id: u8,
+    pub apic_id: u8,
+    pub enabled: bool,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct IoApicInfo {
+    pub id: u8,
+    pub address: u64,
+    /// First global system interrupt handled by this I/O APIC.
+    pub gsi_base: u32,
+}
+
+/// An ISA IRQ that is not wired to the GSI of the same number.
+#[derive(Debug, Clone, Copy)]
+pub struct InterruptOverride {
+    pub irq: u8,
+    pub gsi: u32,
+    /// MPS INTI flags: polarity in bits 0-1, trigger mode in bits 2-3.
+    pub flags: u16,
+}
+
+const MADT_PCAT_COMPAT: u32 = 0x01;
+
+const MADT_LOCAL_APIC: u8 = 0;
+const MADT_IO_APIC: u8 = 1;
+const MADT_INTERRUPT_OVERRIDE: u8 = 2;
+const MADT_LOCAL_APIC_OVERRIDE: u8 = 5;
+
+/// Multiple APIC Description Table.
+#[derive(Debug, Clone)]
+pub struct Madt {
+    pub local_apic_address: u64,
+    /// Whether the machine also has a pair of 8259 PICs.
+    pub pcat_compat: bool,
+    pub local_apics: Vec<LocalApicInfo>,
+    pub io_apics: Vec<IoApicInfo>,
+    pub overrides: Vec<InterruptOverride>,
+}
+
+impl Madt {
+    pub fn find() -> Option<Self> {
+        Self::parse(&find_table(b"APIC")?)
+    }
+
+    fn parse(data: &[u8]) -> Option<Self> {
+        let mut madt = Madt {
+            local_apic_address: u64::from(read_u32(data, HEADER_SIZE)?),
+            pcat_compat: read_u32(data, HEADER_SIZE + 4)? & MADT_PCAT_COMPAT != 0,
+            local_apics: Vec::new(),
+            io_apics: Vec::new(),
+            overrides: Vec::new(),
+        };
+
+        let mut offset = HEADER_SIZE + 8;
+        while let (Some(&kind), Some(&len)) = (data.get(offset), data.get(offset + 1)) {
+            let len = usize::from(len);
+            if len < 2 {
+                break;
+            }
+            let entry = data.get(offset..offset + len)?;
+            match kind {
+                MADT_LOCAL_APIC => madt.local_apics.push(LocalApicInfo {
+                    processor_id: *entry.get(2)?,
+                    apic_id: *entry.get(3)?,
+                    enabled: read_u32(entry, 4)? & 1 != 0,
+                }),
+                MADT_IO_APIC => madt.io_apics.push(IoApicInfo {
+                    id: *entry.get(2)?,
+                    address: u64::from(read_u32(entry, 4)?),
+                    gsi_base: read_u32(entry, 8)?,
+                }),
+                MADT_INTERRUPT_OVERRIDE => madt.overrides.push(InterruptOverride {
+                    irq: *entry.get(3)?,
+                    gsi: read_u32(entry, 4)?,
+                    flags: read_u16(entry, 8)?,
+                }),
+                MADT_LOCAL_APIC_OVERRIDE => madt.local_apic_address = read_u64(entry, 4)?,
+                _ => {}
+            }
+            offset += len;
+        }
+
+        Some(madt)
+    }
+}
+
+/// The FADT says the reset register may be used.
+pub const FADT_RESET_REG_SUP: u32 = 1 << 10;
+
+/// Fixed ACPI Description Table, reduced to the fields drivers use.
+#[derive(Debug, Clone, Copy)]
+pub struct Fadt {
+    pub dsdt: u64,
+    pub sci_interrupt: u16,
+    pub smi_command: u32,
+    pub acpi_enable: u8,
+    pub acpi_disable: u8,
+    pub pm1a_control: u32,
+    pub pm1b_control: u32,
+    pub pm_timer: u32,
+    /// CMOS RAM index of the century, or 0 if there is none.
+    pub century: u8,
+    pub boot_flags: u16,
+    pub flags: u32,
+    pub reset_register: Option<GenericAddress>,
+    pub reset_value: u8,
+}
+
+impl Fadt {
+    pub fn find() -> Option<Self> {
+        Self::parse(&find_table(b"FACP")?)
+    }
+
+    fn parse(data: &[u8]) -> Option<Self> {
+        // the 64-bit DSDT address takes precedence in ACPI 2.0 and later
+        let dsdt = match read_u64(data, 140) {
+            Some(x_dsdt) if x_dsdt != 0 => x_dsdt,
+            _ => u64::from(read_u32(data, 40)?),
+        };
+        Some(Self {
+            dsdt,
+            sci_interrupt: read_u16(data, 46)?,
+            smi_command: read_u32(data, 48)?,
+            acpi_enable: *data.get(52)?,
+            acpi_disable: *data.get(53)?,
+            pm1a_control: read_u32(data, 64)?,
+            pm1b_control: read_u32(data, 68)?,
+            pm_timer: read_u32(data, 76)?,
+            century: *data.get(108)?,
+            // ACPI 1.0 tables end before the boot flags
+            boot_flags: read_u16(data, 109).unwrap_or(0),
+            flags: read_u32(data, 112).unwrap_or(0),
+            reset_register: GenericAddress::parse(data, 116),
+            reset_value: data.get(128).copied().unwrap_or(0),
+        })
+    }
+}
+
+const AML_NAME_OP: u8 = 0x08;
+const AML_PACKAGE_OP: u8 = 0x12;
+const AML_BYTE_PREFIX: u8 = 0x0a;
+const AML_ROOT_CHAR: u8 = b'\\';
+
+/// Returns the SLP_TYPa and SLP_TYPb values for the S5 (soft off) sleep state.
+///
+/// Rather than running an AML interpreter, this looks for the `\_S5_` package
+/// in the DSDT and reads its first two elements.
+pub fn s5_sleep_types() -> Option<(u8, u8)> {
+    let dsdt = read_table(Fadt::find()?.dsdt)?;
+    parse_s5(dsdt.get(HEADER_SIZE..)?)
+}
+
+fn parse_s5(aml: &[u8]) -> Option<(u8, u8)> {
+    // a match may also be a reference to the name, or unrelated data
+    aml.windows(4)
+        .enumerate()
+        .filter(|(_, window)| *window == b"_S5_")
+        .find_map(|(name, _)| parse_s5_at(aml, name))
+}
+
+/// Parses the package defined for the `_S5_` name at `name`.
+fn parse_s5_at(aml: &[u8], name: usize) -> Option<(u8, u8)> {
+    let defined_by_name = match name {
+        0 => false,
+        1 => aml[0] == AML_NAME_OP,
+        _ => {
+            aml[name - 1] == AML_NAME_OP
+                || (aml[name - 1] == AML_ROOT_CHAR && aml[name - 2] == AML_NAME_OP)
+        }
+    };
+    if !defined_by_name || *aml.get(name + 4)? != AML_PACKAGE_OP {
+        return None;
+    }
+
+    // the top two bits of the PkgLength lead byte count the bytes that follow it
+    let pkg_length = name + 5;
+    let num_elements = pkg_length + 1 + usize::from(*aml.get(pkg_length)? >> 6);
+    let mut offset = num_elements + 1;
+    let mut element = || -> Option<u8> {
+        let mut value = *aml.get(offset)?;
+        offset += 1;
+        if value == AML_BYTE_PREFIX {
+            value = *aml.get(offset)?;
+            offset += 1;
+        }
+        Some(value)
+    };
+    Some((element()?, element()?))
+}
+
+/// High Precision Event Timer description.
+#[derive(Debug, Clone, Copy)]
+pub struct Hpet {
+    pub address: GenericAddress,
+    pub number: u8,
+    pub vendor_id: u16,
+    pub comparators: u8,
+    pub counter_64bit: bool,
+    /// Minimum clock ticks for periodic mode without lost interrupts.
+    pub minimum_tick: u16,
+}
+
+impl Hpet {
+    pub fn find() -> Option<Self> {
+        Self::parse(&find_table(b"HPET")?)
+    }
+
+    fn parse(data: &[u8]) -> Option<Self> {
+        let block_id = read_u32(data, HEADER_SIZE)?;
+        Some(Self {
+            address: GenericAddress::parse(data, HEADER_SIZE + 4)?,
+            number: *data.get(HEADER_SIZE + 16)?,
+            vendor_id: (block_id >> 16) as u16,
+            comparators: ((block_id >> 8) & 0x1f) as u8 + 1,
+            counter_64bit: block_id & (1 << 13) != 0,
+            minimum_tick: read_u16(data, HEADER_SIZE + 17)?,
+        })
+    }
+}
+
+/// A range of PCI buses whose configuration space is memory mapped.
+#[derive(Debug, Clone, Copy)]
+pub struct McfgEntry {
+    pub base_address: u64,
+    pub segment: u16,
+    pub start_bus: u8,
+    pub end_bus: u8,
+}
+
+/// PCI Express memory mapped configuration space description.
+#[derive(Debug, Clone)]
+pub struct Mcfg {
+    pub entries: Vec<McfgEntry>,
+}
+
+impl Mcfg {
+    pub fn find() -> Option<Self> {
+        Self::parse(&find_table(b"MCFG")?)
+    }
+
+    fn parse(data: &[u8]) -> Option<Self> {
+        let entries = data
+            .get(HEADER_SIZE + 8..)?
+            .chunks_exact(16)
+            .map(|entry| McfgEntry {
+                base_address: read_u64(entry, 0).unwrap_or(0),
+                segment: read_u16(entry, 8).unwrap_or(0),
+                start_bus: entry[10],
+                end_bus: entry[11],
+            })
+            .collect();
+        Some(Self { entries })
+    }
+}
+
+#[test_case]
+fn test_parse_madt() {
+    let mut data = alloc::vec![0u8; HEADER_SIZE];
+    data.extend_from_slice(&0xfee0_0000u32.to_le_bytes());
+    data.extend_from_slice(&1u32.to_le_bytes());
+    // local APIC, I/O APIC, and IRQ0 redirected to GSI2
+    data.extend_from_slice(&[0, 8, 0, 0, 1, 0, 0, 0]);
+    data.extend_from_slice(&[1, 12, 0, 0, 0, 0, 0xc0, 0xfe, 0, 0, 0, 0]);
+    data.extend_from_slice(&[2, 10, 0, 0, 2, 0, 0, 0, 0, 0]);
+
+    let madt = Madt::parse(&data).unwrap();
+    assert_eq!(madt.local_apic_address, 0xfee0_0000);
+    assert!(madt.pcat_compat);
+    assert_eq!(madt.local_apics.len(), 1);
+    assert!(madt.local_apics[0].enabled);
+    assert_eq!(madt.io_apics.len(), 1);
+    assert_eq!(madt.io_apics[0].address, 0xfec0_0000);
+    assert_eq!(madt.overrides.len(), 1);
+    assert_eq!((madt.overrides[0].irq, madt.overrides[0].gsi), (0, 2));
+
+    // a local APIC entry too short for its fields
+    data.extend_from_slice(&[0, 3, 0]);
+    assert!(Madt::parse(&data).is_none());
+}
+
+#[test_case]
+fn test_parse_s5() {
+    // Name (\_S5, Package (0x04) { 0x05, Zero, Zero, Zero })
+    let aml = [
+        0x10, 0x08, 0x08, b'\\', b'_', b'S', b'5', b'_', 0x12, 0x08, 0x04, 0x0a, 0x05, 0x00, 0x00,
+        0x00,
+    ];
+    assert_eq!(parse_s5(&aml), Some((5, 0)));
+    assert_eq!(parse_s5(b"_S4_"), None);
+    // a reference to the name comes first
+    let mut referenced = b"\x70_S5_\x60".to_vec();
+    referenced.extend_from_slice(&aml);
+    assert_eq!(parse_s5(&referenced), Some((5, 0)));
+}
+
+#[test_case]
+fn test_parse_mcfg() {
+    let mut data = alloc::vec![0u8; HEADER_SIZE + 8];
+    data.extend_from_slice(&0xb000_0000u64.to_le_bytes());
+    data.extend_from_slice(&[0, 0, 0, 0xff, 0, 0, 0, 0]);
+    data[9] = data.iter().fold(0u8, |sum, &byte| sum.wrapping_sub(byte));
+
+    assert!(checksum_ok(&data));
+    let mcfg = Mcfg::parse(&data).unwrap();
+    assert_eq!(mcfg.entries.len(), 1);
+    assert_eq!(mcfg.entries[0].base_address, 0xb000_0000);
+    assert_eq!(
+        (mcfg.entries[0].start_bus, mcfg.entries[0].end_bus),
+        (0, 0xff)
+    );
+}
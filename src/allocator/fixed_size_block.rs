@@ -59,47 +59,53 @@ fn list_index(layout: &Layout) -> Option<usize> {
 
 unsafe impl GlobalAlloc for SpinLock<FixedSizeBlockAllocator> {
     unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
-        let mut allocator = self.lock();
-        match list_index(&layout) {
-            Some(index) => {
-                match allocator.list_heads[index].take() {
-                    Some(node) => {
-                        allocator.list_heads[index] = node.next.take();
-                        node as *mut ListNode as *mut u8
-                    }
-                    None => {
-                        // no block exists in list => allocate new block
-                        let block_size = BLOCK_SIZES[index];
-                        // only works if all block sizes are a power of 2
-                        let block_align = block_size;
-                        let layout = Layout::from_size_align(block_size, block_align).unwrap();
-                        allocator.fallback_alloc(layout)
+        // a thread preempted while holding the lock would block every allocation
+        // made with interrupts disabled, so keep them off while it is held
+        x86_64::instructions::interrupts::without_interrupts(|| {
+            let mut allocator = self.lock();
+            match list_index(&layout) {
+                Some(index) => {
+                    match allocator.list_heads[index].take() {
+                        Some(node) => {
+                            allocator.list_heads[index] = node.next.take();
+                            node as *mut ListNode as *mut u8
+                        }
+                        None => {
+                            // no block exists in list => allocate new block
+                            let block_size = BLOCK_SIZES[index];
+                            // only works if all block sizes are a power of 2
+                            let block_align = block_size;
+                            let layout = Layout::from_size_align(block_size, block_align).unwrap();
+                            allocator.fallback_alloc(layout)
+                        }
                     }
                 }
+                None => allocator.fallback_alloc(layout),
             }
-            None => allocator.fallback_alloc(layout),
-        }
+        })
     }
 
     unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
-        let mut allocator = self.lock();
-        match list_index(&layout) {
-            Some(index) => {
-                let new_node = ListNode {
-                    next: allocator.list_heads[index].take(),
-                };
-                // verify that block has size and alignment required for storing node
-                assert!(mem::size_of::<ListNode>() <= BLOCK_SIZES[index]);
-                assert!(mem::align_of::<ListNode>() <= BLOCK_SIZES[index]);
-                let new_node_ptr = ptr as *mut ListNode;
-                new_node_ptr.write(new_node);
-                allocator.list_heads[index] = Some(&mut *new_node_ptr);
-            }
-            None => {
-                // indicates that the allocation was created by the fallback allocator
-                let ptr = NonNull::new(ptr).unwrap();
-                allocator.fallback_allocator.deallocate(ptr, layout);
+        x86_64::instructions::interrupts::without_interrupts(|| {
+            let mut allocator = self.lock();
+            match list_index(&layout) {
+                Some(index) => {
+                    let new_node = ListNode {
+                        next: allocator.list_heads[index].take(),
+                    };
+                    // verify that block has size and alignment required for storing node
+                    assert!(mem::size_of::<ListNode>() <= BLOCK_SIZES[index]);
+                    assert!(mem::align_of::<ListNode>() <= BLOCK_SIZES[index]);
+                    let new_node_ptr = ptr as *mut ListNode;
+                    new_node_ptr.write(new_node);
+                    allocator.list_heads[index] = Some(&mut *new_node_ptr);
+                }
+                None => {
+                    // indicates that the allocation was created by the fallback allocator
+                    let ptr = NonNull::new(ptr).unwrap();
+                    allocator.fallback_allocator.deallocate(ptr, layout);
+                }
             }
-        }
+        })
     }
 }
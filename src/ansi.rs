@@ -0,0 +1,218 @@
+//! A small ANSI/VT100 escape sequence parser.
+//!
+//! The parser is fed one character at a time and reports what it means once a
+//! complete unit (a printable character, a control code or a full CSI
+//! sequence) has been seen. Sequences that are not understood are consumed
+//! silently.
+
+const MAX_PARAMS: usize = 16;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Action {
+    /// A character to be displayed.
+    Print(char),
+    /// A C0 or C1 control code such as `\n`, `\r` or backspace.
+    Control(u8),
+    /// A complete control sequence, `ESC [ params final`.
+    Csi(Csi),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Csi {
+    params: [u16; MAX_PARAMS],
+    len: usize,
+    /// Set when the sequence starts with `?`, e.g. `ESC [ ? 25 l`.
+    pub private: bool,
+    pub final_byte: u8,
+}
+
+impl Csi {
+    const fn new() -> Self {
+        Self {
+            params: [0; MAX_PARAMS],
+            len: 0,
+            private: false,
+            final_byte: 0,
+        }
+    }
+
+    pub fn params(&self) -> &[u16] {
+        &self.params[..self.len]
+    }
+
+    /// Returns the `index`-th parameter, or `default` when it is missing or zero.
+    pub fn param(&self, index: usize, default: u16) -> u16 {
+        match self.params().get(index) {
+            Some(&value) if value != 0 => value,
+            _ => default,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum State {
+    Ground,
+    Escape,
+    Csi,
+    /// Operating system command, consumed up to BEL or `ESC \`.
+    Osc,
+    OscEscape,
+}
+
+pub struct Parser {
+    state: State,
+    csi: Csi,
+}
+
+impl Default for Parser {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Parser {
+    pub const fn new() -> Self {
+        Self {
+            state: State::Ground,
+            csi: Csi::new(),
+        }
+    }
+
+    pub fn advance(&mut self, c: char) -> Option<Action> {
+        match self.state {
+            State::Ground => match c {
+                '\x1b' => {
+                    self.state = State::Escape;
+                    None
+                }
+                '\x00'..='\x1f' | '\x7f'..='\u{9f}' => Some(Action::Control(c as u8)),
+                _ => Some(Action::Print(c)),
+            },
+            State::Escape => {
+                self.state = match c {
+                    '[' => {
+                        self.csi = Csi::new();
+                        State::Csi
+                    }
+                    ']' => State::Osc,
+                    '\x1b' => State::Escape,
+                    _ => State::Ground,
+                };
+                None
+            }
+            State::Csi => self.advance_csi(c),
+            State::Osc => {
+                self.state = match c {
+                    '\x07' => State::Ground,
+                    '\x1b' => State::OscEscape,
+                    _ => State::Osc,
+                };
+                None
+            }
+            State::OscEscape => {
+                self.state = match c {
+                    '\\' => State::Ground,
+                    _ => State::Osc,
+                };
+                None
+            }
+        }
+    }
+
+    fn advance_csi(&mut self, c: char) -> Option<Action> {
+        let csi = &mut self.csi;
+        let byte = match u8::try_from(c) {
+            Ok(byte) if byte < 0x80 => byte,
+            // nothing outside ASCII belongs in a control sequence
+            _ => {
+                self.state = State::Ground;
+                return None;
+            }
+        };
+        match byte {
+            b'0'..=b'9' => {
+                if csi.len == 0 {
+                    csi.len = 1;
+                }
+                let param = &mut csi.params[csi.len - 1];
+                *param = param
+                    .saturating_mul(10)
+                    .saturating_add(u16::from(byte - b'0'));
+                None
+            }
+            b';' | b':' => {
+                if csi.len == 0 {
+                    csi.len = 1;
+                }
+                if csi.len < MAX_PARAMS {
+                    csi.len += 1;
+                }
+                None
+            }
+            b'?' => {
+                csi.private = true;
+                None
+            }
+            // intermediate bytes and other private markers are ignored
+            0x20..=0x3f => None,
+            0x40..=0x7e => {
+                csi.final_byte = byte;
+                self.state = State::Ground;
+                Some(Action::Csi(*csi))
+            }
+            0x1b => {
+                self.state = State::Escape;
+                None
+            }
+            // control codes are executed even in the middle of a sequence
+            0x00..=0x1f => Some(Action::Control(byte)),
+            _ => {
+                self.state = State::Ground;
+                None
+            }
+        }
+    }
+}
+
+#[test_case]
+fn test_parse_plain_text() {
+    let mut parser = Parser::new();
+    assert_eq!(parser.advance('a'), Some(Action::Print('a')));
+    assert_eq!(parser.advance('é'), Some(Action::Print('é')));
+    assert_eq!(parser.advance('\n'), Some(Action::Control(b'\n')));
+}
+
+#[test_case]
+fn test_parse_csi() {
+    let mut parser = Parser::new();
+    let mut last = None;
+    for c in "\x1b[1;31m".chars() {
+        last = parser.advance(c);
+    }
+    match last {
+        Some(Action::Csi(csi)) => {
+            assert_eq!(csi.final_byte, b'm');
+            assert_eq!(csi.params(), &[1, 31]);
+            assert_eq!(csi.param(2, 7), 7);
+        }
+        other => panic!("unexpected action: {:?}", other),
+    }
+    assert_eq!(parser.advance('x'), Some(Action::Print('x')));
+}
+
+#[test_case]
+fn test_parse_private_csi() {
+    let mut parser = Parser::new();
+    let mut last = None;
+    for c in "\x1b[?25l".chars() {
+        last = parser.advance(c);
+    }
+    match last {
+        Some(Action::Csi(csi)) => {
+            assert!(csi.private);
+            assert_eq!(csi.final_byte, b'l');
+            assert_eq!(csi.param(0, 0), 25);
+        }
+        other => panic!("unexpected action: {:?}", other),
+    }
+}
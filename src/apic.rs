@@ -0,0 +1,278 @@
+//! Local APIC and I/O APIC.
+//!
+//! When the ACPI MADT describes an I/O APIC, the 8259 PICs are masked and the
+//! legacy IRQs are routed through the I/O APIC to the vectors they had behind
+//! the PICs, so interrupt handlers work the same with either controller.
+
+use crate::acpi::{InterruptOverride, IoApicInfo, Madt};
+use crate::bail;
+use crate::error::{ErrorKind, Result};
+use crate::interrupts::{InterruptIndex, PICS};
+use crate::paging::{self, BootInfoFrameAllocator};
+use crate::sync::IrqSpinLock;
+use alloc::vec::Vec;
+use core::sync::atomic::{AtomicBool, Ordering};
+use spin::Once;
+use x86_64::registers::model_specific::Msr;
+use x86_64::structures::paging::OffsetPageTable;
+
+/// Vector the local APIC raises for spurious interrupts.
+pub const SPURIOUS_VECTOR: u8 = 0xff;
+
+const IA32_APIC_BASE: u32 = 0x1b;
+const APIC_BASE_ENABLE: u64 = 1 << 11;
+
+const LAPIC_ID: usize = 0x020;
+const LAPIC_TPR: usize = 0x080;
+const LAPIC_EOI: usize = 0x0b0;
+const LAPIC_SVR: usize = 0x0f0;
+const LAPIC_LVT_TIMER: usize = 0x320;
+const LAPIC_LVT_LINT0: usize = 0x350;
+const LAPIC_LVT_LINT1: usize = 0x360;
+const LAPIC_TIMER_INITIAL_COUNT: usize = 0x380;
+const LAPIC_TIMER_CURRENT_COUNT: usize = 0x390;
+const LAPIC_TIMER_DIVIDE: usize = 0x3e0;
+
+const SVR_ENABLE: u32 = 1 << 8;
+const LVT_MASKED: u32 = 1 << 16;
+const LVT_TIMER_PERIODIC: u32 = 1 << 17;
+const TIMER_DIVIDE_BY_16: u32 = 0x3;
+
+const IOAPIC_REGSEL: usize = 0x00;
+const IOAPIC_WINDOW: usize = 0x10;
+const IOAPIC_VERSION: u32 = 0x01;
+const IOAPIC_REDIRECTION_TABLE: u32 = 0x10;
+
+const REDIRECTION_ACTIVE_LOW: u64 = 1 << 13;
+const REDIRECTION_LEVEL_TRIGGERED: u64 = 1 << 15;
+const REDIRECTION_MASKED: u64 = 1 << 16;
+
+static ENABLED: AtomicBool = AtomicBool::new(false);
+
+static LOCAL_APIC: Once<LocalApic> = Once::new();
+
+static IO_APICS: Once<IrqSpinLock<IoApics>> = Once::new();
+
+struct LocalApic {
+    base: u64,
+}
+
+impl LocalApic {
+    fn read(&self, register: usize) -> u32 {
+        unsafe { core::ptr::read_volatile((self.base as usize + register) as *const u32) }
+    }
+
+    fn write(&self, register: usize, value: u32) {
+        unsafe { core::ptr::write_volatile((self.base as usize + register) as *mut u32, value) }
+    }
+
+    fn id(&self) -> u8 {
+        (self.read(LAPIC_ID) >> 24) as u8
+    }
+}
+
+struct IoApic {
+    base: u64,
+    gsi_base: u32,
+    entries: u32,
+}
+
+impl IoApic {
+    fn read(&mut self, register: u32) -> u32 {
+        unsafe {
+            core::ptr::write_volatile((self.base as usize + IOAPIC_REGSEL) as *mut u32, register);
+            core::ptr::read_volatile((self.base as usize + IOAPIC_WINDOW) as *const u32)
+        }
+    }
+
+    fn write(&mut self, register: u32, value: u32) {
+        unsafe {
+            core::ptr::write_volatile((self.base as usize + IOAPIC_REGSEL) as *mut u32, register);
+            core::ptr::write_volatile((self.base as usize + IOAPIC_WINDOW) as *mut u32, value);
+        }
+    }
+
+    fn redirection(&mut self, index: u32) -> u64 {
+        let register = IOAPIC_REDIRECTION_TABLE + index * 2;
+        u64::from(self.read(register)) | u64::from(self.read(register + 1)) << 32
+    }
+
+    fn set_redirection(&mut self, index: u32, entry: u64) {
+        let register = IOAPIC_REDIRECTION_TABLE + index * 2;
+        // mask while the entry is half written
+        self.write(register, REDIRECTION_MASKED as u32);
+        self.write(register + 1, (entry >> 32) as u32);
+        self.write(register, entry as u32);
+    }
+}
+
+struct IoApics {
+    chips: Vec<IoApic>,
+    overrides: Vec<InterruptOverride>,
+}
+
+impl IoApics {
+    /// Returns the I/O APIC input ISA `irq` is wired to and its polarity and
+    /// trigger mode bits.
+    fn lookup(&mut self, irq: u8) -> Option<(&mut IoApic, u32, u64)> {
+        let (gsi, flags) = match self.overrides.iter().find(|o| o.irq == irq) {
+            Some(o) => (o.gsi, o.flags),
+            None => (u32::from(irq), 0),
+        };
+        // ISA interrupts are active high and edge triggered unless overridden
+        let mut mode = 0;
+        if flags & 0x3 == 0x3 {
+            mode |= REDIRECTION_ACTIVE_LOW;
+        }
+        if flags & 0xc == 0xc {
+            mode |= REDIRECTION_LEVEL_TRIGGERED;
+        }
+        let chip = self
+            .chips
+            .iter_mut()
+            .find(|chip| (chip.gsi_base..chip.gsi_base + chip.entries).contains(&gsi))?;
+        let index = gsi - chip.gsi_base;
+        Some((chip, index, mode))
+    }
+
+    fn route(&mut self, irq: u8, vector: u8, destination: u8, masked: bool) -> Result<()> {
+        let Some((chip, index, mode)) = self.lookup(irq) else {
+            bail!(ErrorKind::DeviceNotFound);
+        };
+        let mut entry = u64::from(vector) | mode | u64::from(destination) << 56;
+        if masked {
+            entry |= REDIRECTION_MASKED;
+        }
+        chip.set_redirection(index, entry);
+        Ok(())
+    }
+
+    fn set_masked(&mut self, irq: u8, masked: bool) {
+        if let Some((chip, index, _)) = self.lookup(irq) {
+            let entry = chip.redirection(index);
+            let entry = if masked {
+                entry | REDIRECTION_MASKED
+            } else {
+                entry & !REDIRECTION_MASKED
+            };
+            chip.set_redirection(index, entry);
+        }
+    }
+}
+
+fn has_apic() -> bool {
+    let cpuid = unsafe { core::arch::x86_64::__cpuid(1) };
+    cpuid.edx & (1 << 9) != 0
+}
+
+/// Switches interrupt delivery from the 8259 PICs to the APICs.
+///
+/// Returns an error, leaving the PICs in charge, if the CPU has no local APIC
+/// or the MADT lists no I/O APIC.
+pub fn init(mapper: &mut OffsetPageTable, allocator: &mut BootInfoFrameAllocator) -> Result<()> {
+    if !has_apic() {
+        bail!(ErrorKind::DeviceNotFound);
+    }
+    let Some(madt) = Madt::find() else {
+        bail!(ErrorKind::DeviceNotFound);
+    };
+    if madt.io_apics.is_empty() {
+        bail!(ErrorKind::DeviceNotFound);
+    }
+
+    paging::make_identity_mapping(mapper, allocator, madt.local_apic_address, 1)?;
+    let mut chips = Vec::new();
+    for &IoApicInfo {
+        address, gsi_base, ..
+    } in &madt.io_apics
+    {
+        paging::make_identity_mapping(mapper, allocator, address, 1)?;
+        let mut chip = IoApic {
+            base: address,
+            gsi_base,
+            entries: 0,
+        };
+        chip.entries = (chip.read(IOAPIC_VERSION) >> 16 & 0xff) + 1;
+        for index in 0..chip.entries {
+            chip.set_redirection(index, REDIRECTION_MASKED);
+        }
+        chips.push(chip);
+    }
+
+    let local = LocalApic {
+        base: madt.local_apic_address,
+    };
+    let mut io_apics = IoApics {
+        chips,
+        overrides: madt.overrides,
+    };
+    for (index, masked) in [
+        (InterruptIndex::Timer, false),
+        (InterruptIndex::Keyboard, false),
+        (InterruptIndex::Serial, false),
+        // unmasked by the mouse driver once the device is set up
+        (InterruptIndex::Mouse, true),
+    ] {
+        io_apics.route(index.irq(), index.as_u8(), local.id(), masked)?;
+    }
+
+    if madt.pcat_compat {
+        unsafe { PICS.lock().disable() };
+    }
+    unsafe {
+        let mut base = Msr::new(IA32_APIC_BASE);
+        let value = base.read();
+        base.write(value | APIC_BASE_ENABLE);
+    }
+    local.write(LAPIC_TPR, 0);
+    // the PICs are masked, so nothing should arrive through the legacy pins
+    local.write(LAPIC_LVT_LINT0, LVT_MASKED);
+    local.write(LAPIC_LVT_LINT1, LVT_MASKED);
+    local.write(LAPIC_SVR, SVR_ENABLE | u32::from(SPURIOUS_VECTOR));
+
+    LOCAL_APIC.call_once(|| local);
+    IO_APICS.call_once(|| IrqSpinLock::new(io_apics));
+
+    ENABLED.store(true, Ordering::Relaxed);
+    Ok(())
+}
+
+/// Whether interrupts are delivered through the APICs rather than the PICs.
+pub fn is_enabled() -> bool {
+    ENABLED.load(Ordering::Relaxed)
+}
+
+pub fn end_of_interrupt() {
+    if let Some(local) = LOCAL_APIC.get() {
+        local.write(LAPIC_EOI, 0);
+    }
+}
+
+/// Masks or unmasks ISA `irq` at the I/O APIC.
+pub fn set_irq_masked(irq: u8, masked: bool) {
+    if let Some(io_apics) = IO_APICS.get() {
+        io_apics.lock().set_masked(irq, masked);
+    }
+}
+
+/// Starts the local APIC timer counting down from `initial_count`, once every
+/// 16 bus clocks. With a `vector` it raises that interrupt and starts over
+/// each time it reaches zero; without one it counts down once, silently.
+pub fn start_timer(initial_count: u32, vector: Option<u8>) {
+    if let Some(local) = LOCAL_APIC.get() {
+        let lvt = match vector {
+            Some(vector) => LVT_TIMER_PERIODIC | u32::from(vector),
+            None => LVT_MASKED,
+        };
+        local.write(LAPIC_TIMER_DIVIDE, TIMER_DIVIDE_BY_16);
+        local.write(LAPIC_LVT_TIMER, lvt);
+        local.write(LAPIC_TIMER_INITIAL_COUNT, initial_count);
+    }
+}
+
+/// The local APIC timer's current count.
+pub fn timer_count() -> u32 {
+    LOCAL_APIC
+        .get()
+        .map_or(0, |local| local.read(LAPIC_TIMER_CURRENT_COUNT))
+}
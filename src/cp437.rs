@@ -0,0 +1,70 @@
+//! Mapping from Unicode to the glyphs of Code Page 437, the character set
+//! built into VGA text mode.
+
+/// Glyphs for `0x01..=0x1f`; the VGA draws these even though they are ASCII
+/// control codes, so they can only be reached through their Unicode symbols.
+const LOW: [char; 31] = [
+    '☺', '☻', '♥', '♦', '♣', '♠', '•', '◘', '○', '◙', '♂', '♀', '♪', '♫', '☼', //
+    '►', '◄', '↕', '‼', '¶', '§', '▬', '↨', '↑', '↓', '→', '←', '∟', '↔', '▲', '▼',
+];
+
+/// Glyphs for `0x80..=0xff`.
+const HIGH: [char; 128] = [
+    'Ç', 'ü', 'é', 'â', 'ä', 'à', 'å', 'ç', 'ê', 'ë', 'è', 'ï', 'î', 'ì', 'Ä', 'Å', //
+    'É', 'æ', 'Æ', 'ô', 'ö', 'ò', 'û', 'ù', 'ÿ', 'Ö', 'Ü', '¢', '£', '¥', '₧', 'ƒ', //
+    'á', 'í', 'ó', 'ú', 'ñ', 'Ñ', 'ª', 'º', '¿', '⌐', '¬', '½', '¼', '¡', '«', '»', //
+    '░', '▒', '▓', '│', '┤', '╡', '╢', '╖', '╕', '╣', '║', '╗', '╝', '╜', '╛', '┐', //
+    '└', '┴', '┬', '├', '─', '┼', '╞', '╟', '╚', '╔', '╩', '╦', '╠', '═', '╬', '╧', //
+    '╨', '╤', '╥', '╙', '╘', '╒', '╓', '╫', '╪', '┘', '┌', '█', '▄', '▌', '▐', '▀', //
+    'α', 'ß', 'Γ', 'π', 'Σ', 'σ', 'µ', 'τ', 'Φ', 'Θ', 'Ω', 'δ', '∞', 'φ', 'ε', '∩', //
+    '≡', '±', '≥', '≤', '⌠', '⌡', '÷', '≈', '°', '∙', '·', '√', 'ⁿ', '²', '■', '\u{a0}',
+];
+
+/// Characters without a glyph of their own that look close enough to one.
+const ALIASES: [(char, u8); 8] = [
+    ('β', 0xe1), // drawn like sharp s
+    ('μ', 0xe6), // Greek mu vs. micro sign
+    ('∑', 0xe4),
+    ('\u{2126}', 0xea), // ohm sign
+    ('∈', 0xee),
+    ('ϕ', 0xed),
+    ('⌂', 0x7f),
+    ('▪', 0xfe),
+];
+
+/// Glyph used for characters that cannot be displayed.
+pub const REPLACEMENT: u8 = 0xfe;
+
+/// Returns the CP437 byte that displays `c`, if there is one.
+pub fn from_char(c: char) -> Option<u8> {
+    match c {
+        ' '..='~' => Some(c as u8),
+        _ => LOW
+            .iter()
+            .position(|&glyph| glyph == c)
+            .map(|i| i as u8 + 0x01)
+            .or_else(|| {
+                HIGH.iter()
+                    .position(|&glyph| glyph == c)
+                    .map(|i| i as u8 + 0x80)
+            })
+            .or_else(|| {
+                ALIASES
+                    .iter()
+                    .find(|&&(alias, _)| alias == c)
+                    .map(|&(_, byte)| byte)
+            }),
+    }
+}
+
+#[test_case]
+fn test_from_char() {
+    assert_eq!(from_char('A'), Some(b'A'));
+    assert_eq!(from_char('é'), Some(0x82));
+    assert_eq!(from_char('─'), Some(0xc4));
+    assert_eq!(from_char('█'), Some(0xdb));
+    assert_eq!(from_char('☺'), Some(0x01));
+    assert_eq!(from_char('μ'), Some(0xe6));
+    assert_eq!(from_char('\u{2126}'), Some(0xea));
+    assert_eq!(from_char('漢'), None);
+}
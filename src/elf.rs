@@ -0,0 +1,148 @@
+//! ELF64 executable headers.
+//!
+//! Only what loading a static x86_64 executable needs is parsed: the file
+//! header and the program headers.
+
+use crate::bail;
+use crate::error::{ErrorKind, Result};
+use alloc::vec::Vec;
+
+const MAGIC: [u8; 4] = [0x7f, b'E', b'L', b'F'];
+const CLASS_64: u8 = 2;
+const DATA_LITTLE_ENDIAN: u8 = 1;
+const VERSION_CURRENT: u8 = 1;
+const TYPE_EXECUTABLE: u16 = 2;
+const MACHINE_X86_64: u16 = 62;
+
+const HEADER_SIZE: usize = 64;
+const PROGRAM_HEADER_SIZE: usize = 56;
+
+pub const PT_LOAD: u32 = 1;
+
+pub const PF_X: u32 = 1;
+pub const PF_W: u32 = 2;
+pub const PF_R: u32 = 4;
+
+/// A program header, which describes a segment.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ProgramHeader {
+    pub kind: u32,
+    pub flags: u32,
+    pub offset: u64,
+    pub vaddr: u64,
+    pub file_size: u64,
+    pub mem_size: u64,
+}
+
+/// A validated executable.
+#[derive(Debug)]
+pub struct Elf<'a> {
+    data: &'a [u8],
+    pub entry: u64,
+    /// Offset of the program headers in the file.
+    pub phoff: u64,
+    pub program_headers: Vec<ProgramHeader>,
+}
+
+fn u16_at(data: &[u8], offset: usize) -> u16 {
+    u16::from_le_bytes(data[offset..offset + 2].try_into().unwrap())
+}
+
+fn u32_at(data: &[u8], offset: usize) -> u32 {
+    u32::from_le_bytes(data[offset..offset + 4].try_into().unwrap())
+}
+
+fn u64_at(data: &[u8], offset: usize) -> u64 {
+    u64::from_le_bytes(data[offset..offset + 8].try_into().unwrap())
+}
+
+impl<'a> Elf<'a> {
+    /// Checks that `data` is a little endian x86_64 executable whose segments
+    /// lie within the file, and reads its program headers.
+    pub fn parse(data: &'a [u8]) -> Result<Self> {
+        if data.len() < HEADER_SIZE
+            || data[..4] != MAGIC
+            || data[4] != CLASS_64
+            || data[5] != DATA_LITTLE_ENDIAN
+            || data[6] != VERSION_CURRENT
+        {
+            bail!(ErrorKind::InvalidArgument);
+        }
+        if u16_at(data, 16) != TYPE_EXECUTABLE || u16_at(data, 18) != MACHINE_X86_64 {
+            bail!(ErrorKind::NotImplemented);
+        }
+        let entry = u64_at(data, 24);
+        let phoff = u64_at(data, 32);
+        let phentsize = usize::from(u16_at(data, 54));
+        let phnum = usize::from(u16_at(data, 56));
+        if phentsize != PROGRAM_HEADER_SIZE {
+            bail!(ErrorKind::InvalidArgument);
+        }
+        let table = phoff as usize;
+        let table_end = table
+            .checked_add(phnum * PROGRAM_HEADER_SIZE)
+            .filter(|&end| end <= data.len())
+            .ok_or(ErrorKind::InvalidArgument)?;
+
+        let program_headers = data[table..table_end]
+            .chunks_exact(PROGRAM_HEADER_SIZE)
+            .map(|header| ProgramHeader {
+                kind: u32_at(header, 0),
+                flags: u32_at(header, 4),
+                offset: u64_at(header, 8),
+                vaddr: u64_at(header, 16),
+                file_size: u64_at(header, 32),
+                mem_size: u64_at(header, 40),
+            })
+            .collect::<Vec<_>>();
+        for header in program_headers.iter().filter(|h| h.kind == PT_LOAD) {
+            let in_file = header
+                .offset
+                .checked_add(header.file_size)
+                .is_some_and(|end| end <= data.len() as u64);
+            if !in_file || header.file_size > header.mem_size {
+                bail!(ErrorKind::InvalidArgument);
+            }
+        }
+
+        Ok(Elf {
+            data,
+            entry,
+            phoff,
+            program_headers,
+        })
+    }
+
+    /// The loadable segments.
+    pub fn segments(&self) -> impl Iterator<Item = &ProgramHeader> {
+        self.program_headers.iter().filter(|h| h.kind == PT_LOAD)
+    }
+
+    /// The bytes of `header`'s segment stored in the file.
+    pub fn file_data(&self, header: &ProgramHeader) -> &'a [u8] {
+        let start = header.offset as usize;
+        &self.data[start..start + header.file_size as usize]
+    }
+}
+
+#[test_case]
+fn test_parse_rejects_invalid() {
+    static ECHO: &[u8] = include_bytes!("../user/echo");
+
+    let elf = Elf::parse(ECHO).unwrap();
+    assert!(elf.segments().count() >= 2);
+    assert!(elf.segments().any(|h| h.flags & PF_X != 0));
+
+    assert!(Elf::parse(&ECHO[..HEADER_SIZE - 1]).is_err());
+    let mut bad = ECHO.to_vec();
+    bad[0] = 0;
+    assert!(Elf::parse(&bad).is_err());
+    // a 32-bit executable
+    let mut bad = ECHO.to_vec();
+    bad[4] = 1;
+    assert!(Elf::parse(&bad).is_err());
+    // program headers past the end of the file
+    let mut bad = ECHO.to_vec();
+    bad[56..58].copy_from_slice(&u16::MAX.to_le_bytes());
+    assert!(Elf::parse(&bad).is_err());
+}
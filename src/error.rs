@@ -15,6 +15,10 @@ impl Error {
         let location = Location::caller();
         Self { kind, location }
     }
+
+    pub fn kind(&self) -> &ErrorKind {
+        &self.kind
+    }
 }
 
 impl From<ErrorKind> for Error {
@@ -52,7 +56,17 @@ pub enum ErrorKind {
     InvalidEndpointNumber,
     TransferRingNotSet,
     AlreadyAllocated,
-    TaskQueueIsFull,
+    DeviceNotFound,
+    InvalidArgument,
+    Cancelled,
+    TimedOut,
+    NotFound,
+    BadFileDescriptor,
+    BadAddress,
+    NoChild,
+    NoSuchProcess,
+    PermissionDenied,
+    BrokenPipe,
     NotImplemented,
     Unknown,
 }
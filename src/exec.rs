@@ -1,4 +1,13 @@
+//! Running programs: ELF executables loaded into their own address space,
+//! and the hand-assembled snippets of `Compiler`, which run in the kernel.
+
+use crate::elf::{Elf, PF_W, PF_X};
+use crate::error::{ErrorKind, Result};
+use crate::usermode::{AddressSpace, Protection, HEAP_START, USER_END, USER_START};
+use crate::{bail, fs};
 use alloc::alloc::{alloc, Layout};
+use alloc::vec::Vec;
+use x86_64::VirtAddr;
 
 pub fn compile_identity() -> fn(i32) -> i32 {
     unsafe {
@@ -60,3 +69,180 @@ impl Compiler {
         self.current_p
     }
 }
+
+/// Top of the stack of a program, at the end of the user part.
+pub const STACK_TOP: u64 = USER_END;
+pub const STACK_SIZE: usize = 128 * 1024;
+
+const PAGE_SIZE: u64 = 4096;
+
+const AT_NULL: u64 = 0;
+const AT_PHDR: u64 = 3;
+const AT_PHENT: u64 = 4;
+const AT_PHNUM: u64 = 5;
+const AT_PAGESZ: u64 = 6;
+const AT_ENTRY: u64 = 9;
+
+/// Programs built into the kernel, put in the filesystem by `install_programs`.
+static PROGRAMS: &[(&str, &[u8])] = &[
+    ("echo", include_bytes!("../user/echo")),
+    ("cat", include_bytes!("../user/cat")),
+];
+
+pub fn install_programs() {
+    for &(name, image) in PROGRAMS {
+        if fs::create_file(&mut fs::Path::from_str(name)).is_ok() {
+            fs::handle_file_mut(
+                |file| file.map(|file| file.write_at(0, image)),
+                fs::Path::from_str(name),
+            )
+            .ok();
+        }
+    }
+}
+
+/// An executable loaded into a new address space, ready to run.
+#[derive(Debug)]
+pub struct Program {
+    pub space: AddressSpace,
+    pub entry: VirtAddr,
+    /// Points at `argc`, followed by `argv`, `envp` and the auxiliary vector.
+    pub stack_pointer: VirtAddr,
+}
+
+/// Loads the executable `image`, passing it `args` and `env`.
+pub fn load(image: &[u8], args: &[&str], env: &[&str]) -> Result<Program> {
+    let elf = Elf::parse(image)?;
+    let space = AddressSpace::new()?;
+
+    let mut executable_entry = false;
+    let mut phdr = None;
+    for segment in elf.segments() {
+        let end = segment.vaddr.checked_add(segment.mem_size);
+        if segment.vaddr < USER_START || end.is_none_or(|end| end > HEAP_START) {
+            bail!(ErrorKind::InvalidArgument);
+        }
+        let protection = Protection {
+            write: segment.flags & PF_W != 0,
+            execute: segment.flags & PF_X != 0,
+        };
+        let start = VirtAddr::new(segment.vaddr);
+        space.map(start, segment.mem_size as usize, protection)?;
+        space.write(start, elf.file_data(segment))?;
+
+        // the rest of the last page with file data may be shared with another
+        // segment, so the start of the BSS is cleared; the pages after it
+        // were mapped zeroed
+        let bss = segment.vaddr + segment.file_size;
+        let bss_end = (segment.vaddr + segment.mem_size).min(bss.next_multiple_of(PAGE_SIZE));
+        space.write(
+            VirtAddr::new(bss),
+            &[0; PAGE_SIZE as usize][..(bss_end - bss) as usize],
+        )?;
+
+        let entry_offset = elf.entry.wrapping_sub(segment.vaddr);
+        executable_entry |= protection.execute && entry_offset < segment.mem_size;
+        let phdr_offset = elf.phoff.wrapping_sub(segment.offset);
+        if phdr_offset < segment.file_size {
+            phdr = Some(segment.vaddr + phdr_offset);
+        }
+    }
+    if !executable_entry {
+        bail!(ErrorKind::InvalidArgument);
+    }
+
+    let mut auxv = Vec::new();
+    if let Some(phdr) = phdr {
+        auxv.extend([(AT_PHDR, phdr), (AT_PHENT, 56)]);
+    }
+    auxv.extend([
+        (AT_PHNUM, elf.program_headers.len() as u64),
+        (AT_PAGESZ, PAGE_SIZE),
+        (AT_ENTRY, elf.entry),
+    ]);
+    let stack_pointer = build_stack(&space, args, env, &auxv)?;
+    Ok(Program {
+        space,
+        entry: VirtAddr::new(elf.entry),
+        stack_pointer,
+    })
+}
+
+/// Maps the stack and lays out the strings of `args` and `env` at its top,
+/// with the pointers to them and `auxv` below, as the System V ABI has it.
+fn build_stack(
+    space: &AddressSpace,
+    args: &[&str],
+    env: &[&str],
+    auxv: &[(u64, u64)],
+) -> Result<VirtAddr> {
+    let bottom = STACK_TOP - STACK_SIZE as u64;
+    space.map(VirtAddr::new(bottom), STACK_SIZE, Protection::READ_WRITE)?;
+
+    let mut strings = Vec::new();
+    let mut offsets = Vec::new();
+    for string in args.iter().chain(env) {
+        offsets.push(strings.len() as u64);
+        strings.extend_from_slice(string.as_bytes());
+        strings.push(0);
+    }
+    let strings_start = STACK_TOP - strings.len() as u64;
+
+    let mut words = Vec::new();
+    words.push(args.len() as u64);
+    let (arg_offsets, env_offsets) = offsets.split_at(args.len());
+    words.extend(arg_offsets.iter().map(|offset| strings_start + offset));
+    words.push(0);
+    words.extend(env_offsets.iter().map(|offset| strings_start + offset));
+    words.push(0);
+    for &(key, value) in auxv.iter().chain(&[(AT_NULL, 0)]) {
+        words.extend([key, value]);
+    }
+
+    // `argc` is 16-byte aligned at the entry point
+    let stack_pointer = (strings_start - words.len() as u64 * 8) & !0xf;
+    // leave most of the stack to the program
+    if stack_pointer < bottom + STACK_SIZE as u64 / 2 {
+        bail!(ErrorKind::InvalidArgument);
+    }
+    let words: Vec<u8> = words.iter().flat_map(|word| word.to_le_bytes()).collect();
+    space.write(VirtAddr::new(stack_pointer), &words)?;
+    space.write(VirtAddr::new(strings_start), &strings)?;
+    Ok(VirtAddr::new(stack_pointer))
+}
+
+/// Loads the executable at `path`, with `args` as its arguments, the first
+/// of which is conventionally the program name.
+pub fn load_path(path: &str, args: &[&str]) -> Result<Program> {
+    let image = fs::handle_file_mut(
+        |file| file.map(|file| file.content().clone()),
+        fs::Path::from_str(path),
+    )
+    .map_err(|_| ErrorKind::NotFound)?;
+    load(&image, args, &[])
+}
+
+#[test_case]
+fn test_load_path() {
+    install_programs();
+    let program = load_path("echo", &["echo", "from", "exec"]).unwrap();
+    assert!(program.space.is_accessible(program.entry, 1, false));
+    assert!(matches!(
+        load_path("no such program", &[]),
+        Err(err) if matches!(err.kind(), ErrorKind::NotFound)
+    ));
+}
+
+#[test_case]
+fn test_load_checks_segments() {
+    let image = PROGRAMS[0].1;
+    let program = load(image, &["echo", "a"], &["TERM=moss"]).unwrap();
+    assert_eq!(program.stack_pointer.as_u64() % 16, 0);
+    assert!(program.space.is_accessible(program.stack_pointer, 8, true));
+
+    // the first segment moved into kernel memory
+    let mut bad = image.to_vec();
+    let phoff = u64::from_le_bytes(bad[32..40].try_into().unwrap()) as usize;
+    bad[phoff + 16..phoff + 24].copy_from_slice(&0x1000u64.to_le_bytes());
+    assert!(load(&bad, &[], &[]).is_err());
+}
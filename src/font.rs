@@ -0,0 +1,88 @@
+//! PC Screen Font (PSF) bitmap fonts.
+//!
+//! Glyphs are looked up by index; the embedded font stores its first 256
+//! glyphs in Code Page 437 order, so CP437 bytes can be used directly.
+
+use spin::Lazy;
+
+static SPLEEN_8X16: &[u8] = include_bytes!("../assets/spleen-8x16.psfu");
+
+pub static FONT: Lazy<Font> =
+    Lazy::new(|| Font::parse(SPLEEN_8X16).expect("embedded font is not a valid PSF file"));
+
+const PSF1_MAGIC: [u8; 2] = [0x36, 0x04];
+const PSF1_MODE512: u8 = 0x01;
+const PSF2_MAGIC: [u8; 4] = [0x72, 0xb5, 0x4a, 0x86];
+
+pub struct Font {
+    width: usize,
+    height: usize,
+    glyph_count: usize,
+    bytes_per_glyph: usize,
+    glyphs: &'static [u8],
+}
+
+impl Font {
+    /// Parses a PSF1 or PSF2 font. Returns `None` if the header is not recognized
+    /// or the file is too short for the glyphs it announces.
+    pub fn parse(data: &'static [u8]) -> Option<Self> {
+        let (width, height, glyph_count, bytes_per_glyph, header_size) =
+            if data.starts_with(&PSF1_MAGIC) {
+                let mode = *data.get(2)?;
+                let height = usize::from(*data.get(3)?);
+                let count = if mode & PSF1_MODE512 != 0 { 512 } else { 256 };
+                (8, height, count, height, 4)
+            } else if data.starts_with(&PSF2_MAGIC) {
+                let field = |index: usize| -> Option<usize> {
+                    let bytes = data.get(index * 4..index * 4 + 4)?;
+                    let value = u32::from_le_bytes(bytes.try_into().ok()?);
+                    usize::try_from(value).ok()
+                };
+                let header_size = field(2)?;
+                let count = field(4)?;
+                let bytes_per_glyph = field(5)?;
+                let height = field(6)?;
+                let width = field(7)?;
+                (width, height, count, bytes_per_glyph, header_size)
+            } else {
+                return None;
+            };
+
+        let glyphs = data.get(header_size..header_size + glyph_count * bytes_per_glyph)?;
+        Some(Self {
+            width,
+            height,
+            glyph_count,
+            bytes_per_glyph,
+            glyphs,
+        })
+    }
+
+    pub fn width(&self) -> usize {
+        self.width
+    }
+
+    pub fn height(&self) -> usize {
+        self.height
+    }
+
+    /// Returns whether pixel `x`, `y` of glyph `index` is set. Out of range
+    /// glyphs are drawn as blanks.
+    pub fn is_set(&self, index: usize, x: usize, y: usize) -> bool {
+        if index >= self.glyph_count || x >= self.width || y >= self.height {
+            return false;
+        }
+        let bytes_per_row = self.width.div_ceil(8);
+        let glyph = &self.glyphs[index * self.bytes_per_glyph..][..self.bytes_per_glyph];
+        glyph[y * bytes_per_row + x / 8] & (0x80 >> (x % 8)) != 0
+    }
+}
+
+#[test_case]
+fn test_embedded_font() {
+    assert_eq!(FONT.width(), 8);
+    assert_eq!(FONT.height(), 16);
+    // the space glyph is empty, the full block is not
+    assert!((0..16).all(|y| (0..8).all(|x| !FONT.is_set(b' ' as usize, x, y))));
+    assert!(FONT.is_set(0xdb, 0, 0));
+}
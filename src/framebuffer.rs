@@ -0,0 +1,245 @@
+//! Linear framebuffer graphics through the Bochs VBE interface that QEMU's
+//! `-vga std` device provides.
+
+use crate::bail;
+use crate::error::{ErrorKind, Result};
+use crate::font::FONT;
+use spin::{Mutex, Once};
+use x86_64::instructions::port::Port;
+use x86_64::structures::paging::{
+    FrameAllocator, Mapper, Page, PageTableFlags, PhysFrame, Size4KiB,
+};
+use x86_64::{PhysAddr, VirtAddr};
+
+/// Virtual address the framebuffer is mapped at.
+pub const FRAMEBUFFER_START: usize = 0x_5555_5555_0000;
+
+pub const DEFAULT_WIDTH: usize = 1024;
+pub const DEFAULT_HEIGHT: usize = 768;
+
+const VBE_DISPI_IOPORT_INDEX: u16 = 0x01ce;
+const VBE_DISPI_IOPORT_DATA: u16 = 0x01cf;
+
+const VBE_DISPI_INDEX_ID: u16 = 0;
+const VBE_DISPI_INDEX_XRES: u16 = 1;
+const VBE_DISPI_INDEX_YRES: u16 = 2;
+const VBE_DISPI_INDEX_BPP: u16 = 3;
+const VBE_DISPI_INDEX_ENABLE: u16 = 4;
+
+/// Oldest interface version that supports 32 bits per pixel.
+const VBE_DISPI_ID2: u16 = 0xb0c2;
+const VBE_DISPI_ID_MAX: u16 = 0xb0c5;
+const VBE_DISPI_ENABLED: u16 = 0x01;
+const VBE_DISPI_LFB_ENABLED: u16 = 0x40;
+
+const BOCHS_VGA_VENDOR: u16 = 0x1234;
+const BOCHS_VGA_DEVICE: u16 = 0x1111;
+
+/// Framebuffer address used by Bochs when the PCI BAR cannot be read.
+const BOCHS_DEFAULT_LFB: u64 = 0xe000_0000;
+
+static FRAMEBUFFER: Once<Mutex<FrameBuffer>> = Once::new();
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Rgb(pub u8, pub u8, pub u8);
+
+impl Rgb {
+    fn to_pixel(self) -> u32 {
+        u32::from(self.0) << 16 | u32::from(self.1) << 8 | u32::from(self.2)
+    }
+}
+
+/// A 32 bits per pixel `0x00RRGGBB` framebuffer.
+pub struct FrameBuffer {
+    pixels: &'static mut [u32],
+    width: usize,
+    height: usize,
+}
+
+impl FrameBuffer {
+    pub fn width(&self) -> usize {
+        self.width
+    }
+
+    pub fn height(&self) -> usize {
+        self.height
+    }
+
+    pub fn pixel(&mut self, x: usize, y: usize, color: Rgb) {
+        if x < self.width && y < self.height {
+            let pixel = &mut self.pixels[y * self.width + x];
+            unsafe { core::ptr::write_volatile(pixel, color.to_pixel()) };
+        }
+    }
+
+    pub fn fill_rect(&mut self, x: usize, y: usize, width: usize, height: usize, color: Rgb) {
+        if x >= self.width || y >= self.height {
+            return;
+        }
+        let x_end = usize::min(x.saturating_add(width), self.width);
+        let y_end = usize::min(y.saturating_add(height), self.height);
+        let pixel = color.to_pixel();
+        for y in y..y_end {
+            for p in &mut self.pixels[y * self.width + x..y * self.width + x_end] {
+                unsafe { core::ptr::write_volatile(p, pixel) };
+            }
+        }
+    }
+
+    /// Draws a line with Bresenham's algorithm.
+    pub fn line(&mut self, x0: usize, y0: usize, x1: usize, y1: usize, color: Rgb) {
+        let (mut x, mut y) = (x0 as isize, y0 as isize);
+        let (x1, y1) = (x1 as isize, y1 as isize);
+        let dx = (x1 - x).abs();
+        let dy = -(y1 - y).abs();
+        let sx = if x < x1 { 1 } else { -1 };
+        let sy = if y < y1 { 1 } else { -1 };
+        let mut err = dx + dy;
+        loop {
+            self.pixel(x as usize, y as usize, color);
+            if x == x1 && y == y1 {
+                break;
+            }
+            let e2 = 2 * err;
+            if e2 >= dy {
+                err += dy;
+                x += sx;
+            }
+            if e2 <= dx {
+                err += dx;
+                y += sy;
+            }
+        }
+    }
+
+    /// Copies a `width` x `height` image of `0x00RRGGBB` pixels to `x`, `y`,
+    /// clipping it at the screen edges.
+    pub fn blit(&mut self, x: usize, y: usize, width: usize, height: usize, image: &[u32]) {
+        assert!(image.len() >= width * height);
+        if x >= self.width || y >= self.height {
+            return;
+        }
+        let columns = usize::min(width, self.width.saturating_sub(x));
+        let rows = usize::min(height, self.height.saturating_sub(y));
+        for row in 0..rows {
+            let src = &image[row * width..][..columns];
+            let dst = &mut self.pixels[(y + row) * self.width + x..][..columns];
+            for (d, s) in dst.iter_mut().zip(src) {
+                unsafe { core::ptr::write_volatile(d, *s) };
+            }
+        }
+    }
+
+    /// Draws glyph `index` of the embedded font with its top left corner at `x`, `y`.
+    pub fn draw_glyph(&mut self, x: usize, y: usize, index: usize, fg: Rgb, bg: Rgb) {
+        for gy in 0..FONT.height() {
+            for gx in 0..FONT.width() {
+                let color = if FONT.is_set(index, gx, gy) { fg } else { bg };
+                self.pixel(x + gx, y + gy, color);
+            }
+        }
+    }
+}
+
+/// Switches the display to a `width` x `height` linear framebuffer and maps it
+/// at `FRAMEBUFFER_START`.
+pub fn init(
+    mapper: &mut impl Mapper<Size4KiB>,
+    frame_allocator: &mut impl FrameAllocator<Size4KiB>,
+    width: usize,
+    height: usize,
+) -> Result<()> {
+    let id = vbe_read(VBE_DISPI_INDEX_ID);
+    if !(VBE_DISPI_ID2..=VBE_DISPI_ID_MAX).contains(&id) {
+        bail!(ErrorKind::DeviceNotFound);
+    }
+
+    vbe_write(VBE_DISPI_INDEX_ENABLE, 0);
+    vbe_write(
+        VBE_DISPI_INDEX_XRES,
+        u16::try_from(width).map_err(ErrorKind::TryFromInt)?,
+    );
+    vbe_write(
+        VBE_DISPI_INDEX_YRES,
+        u16::try_from(height).map_err(ErrorKind::TryFromInt)?,
+    );
+    vbe_write(VBE_DISPI_INDEX_BPP, 32);
+    vbe_write(
+        VBE_DISPI_INDEX_ENABLE,
+        VBE_DISPI_ENABLED | VBE_DISPI_LFB_ENABLED,
+    );
+
+    let size = width * height * 4;
+    let lfb = PhysAddr::new(find_lfb().unwrap_or(BOCHS_DEFAULT_LFB));
+    let start_frame = PhysFrame::<Size4KiB>::containing_address(lfb);
+    let end_frame = PhysFrame::containing_address(lfb + (size - 1) as u64);
+    let start_page = Page::<Size4KiB>::containing_address(VirtAddr::new(FRAMEBUFFER_START as u64));
+
+    let flags = PageTableFlags::PRESENT
+        | PageTableFlags::WRITABLE
+        | PageTableFlags::WRITE_THROUGH
+        | PageTableFlags::NO_EXECUTE;
+    for (i, frame) in PhysFrame::range_inclusive(start_frame, end_frame).enumerate() {
+        let page = start_page + i as u64;
+        unsafe { mapper.map_to(page, frame, flags, frame_allocator)?.flush() };
+    }
+
+    let pixels =
+        unsafe { core::slice::from_raw_parts_mut(FRAMEBUFFER_START as *mut u32, width * height) };
+    FRAMEBUFFER.call_once(|| {
+        Mutex::new(FrameBuffer {
+            pixels,
+            width,
+            height,
+        })
+    });
+
+    Ok(())
+}
+
+/// Runs `f` with the framebuffer, if graphics mode has been set up.
+pub fn with_framebuffer<R>(f: impl FnOnce(&mut FrameBuffer) -> R) -> Option<R> {
+    FRAMEBUFFER
+        .get()
+        .map(|framebuffer| f(&mut framebuffer.lock()))
+}
+
+pub fn is_enabled() -> bool {
+    FRAMEBUFFER.get().is_some()
+}
+
+fn vbe_read(index: u16) -> u16 {
+    unsafe {
+        Port::new(VBE_DISPI_IOPORT_INDEX).write(index);
+        Port::new(VBE_DISPI_IOPORT_DATA).read()
+    }
+}
+
+fn vbe_write(index: u16, value: u16) {
+    unsafe {
+        Port::new(VBE_DISPI_IOPORT_INDEX).write(index);
+        Port::new(VBE_DISPI_IOPORT_DATA).write(value);
+    }
+}
+
+/// Looks for the Bochs VGA device on PCI bus 0 and returns the address in its BAR0.
+fn find_lfb() -> Option<u64> {
+    (0..32).find_map(|device| {
+        let id = pci_config_read(device, 0x00);
+        let vendor = (id & 0xffff) as u16;
+        let device_id = (id >> 16) as u16;
+        if vendor == BOCHS_VGA_VENDOR && device_id == BOCHS_VGA_DEVICE {
+            Some(u64::from(pci_config_read(device, 0x10) & 0xffff_fff0))
+        } else {
+            None
+        }
+    })
+}
+
+fn pci_config_read(device: u8, offset: u8) -> u32 {
+    let address = 0x8000_0000 | u32::from(device) << 11 | u32::from(offset & 0xfc);
+    unsafe {
+        Port::new(0xcf8).write(address);
+        Port::new(0xcfc).read()
+    }
+}
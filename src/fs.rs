@@ -1,3 +1,4 @@
+use crate::rtc;
 use alloc::{collections::BTreeMap, string::String, vec, vec::Vec};
 use spin::{Lazy, Mutex};
 
@@ -13,6 +14,8 @@ pub enum FileError {
 pub struct File {
     name: String,
     content: Vec<u8>,
+    /// Unix time the file was created at.
+    created: u64,
 }
 
 #[derive(Debug, Clone)]
@@ -55,6 +58,7 @@ impl File {
         Self {
             name: name.into(),
             content: Vec::new(),
+            created: rtc::unix_time().as_secs(),
         }
     }
 
@@ -62,6 +66,7 @@ impl File {
         Self {
             name: name.into(),
             content,
+            created: rtc::unix_time().as_secs(),
         }
     }
 
@@ -72,6 +77,27 @@ impl File {
     pub fn content(&self) -> &Vec<u8> {
         &self.content
     }
+
+    pub fn created(&self) -> u64 {
+        self.created
+    }
+
+    /// Copies the content from `offset` into `buf` and returns the bytes copied.
+    pub fn read_at(&self, offset: usize, buf: &mut [u8]) -> usize {
+        let available = self.content.get(offset..).unwrap_or_default();
+        let len = available.len().min(buf.len());
+        buf[..len].copy_from_slice(&available[..len]);
+        len
+    }
+
+    /// Writes `data` at `offset`, growing the file as needed.
+    pub fn write_at(&mut self, offset: usize, data: &[u8]) {
+        let end = offset + data.len();
+        if self.content.len() < end {
+            self.content.resize(end, 0);
+        }
+        self.content[offset..end].copy_from_slice(data);
+    }
 }
 
 pub struct Directory {
@@ -123,6 +149,27 @@ impl Directory {
         }
     }
 
+    fn find_file_mut(&mut self, path: &mut Path) -> Result<&mut File, FileError> {
+        if path.have_parent() {
+            let dir = path.pop_parent().unwrap();
+            if let Some(dir) = self.dirs.get_mut(&dir) {
+                dir.find_file_mut(path)
+            } else {
+                Err(FileError::NotFoundDir)
+            }
+        } else if let Some(file) = self.files.get_mut(path.file_name()) {
+            Ok(file)
+        } else {
+            Err(FileError::NotFoundFile)
+        }
+    }
+
+    /// Names of the subdirectories, ending in `/`, and of the files.
+    fn entries(&self) -> Vec<String> {
+        let dirs = self.dirs.keys().map(|name| alloc::format!("{}/", name));
+        dirs.chain(self.files.keys().cloned()).collect()
+    }
+
     fn find_files(&self, path: &mut Path) -> Result<&BTreeMap<String, File>, FileError> {
         if path.have_parent() {
             let dir = path.pop_parent().unwrap();
@@ -177,6 +224,16 @@ pub fn handle_file<F: Fn(Result<&File, Path>)>(f: F, mut path: Path) {
     f(file);
 }
 
+pub fn handle_file_mut<R>(f: impl FnOnce(Result<&mut File, Path>) -> R, mut path: Path) -> R {
+    let c_path = path.clone();
+    let mut file_system = FILE_SYSTEM.lock();
+    let file = file_system
+        .root_dir
+        .find_file_mut(&mut path)
+        .map_err(|_| c_path);
+    f(file)
+}
+
 pub fn handle_files<F: Fn(Result<&BTreeMap<String, File>, Path>)>(f: F, mut path: Path) {
     let c_path = path.clone();
     let file_system = FILE_SYSTEM.lock();
@@ -186,3 +243,18 @@ pub fn handle_files<F: Fn(Result<&BTreeMap<String, File>, Path>)>(f: F, mut path
         .map_err(|_| c_path);
     f(file);
 }
+
+/// Lists the directory at `path`. Paths name files in the root directory, so
+/// only the root itself, `/`, can be listed.
+pub fn read_dir(path: &str) -> Option<Vec<String>> {
+    match path {
+        "/" => Some(FILE_SYSTEM.lock().root_dir.entries()),
+        _ => None,
+    }
+}
+
+/// Waits for filesystem operations in progress to finish. The filesystem lives
+/// in memory, so there is nothing to write back.
+pub fn sync() {
+    drop(FILE_SYSTEM.lock());
+}
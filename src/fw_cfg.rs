@@ -0,0 +1,76 @@
+//! QEMU's firmware configuration device, through which the host passes named
+//! files to the guest, among them the kernel command line:
+//!
+//! ```text
+//! -fw_cfg name=opt/moss/cmdline,string="console=graphics"
+//! ```
+//!
+//! On machines without the device every lookup comes back empty.
+
+use alloc::{string::String, vec::Vec};
+use x86_64::instructions::port::Port;
+
+const FW_CFG_PORT_SELECTOR: u16 = 0x510;
+const FW_CFG_PORT_DATA: u16 = 0x511;
+
+const FW_CFG_SIGNATURE: u16 = 0x0000;
+const FW_CFG_FILE_DIR: u16 = 0x0019;
+
+/// Length of a name in the file directory, including the terminating NUL.
+const FILE_NAME_LEN: usize = 56;
+
+/// The file holding the kernel command line.
+const CMDLINE_FILE: &str = "opt/moss/cmdline";
+
+fn select(key: u16) {
+    unsafe { Port::new(FW_CFG_PORT_SELECTOR).write(key) };
+}
+
+/// Reads the next bytes of the selected item.
+fn read<const N: usize>() -> [u8; N] {
+    let mut data = Port::<u8>::new(FW_CFG_PORT_DATA);
+    core::array::from_fn(|_| unsafe { data.read() })
+}
+
+pub fn is_present() -> bool {
+    select(FW_CFG_SIGNATURE);
+    &read::<4>() == b"QEMU"
+}
+
+/// Returns the contents of the file `name`, if the host passed one.
+pub fn read_file(name: &str) -> Option<Vec<u8>> {
+    if !is_present() {
+        return None;
+    }
+    select(FW_CFG_FILE_DIR);
+    let count = u32::from_be_bytes(read());
+    let (size, key) = (0..count).find_map(|_| {
+        let size = u32::from_be_bytes(read());
+        let key = u16::from_be_bytes(read());
+        let _reserved: [u8; 2] = read();
+        let entry: [u8; FILE_NAME_LEN] = read();
+        let len = entry.iter().position(|&b| b == 0).unwrap_or(FILE_NAME_LEN);
+        (&entry[..len] == name.as_bytes()).then_some((size, key))
+    })?;
+
+    select(key);
+    let mut data = Port::<u8>::new(FW_CFG_PORT_DATA);
+    Some((0..size).map(|_| unsafe { data.read() }).collect())
+}
+
+/// Returns the value of `key=value` on the kernel command line.
+pub fn boot_option(key: &str) -> Option<String> {
+    let cmdline = String::from_utf8(read_file(CMDLINE_FILE)?).ok()?;
+    cmdline.split_whitespace().find_map(|option| {
+        let value = option.strip_prefix(key)?.strip_prefix('=')?;
+        Some(value.into())
+    })
+}
+
+#[test_case]
+fn test_file_lookup() {
+    // QEMU always has the device
+    assert!(is_present());
+    assert!(read_file("opt/moss/no such file").is_none());
+    assert!(boot_option("console").is_none());
+}
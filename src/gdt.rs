@@ -1,3 +1,5 @@
+use core::cell::UnsafeCell;
+use core::sync::atomic::{AtomicU64, Ordering};
 use spin::Lazy;
 use x86_64::structures::gdt::Descriptor;
 use x86_64::structures::gdt::GlobalDescriptorTable;
@@ -5,9 +7,16 @@ use x86_64::structures::gdt::SegmentSelector;
 use x86_64::structures::tss::TaskStateSegment;
 use x86_64::VirtAddr;
 
+/// Double faults get their own stack. A page fault caused by a stack
+/// overflow cannot push its frame on the faulting stack and becomes one.
 pub const DOUBLE_FAULT_IST_INDEX: u16 = 0;
 
-static TSS: Lazy<TaskStateSegment> = Lazy::new(|| {
+/// The TSS, which `set_kernel_stack` updates on every thread switch.
+struct Tss(UnsafeCell<TaskStateSegment>);
+
+unsafe impl Sync for Tss {}
+
+static TSS: Lazy<Tss> = Lazy::new(|| {
     let mut tss = TaskStateSegment::new();
     tss.interrupt_stack_table[DOUBLE_FAULT_IST_INDEX as usize] = {
         const STACK_SIZE: usize = 4096 * 5;
@@ -17,19 +26,24 @@ static TSS: Lazy<TaskStateSegment> = Lazy::new(|| {
         let stack_end = stack_start + STACK_SIZE;
         stack_end
     };
-    tss
+    Tss(UnsafeCell::new(tss))
 });
 
 static GDT: Lazy<(GlobalDescriptorTable, Selectors)> = Lazy::new(|| {
     let mut gdt = GlobalDescriptorTable::new();
     let code_selector = gdt.add_entry(Descriptor::kernel_code_segment());
     let stack_selector = gdt.add_entry(Descriptor::kernel_data_segment());
-    let tss_selector = gdt.add_entry(Descriptor::tss_segment(&TSS));
+    // sysret expects the user data segment right before the user code segment
+    let user_data_selector = gdt.add_entry(Descriptor::user_data_segment());
+    let user_code_selector = gdt.add_entry(Descriptor::user_code_segment());
+    let tss_selector = gdt.add_entry(Descriptor::tss_segment(unsafe { &*TSS.0.get() }));
     (
         gdt,
         Selectors {
             code_selector,
             stack_selector,
+            user_data_selector,
+            user_code_selector,
             tss_selector,
         },
     )
@@ -38,6 +52,8 @@ static GDT: Lazy<(GlobalDescriptorTable, Selectors)> = Lazy::new(|| {
 pub struct Selectors {
     pub code_selector: SegmentSelector,
     pub stack_selector: SegmentSelector,
+    pub user_data_selector: SegmentSelector,
+    pub user_code_selector: SegmentSelector,
     pub tss_selector: SegmentSelector,
 }
 
@@ -65,3 +81,13 @@ pub fn init() {
 pub fn selectors() -> &'static Selectors {
     &GDT.1
 }
+
+/// Top of the stack that system calls switch to, the same as the TSS's.
+pub(crate) static KERNEL_STACK: AtomicU64 = AtomicU64::new(0);
+
+/// Sets the stack the CPU switches to when an interrupt or system call
+/// arrives in user mode.
+pub fn set_kernel_stack(top: VirtAddr) {
+    unsafe { (*TSS.0.get()).privilege_stack_table[0] = top };
+    KERNEL_STACK.store(top.as_u64(), Ordering::Relaxed);
+}
@@ -1,18 +1,43 @@
 use crate::keyboard::add_scancode;
-use crate::{gdt, println};
+use crate::process;
+use crate::sync::IrqSpinLock;
+use crate::thread::{self, InterruptedRegisters};
+use crate::{apic, gdt, println, serial, syscall, warn};
+use core::arch::asm;
 use spin::Lazy;
-use x86_64::structures::idt::{InterruptDescriptorTable, InterruptStackFrame};
+use x86_64::structures::idt::{InterruptDescriptorTable, InterruptStackFrame, PageFaultErrorCode};
+use x86_64::{PrivilegeLevel, VirtAddr};
+
+/// Vector of the `int 0x80` system call gate, open to user mode.
+const SYSCALL_VECTOR: usize = 0x80;
 
 static IDT: Lazy<InterruptDescriptorTable> = Lazy::new(|| {
     let mut idt = InterruptDescriptorTable::new();
     idt.breakpoint.set_handler_fn(breakpoint_handler);
+    idt.invalid_opcode.set_handler_fn(invalid_opcode_handler);
+    idt.general_protection_fault
+        .set_handler_fn(general_protection_fault_handler);
     unsafe {
         idt.double_fault
             .set_handler_fn(double_fault_handler)
             .set_stack_index(gdt::DOUBLE_FAULT_IST_INDEX);
     }
-    idt[InterruptIndex::Timer.as_usize()].set_handler_fn(timer_interrupt_handler);
+    // on the faulting stack, so a fault in the handler cannot overwrite the
+    // frame of the first one
+    idt.page_fault.set_handler_fn(page_fault_handler);
+    unsafe {
+        idt[InterruptIndex::Timer.as_usize()]
+            .set_handler_addr(VirtAddr::new(timer_interrupt_entry as *const u8 as u64));
+    }
+    unsafe {
+        idt[SYSCALL_VECTOR]
+            .set_handler_addr(VirtAddr::new(syscall::int80_entry as *const u8 as u64))
+            .set_privilege_level(PrivilegeLevel::Ring3);
+    }
     idt[InterruptIndex::Keyboard.as_usize()].set_handler_fn(keyboard_interrupt_handler);
+    idt[InterruptIndex::Serial.as_usize()].set_handler_fn(serial_interrupt_handler);
+    idt[InterruptIndex::Mouse.as_usize()].set_handler_fn(mouse_interrupt_handler);
+    idt[usize::from(apic::SPURIOUS_VECTOR)].set_handler_fn(spurious_interrupt_handler);
 
     idt
 });
@@ -29,48 +54,209 @@ extern "x86-interrupt" fn double_fault_handler(
     stack_frame: InterruptStackFrame,
     _error_code: u64,
 ) -> ! {
+    use x86_64::registers::control::Cr2;
+
+    // a page fault on a stack's guard page cannot push its frame there
+    let addr = Cr2::read();
+    if let Some((id, name)) = thread::stack_overflowed(addr) {
+        panic!(
+            "EXCEPTION: STACK OVERFLOW in thread {} ({}) at {:#x}\n{:#?}",
+            id,
+            name,
+            addr.as_u64(),
+            stack_frame
+        );
+    }
     panic!("EXCEPTION: DOUBLE FAULT\n{:#?}", stack_frame);
 }
 
+extern "x86-interrupt" fn page_fault_handler(
+    stack_frame: InterruptStackFrame,
+    error_code: PageFaultErrorCode,
+) {
+    use x86_64::registers::control::Cr2;
+
+    let addr = Cr2::read();
+    if is_user_mode(&stack_frame) {
+        kill_user_thread("page fault", &stack_frame);
+    }
+    panic!(
+        "EXCEPTION: PAGE FAULT at {:#x} ({:?})\n{:#?}",
+        addr.as_u64(),
+        error_code,
+        stack_frame
+    );
+}
+
+extern "x86-interrupt" fn general_protection_fault_handler(
+    stack_frame: InterruptStackFrame,
+    error_code: u64,
+) {
+    if is_user_mode(&stack_frame) {
+        kill_user_thread("general protection fault", &stack_frame);
+    }
+    panic!(
+        "EXCEPTION: GENERAL PROTECTION FAULT ({:#x})\n{:#?}",
+        error_code, stack_frame
+    );
+}
+
+extern "x86-interrupt" fn invalid_opcode_handler(stack_frame: InterruptStackFrame) {
+    if is_user_mode(&stack_frame) {
+        kill_user_thread("invalid opcode", &stack_frame);
+    }
+    panic!("EXCEPTION: INVALID OPCODE\n{:#?}", stack_frame);
+}
+
+fn is_user_mode(stack_frame: &InterruptStackFrame) -> bool {
+    stack_frame.code_segment & 3 == 3
+}
+
+/// Ends the process whose user mode code raised an exception, instead of
+/// bringing the kernel down with it.
+fn kill_user_thread(exception: &str, stack_frame: &InterruptStackFrame) -> ! {
+    if let Some(id) = thread::current() {
+        warn!(
+            "thread {} killed by {} at {:#x}",
+            id,
+            exception,
+            stack_frame.instruction_pointer.as_u64()
+        );
+    }
+    process::exit_current(process::EXIT_FAULT)
+}
+
 #[test_case]
 fn test_breakpoint_exception() {
     x86_64::instructions::interrupts::int3();
 }
 
+use core::sync::atomic::{AtomicU64, Ordering};
 use pic8259::ChainedPics;
-use spin::Mutex;
 
 pub const PIC_1_OFFSET: u8 = 32;
 pub const PIC_2_OFFSET: u8 = PIC_1_OFFSET + 8;
 
-pub static PICS: Mutex<ChainedPics> =
-    Mutex::new(unsafe { ChainedPics::new(PIC_1_OFFSET, PIC_2_OFFSET) });
+pub static PICS: IrqSpinLock<ChainedPics> =
+    IrqSpinLock::new(unsafe { ChainedPics::new(PIC_1_OFFSET, PIC_2_OFFSET) });
 
 #[derive(Debug, Clone, Copy)]
 #[repr(u8)]
 pub enum InterruptIndex {
     Timer = PIC_1_OFFSET,
     Keyboard,
+    Serial = PIC_1_OFFSET + 4,
+    Mouse = PIC_2_OFFSET + 4,
 }
 
 impl InterruptIndex {
-    fn as_u8(self) -> u8 {
+    pub fn as_u8(self) -> u8 {
         self as u8
     }
 
     fn as_usize(self) -> usize {
         usize::from(self.as_u8())
     }
+
+    /// The legacy ISA IRQ line the interrupt arrives on.
+    pub fn irq(self) -> u8 {
+        self.as_u8() - PIC_1_OFFSET
+    }
 }
 
-pub static GLOBAL_COUNTER: Mutex<u64> = Mutex::new(0);
+/// Lets `index` through whichever interrupt controller is in use.
+pub fn unmask(index: InterruptIndex) {
+    if apic::is_enabled() {
+        return apic::set_irq_masked(index.irq(), false);
+    }
+    let irq = index.irq();
+    let mut pics = PICS.lock();
+    unsafe {
+        let [mut mask1, mut mask2] = pics.read_masks();
+        if irq < 8 {
+            mask1 &= !(1 << irq);
+        } else {
+            // the second PIC is cascaded through IRQ2
+            mask1 &= !(1 << 2);
+            mask2 &= !(1 << (irq - 8));
+        }
+        pics.write_masks(mask1, mask2);
+    }
+}
+
+fn end_of_interrupt(index: InterruptIndex) {
+    if apic::is_enabled() {
+        apic::end_of_interrupt();
+    } else {
+        unsafe { PICS.lock().notify_end_of_interrupt(index.as_u8()) };
+    }
+}
 
-extern "x86-interrupt" fn timer_interrupt_handler(_stack_frame: InterruptStackFrame) {
-    *GLOBAL_COUNTER.lock() += 1;
+pub static GLOBAL_COUNTER: AtomicU64 = AtomicU64::new(0);
+
+/// Number of timer interrupts since boot.
+pub fn ticks() -> u64 {
+    GLOBAL_COUNTER.load(Ordering::Relaxed)
+}
+
+/// Saves the general purpose registers below the interrupt frame, so the
+/// scheduler can switch threads by rewriting them.
+#[naked]
+extern "C" fn timer_interrupt_entry() {
     unsafe {
-        PICS.lock()
-            .notify_end_of_interrupt(InterruptIndex::Timer.as_u8());
+        asm!(
+            "push rax",
+            "push rbx",
+            "push rcx",
+            "push rdx",
+            "push rsi",
+            "push rdi",
+            "push rbp",
+            "push r8",
+            "push r9",
+            "push r10",
+            "push r11",
+            "push r12",
+            "push r13",
+            "push r14",
+            "push r15",
+            //
+            // the frame and 15 registers leave the stack 16-byte aligned
+            "cld",
+            "mov rdi, rsp",
+            "call {handler}",
+            //
+            "pop r15",
+            "pop r14",
+            "pop r13",
+            "pop r12",
+            "pop r11",
+            "pop r10",
+            "pop r9",
+            "pop r8",
+            "pop rbp",
+            "pop rdi",
+            "pop rsi",
+            "pop rdx",
+            "pop rcx",
+            "pop rbx",
+            "pop rax",
+            "iretq",
+            handler = sym timer_interrupt_handler,
+            options(noreturn)
+        );
+    }
+}
+
+extern "C" fn timer_interrupt_handler(registers: &mut InterruptedRegisters) {
+    GLOBAL_COUNTER.fetch_add(1, Ordering::Relaxed);
+    crate::time::tick();
+    end_of_interrupt(InterruptIndex::Timer);
+    // a killed process stuck in user mode leaves here
+    if registers.cs & 3 == 3 && thread::is_killed() {
+        process::exit_current(process::EXIT_KILLED);
     }
+    thread::preempt(registers);
 }
 
 extern "x86-interrupt" fn keyboard_interrupt_handler(_stack_frame: InterruptStackFrame) {
@@ -81,8 +267,25 @@ extern "x86-interrupt" fn keyboard_interrupt_handler(_stack_frame: InterruptStac
     let scancode: u8 = unsafe { port.read() };
     add_scancode(scancode);
 
-    unsafe {
-        PICS.lock()
-            .notify_end_of_interrupt(InterruptIndex::Keyboard.as_u8());
-    }
+    end_of_interrupt(InterruptIndex::Keyboard);
+}
+
+extern "x86-interrupt" fn mouse_interrupt_handler(_stack_frame: InterruptStackFrame) {
+    use x86_64::instructions::port::Port;
+
+    let mut port = Port::new(0x60);
+
+    let byte: u8 = unsafe { port.read() };
+    crate::mouse::add_byte(byte);
+
+    end_of_interrupt(InterruptIndex::Mouse);
+}
+
+extern "x86-interrupt" fn serial_interrupt_handler(_stack_frame: InterruptStackFrame) {
+    serial::receive_interrupt();
+    end_of_interrupt(InterruptIndex::Serial);
+}
+
+extern "x86-interrupt" fn spurious_interrupt_handler(_stack_frame: InterruptStackFrame) {
+    // spurious interrupts must not be acknowledged
 }
@@ -1,4 +1,4 @@
-use crate::{println, terminal};
+use crate::{terminal, vga_buffer, warn};
 use core::{
     pin::Pin,
     task::{Context, Poll},
@@ -15,12 +15,12 @@ static WAKER: AtomicWaker = AtomicWaker::new();
 pub fn add_scancode(scancode: u8) {
     if let Some(queue) = SCANCODE_QUEUE.get() {
         if let Err(_) = queue.push(scancode) {
-            println!("WARNING: scancode queue full; dropping keyboard input");
+            warn!("scancode queue full; dropping keyboard input");
         } else {
             WAKER.wake();
         }
     } else {
-        println!("WARNING: scancode queue uninitialized");
+        warn!("scancode queue uninitialized");
     }
 }
 
@@ -56,18 +56,284 @@ impl Stream for ScancodeStream {
     }
 }
 
+use alloc::{
+    sync::{Arc, Weak},
+    vec::Vec,
+};
+use core::sync::atomic::{AtomicU8, Ordering};
 use futures_util::stream::StreamExt;
-use pc_keyboard::{layouts, HandleControl, Keyboard, ScancodeSet1};
+use pc_keyboard::{layouts, HandleControl, Keyboard, KeyboardLayout, Modifiers, ScancodeSet1};
+use spin::Mutex;
 
-pub async fn print_keypresses() {
+pub use pc_keyboard::{DecodedKey, KeyCode, KeyState};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u8)]
+pub enum Layout {
+    Us,
+    Uk,
+    Jis,
+    Dvorak,
+    Azerty,
+}
+
+impl Layout {
+    pub const ALL: [Layout; 5] = [
+        Layout::Us,
+        Layout::Uk,
+        Layout::Jis,
+        Layout::Dvorak,
+        Layout::Azerty,
+    ];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Layout::Us => "us",
+            Layout::Uk => "uk",
+            Layout::Jis => "jis",
+            Layout::Dvorak => "dvorak",
+            Layout::Azerty => "azerty",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        Self::ALL.into_iter().find(|layout| layout.name() == name)
+    }
+
+    fn map_keycode(self, code: KeyCode, modifiers: &Modifiers) -> DecodedKey {
+        let ctrl = HandleControl::MapLettersToUnicode;
+        match self {
+            Layout::Us => layouts::Us104Key::map_keycode(code, modifiers, ctrl),
+            Layout::Uk => layouts::Uk105Key::map_keycode(code, modifiers, ctrl),
+            Layout::Jis => layouts::Jis109Key::map_keycode(code, modifiers, ctrl),
+            Layout::Dvorak => layouts::Dvorak104Key::map_keycode(code, modifiers, ctrl),
+            Layout::Azerty => layouts::Azerty::map_keycode(code, modifiers, ctrl),
+        }
+    }
+}
+
+static LAYOUT: AtomicU8 = AtomicU8::new(Layout::Us as u8);
+
+pub fn layout() -> Layout {
+    Layout::ALL[usize::from(LAYOUT.load(Ordering::Relaxed))]
+}
+
+pub fn set_layout(layout: Layout) {
+    LAYOUT.store(layout as u8, Ordering::Relaxed);
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ModifierState {
+    pub shift: bool,
+    pub ctrl: bool,
+    pub alt: bool,
+    pub alt_gr: bool,
+    pub caps_lock: bool,
+    pub num_lock: bool,
+}
+
+/// A key press or release as seen by `KeyEventStream` subscribers.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct KeyEvent {
+    pub code: KeyCode,
+    pub state: KeyState,
+    /// Modifiers in effect after this event was applied.
+    pub modifiers: ModifierState,
+    /// What the key means in the current layout; only set for presses.
+    /// Ctrl+letter is delivered as the control character U+0001..U+001A.
+    pub key: Option<DecodedKey>,
+}
+
+/// Turns scancodes into `KeyEvent`s, tracking modifiers across events.
+struct Decoder {
+    keyboard: Keyboard<layouts::Us104Key, ScancodeSet1>,
+    modifiers: Modifiers,
+    alt: bool,
+}
+
+impl Decoder {
+    fn new() -> Self {
+        Self {
+            // only used to assemble scancodes into key codes; the layout is applied separately
+            keyboard: Keyboard::new(layouts::Us104Key, ScancodeSet1, HandleControl::Ignore),
+            modifiers: Modifiers {
+                lshift: false,
+                rshift: false,
+                lctrl: false,
+                rctrl: false,
+                numlock: true,
+                capslock: false,
+                alt_gr: false,
+            },
+            alt: false,
+        }
+    }
+
+    fn add_byte(&mut self, scancode: u8) -> Option<KeyEvent> {
+        let event = self.keyboard.add_byte(scancode).ok()??;
+        let down = event.state == KeyState::Down;
+        let modifiers = &mut self.modifiers;
+        match event.code {
+            KeyCode::ShiftLeft => modifiers.lshift = down,
+            KeyCode::ShiftRight => modifiers.rshift = down,
+            KeyCode::ControlLeft => modifiers.lctrl = down,
+            KeyCode::ControlRight => modifiers.rctrl = down,
+            KeyCode::AltLeft => self.alt = down,
+            KeyCode::AltRight => modifiers.alt_gr = down,
+            KeyCode::CapsLock if down => modifiers.capslock = !modifiers.capslock,
+            KeyCode::NumpadLock if down => modifiers.numlock = !modifiers.numlock,
+            _ => {}
+        }
+
+        let key = match (event.state, event.code) {
+            (KeyState::Up, _) => None,
+            (
+                _,
+                KeyCode::ShiftLeft
+                | KeyCode::ShiftRight
+                | KeyCode::ControlLeft
+                | KeyCode::ControlRight
+                | KeyCode::AltLeft
+                | KeyCode::AltRight
+                | KeyCode::CapsLock
+                | KeyCode::NumpadLock,
+            ) => None,
+            (_, code) => Some(layout().map_keycode(code, &self.modifiers)),
+        };
+
+        Some(KeyEvent {
+            code: event.code,
+            state: event.state,
+            modifiers: ModifierState {
+                shift: self.modifiers.is_shifted(),
+                ctrl: self.modifiers.is_ctrl(),
+                alt: self.alt,
+                alt_gr: self.modifiers.alt_gr,
+                caps_lock: self.modifiers.capslock,
+                num_lock: self.modifiers.numlock,
+            },
+            key,
+        })
+    }
+}
+
+struct Subscriber {
+    queue: ArrayQueue<KeyEvent>,
+    waker: AtomicWaker,
+}
+
+static SUBSCRIBERS: Mutex<Vec<Weak<Subscriber>>> = Mutex::new(Vec::new());
+
+/// Stream of every key event decoded after it was created.
+pub struct KeyEventStream {
+    subscriber: Arc<Subscriber>,
+}
+
+impl KeyEventStream {
+    pub fn new() -> KeyEventStream {
+        let subscriber = Arc::new(Subscriber {
+            queue: ArrayQueue::new(100),
+            waker: AtomicWaker::new(),
+        });
+        SUBSCRIBERS.lock().push(Arc::downgrade(&subscriber));
+        KeyEventStream { subscriber }
+    }
+}
+
+impl Default for KeyEventStream {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Stream for KeyEventStream {
+    type Item = KeyEvent;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Option<KeyEvent>> {
+        let subscriber = &self.subscriber;
+
+        if let Some(event) = subscriber.queue.pop() {
+            return Poll::Ready(Some(event));
+        }
+        subscriber.waker.register(cx.waker());
+
+        match subscriber.queue.pop() {
+            Some(event) => {
+                subscriber.waker.take();
+                Poll::Ready(Some(event))
+            }
+            None => Poll::Pending,
+        }
+    }
+}
+
+fn publish(event: KeyEvent) {
+    SUBSCRIBERS.lock().retain(|subscriber| {
+        let Some(subscriber) = subscriber.upgrade() else {
+            return false;
+        };
+        if subscriber.queue.push(event).is_err() {
+            warn!("key event queue full; dropping {:?}", event.code);
+        }
+        subscriber.waker.wake();
+        true
+    });
+}
+
+/// Decodes scancodes from the keyboard interrupt and hands the resulting
+/// events to every `KeyEventStream`.
+pub async fn dispatch_key_events() {
     let mut scancodes = ScancodeStream::new();
-    let mut keyboard = Keyboard::new(layouts::Us104Key, ScancodeSet1, HandleControl::Ignore);
+    let mut decoder = Decoder::new();
 
     while let Some(scancode) = scancodes.next().await {
-        if let Ok(Some(key_event)) = keyboard.add_byte(scancode) {
-            if let Some(key) = keyboard.process_keyevent(key_event) {
-                terminal::push_key(key);
+        if let Some(event) = decoder.add_byte(scancode) {
+            publish(event);
+        }
+    }
+}
+
+/// Lines moved per Shift+PageUp/PageDown.
+const SCROLL_LINES: usize = 10;
+
+/// Feeds key presses to the terminal.
+pub async fn print_keypresses() {
+    let mut events = KeyEventStream::new();
+
+    while let Some(event) = events.next().await {
+        match (event.key, event.modifiers.shift) {
+            (Some(DecodedKey::RawKey(KeyCode::PageUp)), true) => {
+                vga_buffer::scroll_up(SCROLL_LINES)
+            }
+            (Some(DecodedKey::RawKey(KeyCode::PageDown)), true) => {
+                vga_buffer::scroll_down(SCROLL_LINES)
             }
+            (Some(key), _) => terminal::push_key(key),
+            (None, _) => {}
         }
     }
 }
+
+#[test_case]
+fn test_ctrl_letter() {
+    let mut decoder = Decoder::new();
+    let ctrl = decoder.add_byte(0x1d).unwrap();
+    assert!(ctrl.modifiers.ctrl);
+    assert_eq!(ctrl.key, None);
+    let c = decoder.add_byte(0x2e).unwrap();
+    assert_eq!(c.key, Some(DecodedKey::Unicode('\u{3}')));
+    let release = decoder.add_byte(0xae).unwrap();
+    assert_eq!(release.state, KeyState::Up);
+    assert_eq!(release.key, None);
+}
+
+#[test_case]
+fn test_layout_selection() {
+    let mut decoder = Decoder::new();
+    set_layout(Layout::Azerty);
+    let a = decoder.add_byte(0x10).unwrap();
+    set_layout(Layout::Us);
+    let q = decoder.add_byte(0x10).unwrap();
+    assert_eq!(a.key, Some(DecodedKey::Unicode('a')));
+    assert_eq!(q.key, Some(DecodedKey::Unicode('q')));
+}
@@ -13,17 +13,37 @@ use bootloader::BootInfo;
 use core::panic::PanicInfo;
 use x86_64::VirtAddr;
 
+pub mod acpi;
 pub mod allocator;
+pub mod ansi;
+pub mod apic;
+pub mod cp437;
+pub mod elf;
 pub mod error;
 pub mod exec;
+pub mod font;
+pub mod framebuffer;
 pub mod fs;
+pub mod fw_cfg;
 pub mod gdt;
 pub mod interrupts;
 pub mod keyboard;
+pub mod log;
+pub mod mouse;
 pub mod paging;
+pub mod pipe;
+pub mod power;
+pub mod process;
+pub mod rtc;
 pub mod serial;
+pub mod stack;
+pub mod sync;
+pub mod syscall;
 pub mod task;
 pub mod terminal;
+pub mod thread;
+pub mod time;
+pub mod usermode;
 pub mod vga_buffer;
 
 use error::Result;
@@ -38,11 +58,39 @@ pub fn init(boot_info: &'static BootInfo) -> Result<()> {
     let mut mapper = paging::get_mapper()?;
     let mut allocator = unsafe { paging::BootInfoFrameAllocator::init(&boot_info.memory_map) };
 
-    paging::make_identity_mapping(&mut mapper, &mut allocator, 0xfee00000, 1).unwrap();
     allocator::init_heap(&mut *mapper, &mut allocator)?;
+    acpi::init();
+
+    // the framebuffer console is picked on the command line, and VGA text
+    // mode stays in use if it cannot be set up
+    if fw_cfg::boot_option("console").as_deref() == Some("graphics") {
+        let (width, height) = (framebuffer::DEFAULT_WIDTH, framebuffer::DEFAULT_HEIGHT);
+        match framebuffer::init(&mut *mapper, &mut allocator, width, height) {
+            Ok(()) => vga_buffer::use_framebuffer(),
+            Err(err) => warn!("graphics console unavailable: {}", err),
+        }
+    }
 
     interrupts::init_idt();
+    syscall::init();
     unsafe { interrupts::PICS.lock().initialize() };
+    if let Err(err) = apic::init(&mut mapper, &mut allocator) {
+        warn!("APIC unavailable, using the 8259 PIC: {}", err);
+        // the I/O APIC routing unmasks it otherwise
+        interrupts::unmask(interrupts::InterruptIndex::Serial);
+    }
+    if let Err(err) = time::init(&mut mapper, &mut allocator) {
+        warn!("timer setup failed: {}", err);
+    }
+    rtc::init();
+
+    drop(mapper);
+    paging::install_frame_allocator(allocator);
+    thread::init()?;
+    exec::install_programs();
+    if let Err(err) = mouse::init() {
+        warn!("mouse unavailable: {}", err);
+    }
     x86_64::instructions::interrupts::enable();
 
     Ok(())
@@ -120,7 +168,7 @@ pub fn hlt_loop() -> ! {
     }
 }
 
-use alloc::{boxed::Box, vec, vec::Vec};
+use alloc::boxed::Box;
 use core::arch::asm;
 use core::mem;
 use spin::Once;
@@ -163,29 +211,29 @@ pub struct TaskContext {
     fxsave_area: [u8; 512],
 }
 
-#[derive(Debug, Clone, Copy, Default)]
-#[repr(C, align(16))]
-pub struct TaskStackElement {
-    _dummy: [u64; 2],
-}
-
 pub struct ContextTask {
     ctx: Box<TaskContext>,
-    stack: Vec<TaskStackElement>,
+    /// `None` for the boot thread, which runs on the stack the bootloader set up.
+    stack: Option<stack::KernelStack>,
 }
 
 pub type EntryPoint = extern "C" fn(arg0: u64, arg1: u64);
 
 impl ContextTask {
-    pub fn new(entry_point: EntryPoint, arg0: u64, arg1: u64) -> Self {
-        let stack_size = 1024 * 8;
-        let stack_elem_size = mem::size_of::<TaskStackElement>();
+    pub fn new(entry_point: EntryPoint, arg0: u64, arg1: u64) -> Result<Self> {
+        Self::with_stack_size(entry_point, arg0, arg1, 1024 * 8)
+    }
+
+    pub fn with_stack_size(
+        entry_point: EntryPoint,
+        arg0: u64,
+        arg1: u64,
+        stack_size: usize,
+    ) -> Result<Self> {
+        let stack = stack::KernelStack::new(stack_size)?;
         let mut task = Self {
             ctx: Box::new(unsafe { mem::zeroed() }),
-            stack: vec![
-                TaskStackElement::default();
-                (stack_size + stack_elem_size - 1) / stack_elem_size
-            ],
+            stack: None,
         };
 
         let selectors = crate::gdt::selectors();
@@ -198,12 +246,13 @@ impl ContextTask {
         task.ctx.rflags = 0x202;
         task.ctx.cs = u64::from(selectors.code_selector.0);
         task.ctx.ss = u64::from(selectors.stack_selector.0);
-        task.ctx.rsp = unsafe { (task.stack.as_ptr() as *const u8).add(stack_size - 8) as u64 };
+        task.ctx.rsp = stack.top().as_u64() - 8;
         assert!(task.ctx.rsp & 0xf == 8);
 
         task.ctx.fxsave_area[24..][..4].copy_from_slice(&0x1f80u32.to_le_bytes());
+        task.stack = Some(stack);
 
-        task
+        Ok(task)
     }
 
     pub fn switch(next: &'static ContextTask, current: &'static ContextTask) {
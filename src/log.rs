@@ -0,0 +1,317 @@
+//! Kernel logger.
+//!
+//! Records are kept in a lock-free ring buffer, so the `error!` .. `trace!`
+//! macros can be used from interrupt handlers, and are echoed to the console
+//! and the serial port when their level passes the sink's filter.
+
+use crate::interrupts;
+use alloc::{collections::BTreeMap, string::String};
+use core::{
+    cell::UnsafeCell,
+    fmt::{self, Write},
+    str::FromStr,
+    sync::atomic::{fence, AtomicU64, AtomicU8, Ordering},
+};
+use spin::RwLock;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+#[repr(u8)]
+pub enum Level {
+    Error = 1,
+    Warn,
+    Info,
+    Debug,
+    Trace,
+}
+
+impl FromStr for Level {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, ()> {
+        match s {
+            "error" => Ok(Level::Error),
+            "warn" => Ok(Level::Warn),
+            "info" => Ok(Level::Info),
+            "debug" => Ok(Level::Debug),
+            "trace" => Ok(Level::Trace),
+            _ => Err(()),
+        }
+    }
+}
+
+impl Level {
+    fn from_u8(value: u8) -> Option<Self> {
+        match value {
+            1 => Some(Level::Error),
+            2 => Some(Level::Warn),
+            3 => Some(Level::Info),
+            4 => Some(Level::Debug),
+            5 => Some(Level::Trace),
+            _ => None,
+        }
+    }
+
+    fn as_str(self) -> &'static str {
+        match self {
+            Level::Error => "ERROR",
+            Level::Warn => "WARN",
+            Level::Info => "INFO",
+            Level::Debug => "DEBUG",
+            Level::Trace => "TRACE",
+        }
+    }
+}
+
+impl fmt::Display for Level {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.pad(self.as_str())
+    }
+}
+
+/// Places log records are echoed to besides the ring buffer.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Sink {
+    Console,
+    Serial,
+}
+
+/// Most verbose level recorded for targets without a filter of their own; 0 is off.
+static MAX_LEVEL: AtomicU8 = AtomicU8::new(Level::Info as u8);
+
+static SINK_LEVELS: [AtomicU8; 2] = [
+    AtomicU8::new(Level::Info as u8),
+    AtomicU8::new(Level::Warn as u8),
+];
+
+/// Per-target levels, matched against the longest module path prefix.
+static FILTERS: RwLock<BTreeMap<String, u8>> = RwLock::new(BTreeMap::new());
+
+/// Sets the level for targets under `target`, or the default level if `target` is `None`.
+/// A level of `None` turns logging off.
+pub fn set_level(target: Option<&str>, level: Option<Level>) {
+    let value = level.map_or(0, |level| level as u8);
+    match target {
+        Some(target) => {
+            FILTERS.write().insert(target.into(), value);
+        }
+        None => MAX_LEVEL.store(value, Ordering::Relaxed),
+    }
+}
+
+pub fn set_sink_level(sink: Sink, level: Option<Level>) {
+    let value = level.map_or(0, |level| level as u8);
+    SINK_LEVELS[sink as usize].store(value, Ordering::Relaxed);
+}
+
+fn enabled(level: Level, target: &str) -> bool {
+    let mut max = MAX_LEVEL.load(Ordering::Relaxed);
+    // an interrupted writer holds the lock; fall back to the default level
+    if let Some(filters) = FILTERS.try_read() {
+        let mut best = 0;
+        for (prefix, &value) in filters.iter() {
+            let matches = target
+                .strip_prefix(prefix.as_str())
+                .is_some_and(|rest| rest.is_empty() || rest.starts_with("::"));
+            if matches && prefix.len() >= best {
+                best = prefix.len();
+                max = value;
+            }
+        }
+    }
+    level as u8 <= max
+}
+
+const RING_SLOTS: usize = 256;
+const MESSAGE_LEN: usize = 120;
+
+#[derive(Clone, Copy)]
+pub struct Record {
+    pub ticks: u64,
+    pub level: Level,
+    len: u8,
+    text: [u8; MESSAGE_LEN],
+}
+
+impl Record {
+    /// The formatted `target: message` text, truncated to fit the slot.
+    pub fn text(&self) -> &str {
+        let text = &self.text[..usize::from(self.len)];
+        core::str::from_utf8(text).unwrap_or("<invalid utf-8>")
+    }
+}
+
+impl fmt::Display for Record {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "[{:>8}] {:<5} {}", self.ticks, self.level, self.text())
+    }
+}
+
+/// Fixed-size text buffer that drops whatever does not fit.
+struct Text {
+    buf: [u8; MESSAGE_LEN],
+    len: usize,
+}
+
+impl fmt::Write for Text {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        for c in s.chars() {
+            let end = self.len + c.len_utf8();
+            if end > MESSAGE_LEN {
+                break;
+            }
+            c.encode_utf8(&mut self.buf[self.len..end]);
+            self.len = end;
+        }
+        Ok(())
+    }
+}
+
+struct Slot {
+    /// 0 while empty, `2 * seq + 1` while record `seq` is written and
+    /// `2 * seq + 2` once it is complete.
+    state: AtomicU64,
+    record: UnsafeCell<Record>,
+}
+
+struct Ring {
+    next: AtomicU64,
+    slots: [Slot; RING_SLOTS],
+}
+
+// Slots are only accessed under the sequence protocol in `push` and `read`.
+unsafe impl Sync for Ring {}
+
+static RING: Ring = Ring {
+    next: AtomicU64::new(0),
+    slots: [const {
+        Slot {
+            state: AtomicU64::new(0),
+            record: UnsafeCell::new(Record {
+                ticks: 0,
+                level: Level::Trace,
+                len: 0,
+                text: [0; MESSAGE_LEN],
+            }),
+        }
+    }; RING_SLOTS],
+};
+
+impl Ring {
+    fn push(&self, record: &Record) {
+        let seq = self.next.fetch_add(1, Ordering::Relaxed);
+        let slot = &self.slots[seq as usize % RING_SLOTS];
+        slot.state.store(2 * seq + 1, Ordering::Relaxed);
+        fence(Ordering::Release);
+        unsafe { slot.record.get().write_volatile(*record) };
+        slot.state.store(2 * seq + 2, Ordering::Release);
+    }
+
+    /// Returns record `seq` unless it was overwritten or is still being written.
+    fn read(&self, seq: u64) -> Option<Record> {
+        let slot = &self.slots[seq as usize % RING_SLOTS];
+        if slot.state.load(Ordering::Acquire) != 2 * seq + 2 {
+            return None;
+        }
+        let record = unsafe { slot.record.get().read_volatile() };
+        fence(Ordering::Acquire);
+        (slot.state.load(Ordering::Relaxed) == 2 * seq + 2).then_some(record)
+    }
+}
+
+/// Calls `f` with the records still held in the ring buffer, oldest first.
+pub fn for_each_record(mut f: impl FnMut(&Record)) {
+    let next = RING.next.load(Ordering::Acquire);
+    let first = next.saturating_sub(RING_SLOTS as u64);
+    for seq in first..next {
+        if let Some(record) = RING.read(seq) {
+            f(&record);
+        }
+    }
+}
+
+#[doc(hidden)]
+pub fn _log(level: Level, target: &'static str, args: fmt::Arguments) {
+    if !enabled(level, target) {
+        return;
+    }
+
+    let mut text = Text {
+        buf: [0; MESSAGE_LEN],
+        len: 0,
+    };
+    let _ = write!(text, "{}: {}", target, args);
+    let record = Record {
+        ticks: interrupts::ticks(),
+        level,
+        len: text.len as u8,
+        text: text.buf,
+    };
+    RING.push(&record);
+
+    for sink in [Sink::Console, Sink::Serial] {
+        let max = SINK_LEVELS[sink as usize].load(Ordering::Relaxed);
+        if Level::from_u8(max).is_none_or(|max| level > max) {
+            continue;
+        }
+        let args = format_args!("{}\n", record);
+        match sink {
+            Sink::Console => crate::vga_buffer::_try_print(args),
+            Sink::Serial => crate::serial::_try_print(args),
+        }
+    }
+}
+
+#[macro_export]
+macro_rules! log {
+    ($level:expr, $($arg:tt)*) => (
+        $crate::log::_log($level, module_path!(), format_args!($($arg)*))
+    );
+}
+
+#[macro_export]
+macro_rules! error {
+    ($($arg:tt)*) => ($crate::log!($crate::log::Level::Error, $($arg)*));
+}
+
+#[macro_export]
+macro_rules! warn {
+    ($($arg:tt)*) => ($crate::log!($crate::log::Level::Warn, $($arg)*));
+}
+
+#[macro_export]
+macro_rules! info {
+    ($($arg:tt)*) => ($crate::log!($crate::log::Level::Info, $($arg)*));
+}
+
+#[macro_export]
+macro_rules! debug {
+    ($($arg:tt)*) => ($crate::log!($crate::log::Level::Debug, $($arg)*));
+}
+
+#[macro_export]
+macro_rules! trace {
+    ($($arg:tt)*) => ($crate::log!($crate::log::Level::Trace, $($arg)*));
+}
+
+#[test_case]
+fn test_ring_buffer() {
+    set_sink_level(Sink::Console, None);
+    crate::info!("test_ring_buffer {}", 42);
+    set_sink_level(Sink::Console, Some(Level::Info));
+
+    let mut found = false;
+    for_each_record(|record| {
+        found |= record.level == Level::Info && record.text().ends_with("test_ring_buffer 42")
+    });
+    assert!(found);
+}
+
+#[test_case]
+fn test_filter() {
+    set_level(Some("moss::log"), Some(Level::Error));
+    assert!(!enabled(Level::Warn, "moss::log"));
+    assert!(enabled(Level::Warn, "moss::keyboard"));
+    set_level(Some("moss::log"), Some(Level::Trace));
+    assert!(enabled(Level::Trace, "moss::log"));
+    FILTERS.write().remove("moss::log");
+}
@@ -9,7 +9,12 @@ extern crate alloc;
 
 use bootloader::{entry_point, BootInfo};
 use core::panic::PanicInfo;
-use moss::{hlt_loop, keyboard::print_keypresses, print, println};
+use moss::{
+    hlt_loop,
+    keyboard::{dispatch_key_events, print_keypresses},
+    print, println,
+    task::Priority,
+};
 
 entry_point!(kernel_main);
 
@@ -32,8 +37,15 @@ fn kernel_main(boot_info: &'static BootInfo) -> ! {
     );
 
     print!(">");
-    let task = moss::task::Task::new(print_keypresses());
-    moss::task::add(task);
+    // input is handled first so the terminal stays responsive
+    moss::task::spawn_with_priority("key events", Priority::High, dispatch_key_events());
+    moss::task::spawn_with_priority("keypresses", Priority::High, print_keypresses());
+    moss::task::spawn("pointer", moss::mouse::track_pointer());
+    moss::task::spawn_with_priority(
+        "serial input",
+        Priority::High,
+        moss::serial::forward_input(),
+    );
 
     moss::task::run();
 }
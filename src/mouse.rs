@@ -0,0 +1,269 @@
+//! PS/2 mouse on the 8042 auxiliary port.
+
+use crate::error::{ErrorKind, Result};
+use crate::interrupts::{self, InterruptIndex};
+use crate::{bail, vga_buffer, warn};
+use core::{
+    pin::Pin,
+    task::{Context, Poll},
+};
+use crossbeam_queue::ArrayQueue;
+use futures_util::stream::{Stream, StreamExt};
+use futures_util::task::AtomicWaker;
+use spin::Once;
+use x86_64::instructions::port::Port;
+
+const DATA_PORT: u16 = 0x60;
+const STATUS_PORT: u16 = 0x64;
+const COMMAND_PORT: u16 = 0x64;
+
+const STATUS_OUTPUT_FULL: u8 = 0x01;
+const STATUS_INPUT_FULL: u8 = 0x02;
+
+const CONTROLLER_READ_CONFIG: u8 = 0x20;
+const CONTROLLER_WRITE_CONFIG: u8 = 0x60;
+const CONTROLLER_ENABLE_AUX: u8 = 0xa8;
+const CONTROLLER_WRITE_AUX: u8 = 0xd4;
+
+const CONFIG_AUX_INTERRUPT: u8 = 0x02;
+const CONFIG_AUX_CLOCK_DISABLED: u8 = 0x20;
+
+const MOUSE_SET_DEFAULTS: u8 = 0xf6;
+const MOUSE_ENABLE_REPORTING: u8 = 0xf4;
+const MOUSE_SET_SAMPLE_RATE: u8 = 0xf3;
+const MOUSE_GET_ID: u8 = 0xf2;
+const MOUSE_ACK: u8 = 0xfa;
+
+/// Device ID reported once the IntelliMouse wheel extension is enabled.
+const INTELLIMOUSE_ID: u8 = 3;
+
+/// Polls before the controller is considered unresponsive.
+const TIMEOUT: usize = 100_000;
+
+static PACKET_SIZE: Once<usize> = Once::new();
+
+static BYTE_QUEUE: Once<ArrayQueue<u8>> = Once::new();
+
+static WAKER: AtomicWaker = AtomicWaker::new();
+
+/// Enables the auxiliary port, detects a wheel and turns on data reporting.
+pub fn init() -> Result<()> {
+    controller_command(CONTROLLER_ENABLE_AUX)?;
+
+    controller_command(CONTROLLER_READ_CONFIG)?;
+    let config = read_data()?;
+    let config = (config | CONFIG_AUX_INTERRUPT) & !CONFIG_AUX_CLOCK_DISABLED;
+    controller_command(CONTROLLER_WRITE_CONFIG)?;
+    write_data(config)?;
+
+    mouse_command(MOUSE_SET_DEFAULTS)?;
+
+    // the magic sample rate sequence that switches on the IntelliMouse wheel
+    for rate in [200, 100, 80] {
+        mouse_command(MOUSE_SET_SAMPLE_RATE)?;
+        mouse_command(rate)?;
+    }
+    mouse_command(MOUSE_GET_ID)?;
+    let packet_size = if read_data()? == INTELLIMOUSE_ID {
+        4
+    } else {
+        3
+    };
+    PACKET_SIZE.call_once(|| packet_size);
+
+    mouse_command(MOUSE_ENABLE_REPORTING)?;
+
+    interrupts::unmask(InterruptIndex::Mouse);
+
+    Ok(())
+}
+
+fn wait_for(mask: u8, set: bool) -> Result<()> {
+    let mut status = Port::<u8>::new(STATUS_PORT);
+    for _ in 0..TIMEOUT {
+        if (unsafe { status.read() } & mask != 0) == set {
+            return Ok(());
+        }
+        core::hint::spin_loop();
+    }
+    bail!(ErrorKind::DeviceNotFound)
+}
+
+fn controller_command(command: u8) -> Result<()> {
+    wait_for(STATUS_INPUT_FULL, false)?;
+    unsafe { Port::new(COMMAND_PORT).write(command) };
+    Ok(())
+}
+
+fn write_data(data: u8) -> Result<()> {
+    wait_for(STATUS_INPUT_FULL, false)?;
+    unsafe { Port::new(DATA_PORT).write(data) };
+    Ok(())
+}
+
+fn read_data() -> Result<u8> {
+    wait_for(STATUS_OUTPUT_FULL, true)?;
+    Ok(unsafe { Port::new(DATA_PORT).read() })
+}
+
+/// Sends a byte to the mouse and waits for it to be acknowledged.
+fn mouse_command(command: u8) -> Result<()> {
+    controller_command(CONTROLLER_WRITE_AUX)?;
+    write_data(command)?;
+    match read_data()? {
+        MOUSE_ACK => Ok(()),
+        _ => bail!(ErrorKind::Unknown),
+    }
+}
+
+/// Called by the mouse interrupt handler with each byte the mouse sends.
+pub fn add_byte(byte: u8) {
+    if let Some(queue) = BYTE_QUEUE.get() {
+        if queue.push(byte).is_err() {
+            warn!("mouse queue full; dropping mouse input");
+        } else {
+            WAKER.wake();
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Buttons {
+    pub left: bool,
+    pub right: bool,
+    pub middle: bool,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MouseEvent {
+    /// Movement to the right.
+    pub dx: i16,
+    /// Movement upwards.
+    pub dy: i16,
+    /// Wheel movement, positive when scrolled towards the user.
+    pub wheel: i8,
+    pub buttons: Buttons,
+}
+
+impl MouseEvent {
+    fn decode(packet: &[u8]) -> Option<Self> {
+        let flags = packet[0];
+        // bit 3 is always set in the first byte; drop packets that overflowed
+        if flags & 0x08 == 0 || flags & 0xc0 != 0 {
+            return None;
+        }
+        let dx = i16::from(packet[1]) - if flags & 0x10 != 0 { 0x100 } else { 0 };
+        let dy = i16::from(packet[2]) - if flags & 0x20 != 0 { 0x100 } else { 0 };
+        // the wheel delta is a 4 bit two's complement value
+        let wheel = packet.get(3).map_or(0, |&z| ((z << 4) as i8) >> 4);
+        Some(Self {
+            dx,
+            dy,
+            wheel,
+            buttons: Buttons {
+                left: flags & 0x01 != 0,
+                right: flags & 0x02 != 0,
+                middle: flags & 0x04 != 0,
+            },
+        })
+    }
+}
+
+pub struct MouseEventStream {
+    packet: [u8; 4],
+    len: usize,
+}
+
+impl MouseEventStream {
+    pub fn new() -> MouseEventStream {
+        BYTE_QUEUE.call_once(|| ArrayQueue::new(100));
+        MouseEventStream {
+            packet: [0; 4],
+            len: 0,
+        }
+    }
+
+    /// Adds a byte to the current packet, returning the event once it is complete.
+    fn push(&mut self, byte: u8) -> Option<MouseEvent> {
+        // resynchronize on a byte that cannot start a packet
+        if self.len == 0 && byte & 0x08 == 0 {
+            return None;
+        }
+        self.packet[self.len] = byte;
+        self.len += 1;
+
+        let packet_size = *PACKET_SIZE.get().unwrap_or(&3);
+        if self.len < packet_size {
+            return None;
+        }
+        self.len = 0;
+        MouseEvent::decode(&self.packet[..packet_size])
+    }
+}
+
+impl Default for MouseEventStream {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Stream for MouseEventStream {
+    type Item = MouseEvent;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<Option<MouseEvent>> {
+        let queue = BYTE_QUEUE.get().expect("not initialized");
+
+        loop {
+            while let Some(byte) = queue.pop() {
+                if let Some(event) = self.push(byte) {
+                    return Poll::Ready(Some(event));
+                }
+            }
+            WAKER.register(cx.waker());
+
+            if queue.is_empty() {
+                return Poll::Pending;
+            }
+            WAKER.take();
+        }
+    }
+}
+
+/// Mouse movement, in counts, that moves the pointer by one text cell.
+const COUNTS_PER_COLUMN: i32 = 8;
+const COUNTS_PER_ROW: i32 = 16;
+
+/// Shows the mouse as a highlighted cell on the console; the wheel scrolls
+/// through the scrollback.
+pub async fn track_pointer() {
+    let mut events = MouseEventStream::new();
+    let (rows, columns) = vga_buffer::size();
+    let (mut x, mut y) = (0, 0);
+
+    while let Some(event) = events.next().await {
+        let max_x = columns as i32 * COUNTS_PER_COLUMN - 1;
+        let max_y = rows as i32 * COUNTS_PER_ROW - 1;
+        x = (x + i32::from(event.dx)).clamp(0, max_x);
+        y = (y - i32::from(event.dy)).clamp(0, max_y);
+        let row = (y / COUNTS_PER_ROW) as usize;
+        let col = (x / COUNTS_PER_COLUMN) as usize;
+        vga_buffer::set_pointer(Some((row, col)));
+
+        match event.wheel {
+            wheel if wheel < 0 => vga_buffer::scroll_up(3),
+            wheel if wheel > 0 => vga_buffer::scroll_down(3),
+            _ => {}
+        }
+    }
+}
+
+#[test_case]
+fn test_decode_packet() {
+    let event = MouseEvent::decode(&[0x39, 0xfe, 0x05, 0x0f]).unwrap();
+    assert_eq!(event.dx, -2);
+    assert_eq!(event.dy, -251);
+    assert_eq!(event.wheel, -1);
+    assert!(event.buttons.left);
+    assert!(!event.buttons.right);
+    assert!(MouseEvent::decode(&[0x01, 0, 0]).is_none());
+}
@@ -1,10 +1,11 @@
 use crate::error::{ErrorKind, Result};
+use crate::sync::{IrqSpinLock, IrqSpinLockGuard};
+use alloc::vec::Vec;
 use bootloader::bootinfo::MemoryMap;
 use bootloader::bootinfo::MemoryRegionType;
 use spin::once::Once;
-use spin::{Mutex, MutexGuard};
 use x86_64::structures::paging::{
-    FrameAllocator, Mapper, OffsetPageTable, Page, PageTable, PhysFrame, Size4KiB,
+    FrameAllocator, FrameDeallocator, Mapper, OffsetPageTable, Page, PageTable, PhysFrame, Size4KiB,
 };
 use x86_64::{PhysAddr, VirtAddr};
 
@@ -26,7 +27,11 @@ pub fn make_identity_mapping(
     Ok(())
 }
 
-static OFFSET_PAGE_TABLE: Once<Mutex<OffsetPageTable<'static>>> = Once::new();
+/// Taken with interrupts disabled, like `FRAME_ALLOCATOR`, so a thread
+/// preempted while mapping cannot leave the next one spinning.
+static OFFSET_PAGE_TABLE: Once<IrqSpinLock<OffsetPageTable<'static>>> = Once::new();
+
+static PHYSICAL_MEMORY_OFFSET: Once<VirtAddr> = Once::new();
 
 /// Initialize a new OffsetPageTable.
 ///
@@ -37,19 +42,50 @@ static OFFSET_PAGE_TABLE: Once<Mutex<OffsetPageTable<'static>>> = Once::new();
 /// `physical_memory_offset`. Also, this function must be only called once
 /// to avoid aliasing `&mut` references (which is undefined behavior).
 pub unsafe fn init(physical_memory_offset: VirtAddr) {
+    PHYSICAL_MEMORY_OFFSET.call_once(|| physical_memory_offset);
     OFFSET_PAGE_TABLE.call_once(|| {
         let level_4_table = unsafe { active_level_4_table(physical_memory_offset) };
 
-        Mutex::new(unsafe { OffsetPageTable::new(level_4_table, physical_memory_offset) })
+        IrqSpinLock::new(unsafe { OffsetPageTable::new(level_4_table, physical_memory_offset) })
     });
 }
 
-pub fn get_mapper() -> Result<MutexGuard<'static, OffsetPageTable<'static>>> {
+pub fn get_mapper() -> Result<IrqSpinLockGuard<'static, OffsetPageTable<'static>>> {
     Ok(OFFSET_PAGE_TABLE
         .get()
         .ok_or_else(|| ErrorKind::NotImplemented)?
         .lock())
 }
+
+static FRAME_ALLOCATOR: Once<IrqSpinLock<BootInfoFrameAllocator>> = Once::new();
+
+/// Makes `allocator` available through `get_frame_allocator` once booting no
+/// longer needs it.
+pub fn install_frame_allocator(allocator: BootInfoFrameAllocator) {
+    FRAME_ALLOCATOR.call_once(|| IrqSpinLock::new(allocator));
+}
+
+/// Lock the page table with `get_mapper` first when both are needed, and
+/// release them in the reverse order.
+pub fn get_frame_allocator() -> Result<IrqSpinLockGuard<'static, BootInfoFrameAllocator>> {
+    Ok(FRAME_ALLOCATOR
+        .get()
+        .ok_or(ErrorKind::NotImplemented)?
+        .lock())
+}
+
+/// Returns where the bootloader mapped the start of physical memory.
+pub fn physical_memory_offset() -> VirtAddr {
+    *PHYSICAL_MEMORY_OFFSET
+        .get()
+        .expect("paging not initialized")
+}
+
+/// Returns the address physical memory at `addr` is mapped to by the bootloader.
+pub fn phys_to_virt(addr: PhysAddr) -> VirtAddr {
+    physical_memory_offset() + addr.as_u64()
+}
+
 /// Returns a mutable reference to the active level 4 table.
 ///
 /// This function is unsafe because the caller must guarantee that the
@@ -72,6 +108,8 @@ pub unsafe fn active_level_4_table(physical_memory_offset: VirtAddr) -> &'static
 pub struct BootInfoFrameAllocator {
     memory_map: &'static MemoryMap,
     next: usize,
+    /// Frames handed back with `deallocate_frame`, reused before new ones.
+    free: Vec<PhysFrame>,
 }
 
 impl BootInfoFrameAllocator {
@@ -84,6 +122,7 @@ impl BootInfoFrameAllocator {
         BootInfoFrameAllocator {
             memory_map,
             next: 0,
+            free: Vec::new(),
         }
     }
 
@@ -103,8 +142,17 @@ impl BootInfoFrameAllocator {
 
 unsafe impl FrameAllocator<Size4KiB> for BootInfoFrameAllocator {
     fn allocate_frame(&mut self) -> Option<PhysFrame> {
+        if let Some(frame) = self.free.pop() {
+            return Some(frame);
+        }
         let frame = self.usable_frames().nth(self.next);
         self.next += 1;
         frame
     }
 }
+
+impl FrameDeallocator<Size4KiB> for BootInfoFrameAllocator {
+    unsafe fn deallocate_frame(&mut self, frame: PhysFrame) {
+        self.free.push(frame);
+    }
+}
@@ -0,0 +1,274 @@
+//! Anonymous pipes: a bounded byte buffer between a reading and a writing
+//! end, for threads, processes and async tasks alike.
+//!
+//! Reading returns what is buffered, or waits for data; it returns 0 once
+//! the writing end is gone and the buffer is drained. Writing waits for room
+//! and fails with `BrokenPipe` once the reading end is gone.
+
+use crate::bail;
+use crate::error::{ErrorKind, Result};
+use crate::sync::{IrqSpinLock, WaitQueue};
+use crate::thread;
+use alloc::{collections::VecDeque, sync::Arc};
+
+/// Bytes buffered at most before writers wait.
+pub const CAPACITY: usize = 4096;
+
+struct Buffer {
+    data: VecDeque<u8>,
+    /// Clones of the reading end not dropped yet.
+    readers: usize,
+    /// Clones of the writing end not dropped yet.
+    writers: usize,
+}
+
+struct Pipe {
+    /// Also taken when a killed process closes its files on the way out of
+    /// an interrupt handler.
+    buffer: IrqSpinLock<Buffer>,
+    /// Notified when data arrives or the writer closes.
+    readable: WaitQueue,
+    /// Notified when room is made or the reader closes.
+    writable: WaitQueue,
+}
+
+impl Pipe {
+    fn can_read(&self) -> bool {
+        let buffer = self.buffer.lock();
+        !buffer.data.is_empty() || buffer.writers == 0
+    }
+
+    fn can_write(&self) -> bool {
+        let buffer = self.buffer.lock();
+        buffer.data.len() < CAPACITY || buffer.readers == 0
+    }
+
+    fn take(&self, buf: &mut [u8]) -> usize {
+        let len = {
+            let mut buffer = self.buffer.lock();
+            let len = buffer.data.len().min(buf.len());
+            for (byte, data) in buf.iter_mut().zip(buffer.data.drain(..len)) {
+                *byte = data;
+            }
+            len
+        };
+        if len > 0 {
+            self.writable.notify_all();
+        }
+        len
+    }
+
+    /// Buffers what fits of `data`.
+    fn put(&self, data: &[u8]) -> Result<usize> {
+        let len = {
+            let mut buffer = self.buffer.lock();
+            if buffer.readers == 0 {
+                bail!(ErrorKind::BrokenPipe);
+            }
+            let len = (CAPACITY - buffer.data.len()).min(data.len());
+            buffer.data.extend(&data[..len]);
+            len
+        };
+        if len > 0 {
+            self.readable.notify_all();
+        }
+        Ok(len)
+    }
+}
+
+/// The reading end of a pipe. Dropping its last clone makes writes fail.
+pub struct PipeReader {
+    pipe: Arc<Pipe>,
+}
+
+/// The writing end of a pipe. Dropping its last clone ends the data for the
+/// reader.
+pub struct PipeWriter {
+    pipe: Arc<Pipe>,
+}
+
+pub fn pipe() -> (PipeReader, PipeWriter) {
+    let pipe = Arc::new(Pipe {
+        buffer: IrqSpinLock::new(Buffer {
+            data: VecDeque::new(),
+            readers: 1,
+            writers: 1,
+        }),
+        readable: WaitQueue::new(),
+        writable: WaitQueue::new(),
+    });
+    (PipeReader { pipe: pipe.clone() }, PipeWriter { pipe })
+}
+
+impl PipeReader {
+    /// Blocks until there is data or the writer is gone, and reads what fits
+    /// into `buf`. Fails with `Cancelled` if the thread is killed meanwhile.
+    pub fn read(&self, buf: &mut [u8]) -> Result<usize> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+        self.pipe
+            .readable
+            .wait_until(|| self.pipe.can_read() || thread::is_killed());
+        if !self.pipe.can_read() {
+            bail!(ErrorKind::Cancelled);
+        }
+        Ok(self.pipe.take(buf))
+    }
+
+    pub async fn read_async(&self, buf: &mut [u8]) -> usize {
+        if buf.is_empty() {
+            return 0;
+        }
+        self.pipe
+            .readable
+            .wait_until_async(|| self.pipe.can_read())
+            .await;
+        self.pipe.take(buf)
+    }
+
+    /// Reads what is buffered without waiting.
+    pub fn try_read(&self, buf: &mut [u8]) -> usize {
+        self.pipe.take(buf)
+    }
+}
+
+impl Clone for PipeReader {
+    fn clone(&self) -> Self {
+        self.pipe.buffer.lock().readers += 1;
+        Self {
+            pipe: self.pipe.clone(),
+        }
+    }
+}
+
+impl Drop for PipeReader {
+    fn drop(&mut self) {
+        self.pipe.buffer.lock().readers -= 1;
+        self.pipe.writable.notify_all();
+    }
+}
+
+impl PipeWriter {
+    /// Blocks until all of `data` is buffered. Once the reader is gone, fails
+    /// with `BrokenPipe`, unless part of `data` was written already.
+    pub fn write(&self, data: &[u8]) -> Result<usize> {
+        let mut written = 0;
+        while written < data.len() {
+            self.pipe
+                .writable
+                .wait_until(|| self.pipe.can_write() || thread::is_killed());
+            match self.pipe.put(&data[written..]) {
+                Ok(0) if thread::is_killed() => bail!(ErrorKind::Cancelled),
+                Ok(len) => written += len,
+                Err(_) if written > 0 => break,
+                Err(err) => return Err(err),
+            }
+        }
+        Ok(written)
+    }
+
+    pub async fn write_async(&self, data: &[u8]) -> Result<usize> {
+        let mut written = 0;
+        while written < data.len() {
+            self.pipe
+                .writable
+                .wait_until_async(|| self.pipe.can_write())
+                .await;
+            match self.pipe.put(&data[written..]) {
+                Ok(len) => written += len,
+                Err(_) if written > 0 => break,
+                Err(err) => return Err(err),
+            }
+        }
+        Ok(written)
+    }
+}
+
+impl Clone for PipeWriter {
+    fn clone(&self) -> Self {
+        self.pipe.buffer.lock().writers += 1;
+        Self {
+            pipe: self.pipe.clone(),
+        }
+    }
+}
+
+impl Drop for PipeWriter {
+    fn drop(&mut self) {
+        self.pipe.buffer.lock().writers -= 1;
+        self.pipe.readable.notify_all();
+    }
+}
+
+#[test_case]
+fn test_pipe_between_threads() {
+    let (reader, writer) = pipe();
+    let data: alloc::vec::Vec<u8> = (0..3 * CAPACITY).map(|i| i as u8).collect();
+    let expected = data.clone();
+    // more than fits, so the writer waits for the reader
+    let handle = thread::spawn("pipe test", thread::Priority::Normal, move || {
+        assert_eq!(writer.write(&data).unwrap(), data.len());
+    })
+    .unwrap();
+
+    let mut received = alloc::vec::Vec::new();
+    let mut buf = [0; 1000];
+    loop {
+        let len = reader.read(&mut buf).unwrap();
+        if len == 0 {
+            break;
+        }
+        received.extend_from_slice(&buf[..len]);
+    }
+    handle.join();
+    assert_eq!(received, expected);
+
+    let (reader, writer) = pipe();
+    drop(reader);
+    assert!(matches!(
+        writer.write(b"x"),
+        Err(err) if matches!(err.kind(), ErrorKind::BrokenPipe)
+    ));
+
+    // open until the last clone is dropped
+    let (reader, writer) = pipe();
+    let second = writer.clone();
+    drop(writer);
+    assert_eq!(second.write(b"x").unwrap(), 1);
+    drop(second);
+    let mut buf = [0; 2];
+    assert_eq!(reader.read(&mut buf).unwrap(), 1);
+    assert_eq!(reader.read(&mut buf).unwrap(), 0);
+}
+
+#[test_case]
+fn test_pipe_between_tasks() {
+    use crate::task::{executor, spawn};
+
+    let (reader, writer) = pipe();
+    let received = Arc::new(spin::Mutex::new(alloc::vec::Vec::new()));
+    let producer = spawn("pipe test", async move {
+        for chunk in [b"ab", b"cd"] {
+            writer.write_async(chunk).await.unwrap();
+        }
+    });
+    let consumer = spawn("pipe test", {
+        let received = received.clone();
+        async move {
+            let mut buf = [0; 4];
+            loop {
+                let len = reader.read_async(&mut buf).await;
+                if len == 0 {
+                    break;
+                }
+                received.lock().extend_from_slice(&buf[..len]);
+            }
+        }
+    });
+    while !consumer.is_finished() {
+        executor::run_ready_tasks();
+    }
+    assert!(producer.is_finished());
+    assert_eq!(*received.lock(), b"abcd");
+}
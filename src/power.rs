@@ -0,0 +1,116 @@
+//! Powering off and resetting the machine.
+
+use crate::acpi::{self, AddressSpace, Fadt};
+use crate::{fs, hlt_loop, info, paging, warn};
+use x86_64::instructions::port::Port;
+use x86_64::structures::DescriptorTablePointer;
+use x86_64::{PhysAddr, VirtAddr};
+
+const PM1_SCI_EN: u16 = 1 << 0;
+const PM1_SLP_TYP_SHIFT: u16 = 10;
+const PM1_SLP_EN: u16 = 1 << 13;
+
+const KEYBOARD_STATUS_PORT: u16 = 0x64;
+const KEYBOARD_INPUT_FULL: u8 = 0x02;
+const KEYBOARD_PULSE_RESET: u8 = 0xfe;
+
+/// Polls before giving up on a device.
+const TIMEOUT: usize = 100_000;
+
+/// Turns the machine off through the ACPI S5 sleep state, halting if that fails.
+pub fn shutdown() -> ! {
+    info!("shutting down");
+    fs::sync();
+    x86_64::instructions::interrupts::disable();
+
+    match (Fadt::find(), acpi::s5_sleep_types()) {
+        (Some(fadt), Some((slp_typa, slp_typb))) => unsafe {
+            enable_acpi(&fadt);
+            let mut pm1a = Port::<u16>::new(fadt.pm1a_control as u16);
+            pm1a.write(u16::from(slp_typa) << PM1_SLP_TYP_SHIFT | PM1_SLP_EN);
+            if fadt.pm1b_control != 0 {
+                let mut pm1b = Port::<u16>::new(fadt.pm1b_control as u16);
+                pm1b.write(u16::from(slp_typb) << PM1_SLP_TYP_SHIFT | PM1_SLP_EN);
+            }
+        },
+        _ => warn!("no ACPI S5 sleep state"),
+    }
+
+    warn!("shutdown failed; it is now safe to turn off the machine");
+    hlt_loop()
+}
+
+/// Hands power management over from the firmware to the OS, if it is not
+/// already in ACPI mode.
+unsafe fn enable_acpi(fadt: &Fadt) {
+    let mut pm1a = Port::<u16>::new(fadt.pm1a_control as u16);
+    if unsafe { pm1a.read() } & PM1_SCI_EN != 0 || fadt.smi_command == 0 || fadt.acpi_enable == 0 {
+        return;
+    }
+    unsafe { Port::<u8>::new(fadt.smi_command as u16).write(fadt.acpi_enable) };
+    for _ in 0..TIMEOUT {
+        if unsafe { pm1a.read() } & PM1_SCI_EN != 0 {
+            return;
+        }
+        core::hint::spin_loop();
+    }
+}
+
+/// Resets the machine with the ACPI reset register, then the keyboard
+/// controller, and finally a triple fault.
+pub fn reboot() -> ! {
+    info!("rebooting");
+    fs::sync();
+    x86_64::instructions::interrupts::disable();
+
+    if let Some(fadt) = Fadt::find() {
+        acpi_reset(&fadt);
+    }
+    keyboard_controller_reset();
+    triple_fault()
+}
+
+fn acpi_reset(fadt: &Fadt) {
+    let Some(register) = fadt.reset_register else {
+        return;
+    };
+    if fadt.flags & acpi::FADT_RESET_REG_SUP == 0 {
+        return;
+    }
+    match register.space {
+        AddressSpace::Io => unsafe {
+            Port::<u8>::new(register.address as u16).write(fadt.reset_value)
+        },
+        AddressSpace::Memory => {
+            let addr = paging::phys_to_virt(PhysAddr::new(register.address));
+            unsafe { core::ptr::write_volatile(addr.as_mut_ptr::<u8>(), fadt.reset_value) };
+        }
+        _ => {}
+    }
+}
+
+/// Pulses the CPU reset line through the 8042 output port.
+fn keyboard_controller_reset() {
+    let mut status = Port::<u8>::new(KEYBOARD_STATUS_PORT);
+    for _ in 0..TIMEOUT {
+        if unsafe { status.read() } & KEYBOARD_INPUT_FULL == 0 {
+            break;
+        }
+        core::hint::spin_loop();
+    }
+    unsafe { status.write(KEYBOARD_PULSE_RESET) };
+    for _ in 0..TIMEOUT {
+        core::hint::spin_loop();
+    }
+}
+
+/// Raises an exception with an empty IDT, which the CPU answers with a reset.
+fn triple_fault() -> ! {
+    let idt = DescriptorTablePointer {
+        limit: 0,
+        base: VirtAddr::new(0),
+    };
+    unsafe { x86_64::instructions::tables::lidt(&idt) };
+    x86_64::instructions::interrupts::int3();
+    hlt_loop()
+}
@@ -0,0 +1,369 @@
+//! Processes: a user program with its own address space and open files,
+//! running in a thread.
+//!
+//! A process that exits stays in the table with its exit code until it is
+//! waited for, by its parent or, for processes without one such as the
+//! shell's jobs, by anyone. Orphans, whose parent exited first, are removed
+//! as soon as they exit since nobody waits for them.
+
+use crate::bail;
+use crate::error::{ErrorKind, Result};
+use crate::exec::{self, Program};
+use crate::sync::{IrqSpinLock, Mutex as SleepMutex, WaitQueue};
+use crate::syscall::FileTable;
+use crate::thread::{self, Priority, ThreadId};
+use crate::usermode::AddressSpace;
+use alloc::{collections::BTreeMap, string::String, sync::Arc, vec::Vec};
+use core::fmt;
+use core::sync::atomic::{AtomicU64, Ordering};
+
+/// Exit code of a process ended by `kill`.
+pub const EXIT_KILLED: i32 = 137;
+/// Exit code of a process ended by a fault in user mode.
+pub const EXIT_FAULT: i32 = 139;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Pid(u64);
+
+impl Pid {
+    fn new() -> Self {
+        static NEXT_ID: AtomicU64 = AtomicU64::new(1);
+        Pid(NEXT_ID.fetch_add(1, Ordering::Relaxed))
+    }
+
+    pub fn from_u64(pid: u64) -> Self {
+        Pid(pid)
+    }
+
+    pub fn as_u64(&self) -> u64 {
+        self.0
+    }
+}
+
+impl fmt::Display for Pid {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum State {
+    Running,
+    Exited(i32),
+}
+
+impl State {
+    pub fn name(&self) -> &'static str {
+        match self {
+            State::Running => "running",
+            State::Exited(_) => "exited",
+        }
+    }
+}
+
+struct Process {
+    parent: Option<Pid>,
+    name: String,
+    /// Released when the process exits.
+    address_space: Option<Arc<AddressSpace>>,
+    files: Option<Arc<SleepMutex<FileTable>>>,
+    thread: ThreadId,
+    state: State,
+    /// The parent exited before this process.
+    orphan: bool,
+}
+
+/// A snapshot of a process, for listing.
+#[derive(Debug, Clone)]
+pub struct ProcessInfo {
+    pub pid: Pid,
+    pub parent: Option<Pid>,
+    pub name: String,
+    pub state: State,
+}
+
+/// Also taken when a killed process leaves the timer interrupt.
+static PROCESSES: IrqSpinLock<BTreeMap<Pid, Process>> = IrqSpinLock::new(BTreeMap::new());
+/// Notified whenever a process exits.
+static EXITED: WaitQueue = WaitQueue::new();
+
+/// Starts the executable at `path` as a child of `parent`, with `args` as
+/// its arguments. It inherits the descriptors of `parent` not closed on exec,
+/// or has standard input, output and error on the console without one.
+pub fn spawn(parent: Option<Pid>, path: &str, args: &[&str]) -> Result<Pid> {
+    let inherited = parent.and_then(files_of);
+    let files = match inherited {
+        Some(files) => files.lock().inherit(),
+        None => FileTable::with_console(),
+    };
+    spawn_with_files(parent, path, args, files)
+}
+
+/// Like `spawn`, with the descriptors given.
+pub fn spawn_with_files(
+    parent: Option<Pid>,
+    path: &str,
+    args: &[&str],
+    files: FileTable,
+) -> Result<Pid> {
+    let program = exec::load_path(path, args)?;
+    create(parent, path, program, files)
+}
+
+/// Starts a process running the loaded `program`.
+pub fn create(parent: Option<Pid>, name: &str, program: Program, files: FileTable) -> Result<Pid> {
+    let pid = Pid::new();
+    let space = Arc::new(program.space);
+    let handle = thread::spawn_user(
+        name,
+        Priority::Normal,
+        Some(pid),
+        space.clone(),
+        program.entry,
+        program.stack_pointer,
+    )?;
+    let id = handle.id();
+    // the exit code is kept in the table instead
+    drop(handle);
+    let process = Process {
+        parent,
+        name: name.into(),
+        address_space: Some(space),
+        files: Some(Arc::new(SleepMutex::new(files))),
+        thread: id,
+        state: State::Running,
+        orphan: false,
+    };
+    PROCESSES.lock().insert(pid, process);
+    // the thread starts blocked, since it is looked up in the table on its
+    // first system call
+    thread::unblock(id);
+    Ok(pid)
+}
+
+/// Blocks until `pid`, a child of `parent`, has exited, reaps it and returns
+/// its exit code. Fails with `Cancelled` if the caller is killed meanwhile.
+pub fn wait(parent: Option<Pid>, pid: Pid) -> Result<i32> {
+    check_child(parent, pid)?;
+    EXITED.wait_until(|| has_exited(pid) || thread::is_killed());
+    match reap(pid) {
+        Some(code) => Ok(code),
+        None if thread::is_killed() => bail!(ErrorKind::Cancelled),
+        None => bail!(ErrorKind::NoChild),
+    }
+}
+
+/// Completes once `pid`, a child of `parent`, has exited, reaps it and
+/// returns its exit code.
+pub async fn wait_async(parent: Option<Pid>, pid: Pid) -> Result<i32> {
+    check_child(parent, pid)?;
+    EXITED.wait_until_async(|| has_exited(pid)).await;
+    reap(pid).ok_or(ErrorKind::NoChild.into())
+}
+
+fn check_child(parent: Option<Pid>, pid: Pid) -> Result<()> {
+    match PROCESSES.lock().get(&pid) {
+        Some(process) if process.parent == parent => Ok(()),
+        _ => bail!(ErrorKind::NoChild),
+    }
+}
+
+fn has_exited(pid: Pid) -> bool {
+    PROCESSES
+        .lock()
+        .get(&pid)
+        .is_none_or(|process| matches!(process.state, State::Exited(_)))
+}
+
+fn reap(pid: Pid) -> Option<i32> {
+    let mut processes = PROCESSES.lock();
+    match processes.get(&pid)?.state {
+        State::Exited(code) => {
+            processes.remove(&pid);
+            Some(code)
+        }
+        State::Running => None,
+    }
+}
+
+/// Ends `pid`, once its thread is about to run in user mode again or leaves
+/// an interruptible wait.
+pub fn kill(pid: Pid) -> Result<()> {
+    let thread = match PROCESSES.lock().get(&pid) {
+        Some(process) if process.state == State::Running => process.thread,
+        _ => bail!(ErrorKind::NoSuchProcess),
+    };
+    thread::kill(thread);
+    Ok(())
+}
+
+/// Like `kill`, but fails with `PermissionDenied` unless `pid` is a child of
+/// `ancestor` or one of their descendants.
+pub fn kill_descendant(ancestor: Pid, pid: Pid) -> Result<()> {
+    let thread = {
+        let processes = PROCESSES.lock();
+        let process = match processes.get(&pid) {
+            Some(process) if process.state == State::Running => process,
+            _ => bail!(ErrorKind::NoSuchProcess),
+        };
+        let mut parent = process.parent;
+        loop {
+            match parent {
+                Some(parent) if parent == ancestor => break,
+                Some(other) => parent = processes.get(&other).and_then(|other| other.parent),
+                None => bail!(ErrorKind::PermissionDenied),
+            }
+        }
+        process.thread
+    };
+    thread::kill(thread);
+    Ok(())
+}
+
+/// Ends the current process with `code`.
+pub fn exit_current(code: i32) -> ! {
+    if let Some(pid) = current() {
+        exit(pid, code);
+    }
+    thread::exit_with(code)
+}
+
+fn exit(pid: Pid, code: i32) {
+    let released = {
+        let mut processes = PROCESSES.lock();
+        let Some(process) = processes.get_mut(&pid) else {
+            return;
+        };
+        process.state = State::Exited(code);
+        let released = (process.address_space.take(), process.files.take());
+        if process.orphan {
+            processes.remove(&pid);
+        }
+
+        // children nobody can wait for any more
+        processes.retain(|_, child| child.parent != Some(pid) || child.state == State::Running);
+        for child in processes.values_mut() {
+            if child.parent == Some(pid) {
+                child.parent = None;
+                child.orphan = true;
+            }
+        }
+        released
+    };
+    // may free page tables and close files, so not under the lock
+    drop(released);
+    EXITED.notify_all();
+}
+
+/// The process of the current thread.
+pub fn current() -> Option<Pid> {
+    thread::current_process()
+}
+
+/// The open files of the current process.
+pub fn files() -> Option<Arc<SleepMutex<FileTable>>> {
+    files_of(current()?)
+}
+
+fn files_of(pid: Pid) -> Option<Arc<SleepMutex<FileTable>>> {
+    PROCESSES.lock().get(&pid)?.files.clone()
+}
+
+pub fn processes() -> Vec<ProcessInfo> {
+    PROCESSES
+        .lock()
+        .iter()
+        .map(|(&pid, process)| ProcessInfo {
+            pid,
+            parent: process.parent,
+            name: process.name.clone(),
+            state: process.state,
+        })
+        .collect()
+}
+
+#[test_case]
+fn test_spawn_and_wait() {
+    exec::install_programs();
+    let pid = spawn(None, "echo", &["echo", "from", "process"]).unwrap();
+    assert_eq!(wait(None, pid).unwrap(), 0);
+    // reaped
+    assert!(wait(None, pid).is_err());
+    assert!(spawn(None, "no such program", &[]).is_err());
+}
+
+#[cfg(test)]
+fn spin_program() -> Program {
+    use crate::usermode::{Protection, USER_START};
+    use x86_64::VirtAddr;
+
+    // jmp $
+    let space = AddressSpace::new().unwrap();
+    let code = VirtAddr::new(USER_START);
+    space.map(code, 4096, Protection::READ_EXECUTE).unwrap();
+    space.write(code, &[0xeb, 0xfe]).unwrap();
+    Program {
+        space,
+        entry: code,
+        stack_pointer: VirtAddr::new(USER_START + 4096),
+    }
+}
+
+#[test_case]
+fn test_kill() {
+    let pid = create(None, "spin", spin_program(), FileTable::new()).unwrap();
+    thread::sleep(crate::time::Duration::from_millis(20));
+    assert_eq!(
+        processes().iter().find(|p| p.pid == pid).unwrap().state,
+        State::Running
+    );
+    kill(pid).unwrap();
+    assert_eq!(wait(None, pid).unwrap(), EXIT_KILLED);
+    assert!(kill(pid).is_err());
+}
+
+#[test_case]
+fn test_kill_descendant() {
+    let parent = create(None, "spin", spin_program(), FileTable::new()).unwrap();
+    let child = create(Some(parent), "spin", spin_program(), FileTable::new()).unwrap();
+    let denied = kill_descendant(child, parent).unwrap_err();
+    assert!(matches!(denied.kind(), ErrorKind::PermissionDenied));
+    let missing = kill_descendant(parent, Pid::from_u64(u64::MAX)).unwrap_err();
+    assert!(matches!(missing.kind(), ErrorKind::NoSuchProcess));
+
+    kill_descendant(parent, child).unwrap();
+    assert_eq!(wait(Some(parent), child).unwrap(), EXIT_KILLED);
+    kill(parent).unwrap();
+    assert_eq!(wait(None, parent).unwrap(), EXIT_KILLED);
+}
+
+#[test_case]
+fn test_pipeline() {
+    use crate::pipe;
+    use crate::syscall::OpenFile;
+
+    exec::install_programs();
+    let (cat_input, echo_output) = pipe::pipe();
+    let (output, cat_output) = pipe::pipe();
+    let mut echo_files = FileTable::with_console();
+    echo_files.set(1, OpenFile::PipeWrite(echo_output)).unwrap();
+    let mut cat_files = FileTable::with_console();
+    cat_files.set(0, OpenFile::PipeRead(cat_input)).unwrap();
+    cat_files.set(1, OpenFile::PipeWrite(cat_output)).unwrap();
+    let echo = spawn_with_files(None, "echo", &["echo", "through", "cat"], echo_files).unwrap();
+    let cat = spawn_with_files(None, "cat", &["cat"], cat_files).unwrap();
+
+    // ends once both exited and closed their ends
+    let mut received = Vec::new();
+    let mut buf = [0; 64];
+    loop {
+        let len = output.read(&mut buf).unwrap();
+        if len == 0 {
+            break;
+        }
+        received.extend_from_slice(&buf[..len]);
+    }
+    assert_eq!(received, b"through cat\n");
+    assert_eq!(wait(None, echo).unwrap(), 0);
+    assert_eq!(wait(None, cat).unwrap(), 0);
+}
@@ -0,0 +1,230 @@
+//! CMOS real-time clock and wall-clock time.
+//!
+//! The RTC is only read once, at boot; after that the wall clock advances with
+//! the monotonic clock in `time`.
+
+use crate::acpi::Fadt;
+use crate::time::{self, Duration};
+use core::fmt;
+use spin::Once;
+use x86_64::instructions::port::Port;
+
+const CMOS_ADDRESS: u16 = 0x70;
+const CMOS_DATA: u16 = 0x71;
+/// Keeps NMIs disabled while a register is selected.
+const CMOS_NMI_DISABLE: u8 = 0x80;
+
+const RTC_SECONDS: u8 = 0x00;
+const RTC_MINUTES: u8 = 0x02;
+const RTC_HOURS: u8 = 0x04;
+const RTC_DAY: u8 = 0x07;
+const RTC_MONTH: u8 = 0x08;
+const RTC_YEAR: u8 = 0x09;
+const RTC_STATUS_A: u8 = 0x0a;
+const RTC_STATUS_B: u8 = 0x0b;
+
+const STATUS_A_UPDATE_IN_PROGRESS: u8 = 0x80;
+const STATUS_B_24_HOUR: u8 = 0x02;
+const STATUS_B_BINARY: u8 = 0x04;
+const HOUR_PM: u8 = 0x80;
+
+const SECONDS_PER_DAY: u64 = 86_400;
+
+/// Unix time when the monotonic clock read zero.
+static BOOT_TIME: Once<Duration> = Once::new();
+
+/// A UTC calendar date and time.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct DateTime {
+    pub year: u16,
+    pub month: u8,
+    pub day: u8,
+    pub hour: u8,
+    pub minute: u8,
+    pub second: u8,
+}
+
+impl DateTime {
+    /// Seconds since 1970-01-01 00:00:00 UTC, or 0 for earlier dates and
+    /// ones without a valid month and day, as a confused RTC may report.
+    pub fn to_unix(&self) -> u64 {
+        if self.year < 1970 || !(1..=12).contains(&self.month) || self.day == 0 {
+            return 0;
+        }
+        // days_from_civil from Howard Hinnant's date algorithms
+        let (month, day) = (u64::from(self.month), u64::from(self.day));
+        let year = u64::from(self.year) - u64::from(month <= 2);
+        let era = year / 400;
+        let year_of_era = year - era * 400;
+        let day_of_year = (153 * ((month + 9) % 12) + 2) / 5 + day - 1;
+        let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+        let days = era * 146_097 + day_of_era - 719_468;
+        days * SECONDS_PER_DAY
+            + u64::from(self.hour) * 3600
+            + u64::from(self.minute) * 60
+            + u64::from(self.second)
+    }
+
+    pub fn from_unix(seconds: u64) -> Self {
+        let days = seconds / SECONDS_PER_DAY + 719_468;
+        let time = seconds % SECONDS_PER_DAY;
+        let era = days / 146_097;
+        let day_of_era = days - era * 146_097;
+        let year_of_era =
+            (day_of_era - day_of_era / 1460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+        let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+        let mp = (5 * day_of_year + 2) / 153;
+        let day = day_of_year - (153 * mp + 2) / 5 + 1;
+        let month = if mp < 10 { mp + 3 } else { mp - 9 };
+        let year = year_of_era + era * 400 + u64::from(month <= 2);
+        Self {
+            year: year as u16,
+            month: month as u8,
+            day: day as u8,
+            hour: (time / 3600) as u8,
+            minute: (time / 60 % 60) as u8,
+            second: (time % 60) as u8,
+        }
+    }
+}
+
+impl fmt::Display for DateTime {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{:04}-{:02}-{:02} {:02}:{:02}:{:02} UTC",
+            self.year, self.month, self.day, self.hour, self.minute, self.second
+        )
+    }
+}
+
+fn cmos_read(register: u8) -> u8 {
+    unsafe {
+        Port::new(CMOS_ADDRESS).write(CMOS_NMI_DISABLE | register);
+        Port::new(CMOS_DATA).read()
+    }
+}
+
+fn bcd_to_binary(value: u8) -> u8 {
+    (value >> 4) * 10 + (value & 0x0f)
+}
+
+/// Raw clock registers, with the century register last if there is one.
+type Registers = [u8; 7];
+
+fn read_registers(century: Option<u8>) -> Registers {
+    while cmos_read(RTC_STATUS_A) & STATUS_A_UPDATE_IN_PROGRESS != 0 {
+        core::hint::spin_loop();
+    }
+    [
+        cmos_read(RTC_SECONDS),
+        cmos_read(RTC_MINUTES),
+        cmos_read(RTC_HOURS),
+        cmos_read(RTC_DAY),
+        cmos_read(RTC_MONTH),
+        cmos_read(RTC_YEAR),
+        century.map_or(0, cmos_read),
+    ]
+}
+
+fn decode(registers: Registers, status_b: u8, has_century: bool) -> DateTime {
+    let [second, minute, hour, day, month, year, century] = registers;
+    let pm = hour & HOUR_PM != 0;
+    let convert = |value: u8| {
+        if status_b & STATUS_B_BINARY != 0 {
+            value
+        } else {
+            bcd_to_binary(value)
+        }
+    };
+
+    let mut hour = convert(hour & !HOUR_PM);
+    if status_b & STATUS_B_24_HOUR == 0 {
+        // 12 AM is midnight and 12 PM is noon
+        hour = hour % 12 + if pm { 12 } else { 0 };
+    }
+    let year = u16::from(convert(year));
+    let year = if has_century {
+        u16::from(convert(century)) * 100 + year
+    } else {
+        2000 + year
+    };
+
+    DateTime {
+        year,
+        month: convert(month),
+        day: convert(day),
+        hour,
+        minute: convert(minute),
+        second: convert(second),
+    }
+}
+
+/// Reads the date and time from the CMOS clock.
+pub fn read_rtc() -> DateTime {
+    let century = Fadt::find()
+        .map(|fadt| fadt.century)
+        .filter(|&index| index != 0);
+
+    // the registers may change while they are read, so read until two reads agree
+    let mut registers = read_registers(century);
+    loop {
+        let again = read_registers(century);
+        if again == registers {
+            break;
+        }
+        registers = again;
+    }
+    decode(registers, cmos_read(RTC_STATUS_B), century.is_some())
+}
+
+/// Sets the wall clock from the RTC.
+pub fn init() {
+    let rtc = Duration::from_secs(read_rtc().to_unix());
+    BOOT_TIME.call_once(|| rtc.saturating_sub(time::uptime()));
+}
+
+/// Time since the Unix epoch.
+pub fn unix_time() -> Duration {
+    let boot = BOOT_TIME.get().copied().unwrap_or_default();
+    boot + time::uptime()
+}
+
+/// The current date and time.
+pub fn now() -> DateTime {
+    DateTime::from_unix(unix_time().as_secs())
+}
+
+#[test_case]
+fn test_unix_time_conversion() {
+    let date = DateTime {
+        year: 2024,
+        month: 2,
+        day: 29,
+        hour: 13,
+        minute: 37,
+        second: 5,
+    };
+    assert_eq!(date.to_unix(), 1_709_213_825);
+    assert_eq!(DateTime::from_unix(1_709_213_825), date);
+    assert_eq!(DateTime::from_unix(0).year, 1970);
+
+    let garbage = DateTime {
+        year: 0,
+        month: 0,
+        day: 0,
+        ..date
+    };
+    assert_eq!(garbage.to_unix(), 0);
+    assert_eq!(DateTime { year: 1969, ..date }.to_unix(), 0);
+}
+
+#[test_case]
+fn test_decode_bcd_12_hour() {
+    // 12:30:45 AM on 1999-12-31, in BCD with a century register
+    let date = decode([0x45, 0x30, 0x12, 0x31, 0x12, 0x99, 0x19], 0, true);
+    assert_eq!(date.to_unix(), 946_600_245);
+    assert_eq!((date.year, date.hour, date.minute), (1999, 0, 30));
+    let pm = decode([0, 0, HOUR_PM | 0x01, 0x01, 0x01, 0x24, 0], 0, false);
+    assert_eq!((pm.year, pm.hour), (2024, 13));
+}
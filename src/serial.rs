@@ -1,4 +1,13 @@
-use spin::{Lazy, Mutex};
+use crate::{terminal, warn};
+use core::{
+    pin::Pin,
+    task::{Context, Poll},
+};
+use crossbeam_queue::ArrayQueue;
+use futures_util::stream::{Stream, StreamExt};
+use futures_util::task::AtomicWaker;
+use pc_keyboard::DecodedKey;
+use spin::{Lazy, Mutex, Once};
 use uart_16550::SerialPort;
 
 static SERIAL1: Lazy<Mutex<SerialPort>> = Lazy::new(|| {
@@ -7,18 +16,115 @@ static SERIAL1: Lazy<Mutex<SerialPort>> = Lazy::new(|| {
     Mutex::new(serial_port)
 });
 
+static INPUT_QUEUE: Once<ArrayQueue<u8>> = Once::new();
+
+static WAKER: AtomicWaker = AtomicWaker::new();
+
+/// Called by the serial interrupt handler when the port has received a byte.
+pub fn receive_interrupt() {
+    let byte = SERIAL1.lock().receive();
+    if let Some(queue) = INPUT_QUEUE.get() {
+        if queue.push(byte).is_err() {
+            warn!("serial input queue full; dropping input");
+        } else {
+            WAKER.wake();
+        }
+    }
+}
+
+/// Bytes received on the serial port.
+pub struct SerialStream {
+    _private: (),
+}
+
+impl SerialStream {
+    pub fn new() -> SerialStream {
+        INPUT_QUEUE.call_once(|| ArrayQueue::new(100));
+        SerialStream { _private: () }
+    }
+}
+
+impl Default for SerialStream {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Stream for SerialStream {
+    type Item = u8;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Option<u8>> {
+        let queue = INPUT_QUEUE.get().expect("not initialized");
+
+        if let Some(byte) = queue.pop() {
+            return Poll::Ready(Some(byte));
+        }
+        WAKER.register(cx.waker());
+
+        match queue.pop() {
+            Some(byte) => {
+                WAKER.take();
+                Poll::Ready(Some(byte))
+            }
+            None => Poll::Pending,
+        }
+    }
+}
+
+/// Feeds input from the serial port to the terminal, so the shell can be used
+/// from the host.
+pub async fn forward_input() {
+    let mut bytes = SerialStream::new();
+
+    while let Some(byte) = bytes.next().await {
+        let key = match byte {
+            b'\r' => '\n',
+            // terminals send DEL for the backspace key
+            0x7f => '\u{8}',
+            byte if byte.is_ascii() => char::from(byte),
+            _ => continue,
+        };
+        terminal::push_key(DecodedKey::Unicode(key));
+    }
+}
+
+/// Writes bytes to the port unchanged, so escape sequences and control bytes
+/// reach the host terminal as they were printed.
+struct RawWriter<'a>(&'a mut SerialPort);
+
+impl core::fmt::Write for RawWriter<'_> {
+    fn write_str(&mut self, s: &str) -> core::fmt::Result {
+        for byte in s.bytes() {
+            self.0.send_raw(byte);
+        }
+        Ok(())
+    }
+}
+
 #[doc(hidden)]
 pub fn _print(args: ::core::fmt::Arguments) {
     use core::fmt::Write;
     use x86_64::instructions::interrupts;
     interrupts::without_interrupts(|| {
-        SERIAL1
-            .lock()
+        RawWriter(&mut SERIAL1.lock())
             .write_fmt(args)
             .expect("Printing to serial failed");
     })
 }
 
+/// Like `_print`, but gives up instead of spinning if the port is busy, so it
+/// is safe to call from interrupt handlers.
+#[doc(hidden)]
+pub fn _try_print(args: ::core::fmt::Arguments) {
+    use core::fmt::Write;
+    use x86_64::instructions::interrupts;
+    interrupts::without_interrupts(|| {
+        if let Some(mut serial) = SERIAL1.try_lock() {
+            let _ = RawWriter(&mut serial).write_fmt(args);
+        }
+    })
+}
+
 /// Prints to the host through the serial interface.
 #[macro_export]
 macro_rules! serial_print {
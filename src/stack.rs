@@ -0,0 +1,222 @@
+//! Kernel stacks with guard pages.
+//!
+//! Stacks are mapped in their own virtual region, each with an unmapped page
+//! below it, so overflowing one raises a page fault instead of overwriting
+//! whatever lies below.
+
+use crate::error::{ErrorKind, Result};
+use crate::paging;
+use alloc::collections::BTreeMap;
+use core::ops::Range;
+use spin::Mutex;
+use x86_64::instructions::interrupts::without_interrupts;
+use x86_64::structures::paging::{
+    FrameAllocator, FrameDeallocator, Mapper, Page, PageTableFlags, Size4KiB,
+};
+use x86_64::VirtAddr;
+
+pub const STACK_REGION_START: u64 = 0x_5555_0000_0000;
+pub const STACK_REGION_SIZE: u64 = 1 << 30; // 1 GiB
+
+const PAGE_SIZE: u64 = 4096;
+
+/// Virtual address ranges handed out for stacks, guard page included.
+struct StackRegion {
+    next: u64,
+    /// Start address to page count, of ranges in use.
+    used: BTreeMap<u64, u64>,
+    /// Start address to page count, of ranges returned by dropped stacks.
+    /// Adjacent ranges are merged.
+    free: BTreeMap<u64, u64>,
+}
+
+static REGION: Mutex<StackRegion> = Mutex::new(StackRegion {
+    next: STACK_REGION_START,
+    used: BTreeMap::new(),
+    free: BTreeMap::new(),
+});
+
+impl StackRegion {
+    fn reserve(&mut self, pages: u64) -> Result<u64> {
+        // the first free range large enough, with the rest left free
+        let reused = self.free.iter().find(|&(_, &free)| free >= pages);
+        let start = match reused.map(|(&start, &free)| (start, free)) {
+            Some((start, free)) => {
+                self.free.remove(&start);
+                if free > pages {
+                    self.free.insert(start + pages * PAGE_SIZE, free - pages);
+                }
+                start
+            }
+            None => {
+                let start = self.next;
+                if start + pages * PAGE_SIZE > STACK_REGION_START + STACK_REGION_SIZE {
+                    return Err(ErrorKind::NoEnoughMemory.into());
+                }
+                self.next += pages * PAGE_SIZE;
+                start
+            }
+        };
+        self.used.insert(start, pages);
+        Ok(start)
+    }
+
+    fn release(&mut self, start: u64) {
+        let Some(mut pages) = self.used.remove(&start) else {
+            return;
+        };
+        let mut start = start;
+        if let Some(next) = self.free.remove(&(start + pages * PAGE_SIZE)) {
+            pages += next;
+        }
+        if let Some((&before, &before_pages)) = self.free.range(..start).next_back() {
+            if before + before_pages * PAGE_SIZE == start {
+                start = before;
+                pages += before_pages;
+            }
+        }
+        self.free.insert(start, pages);
+    }
+
+    /// The range in use that contains `addr`.
+    fn find(&self, addr: u64) -> Option<Range<u64>> {
+        let (&start, &pages) = self.used.range(..=addr).next_back()?;
+        let range = start..start + pages * PAGE_SIZE;
+        range.contains(&addr).then_some(range)
+    }
+}
+
+/// A mapped stack with an unmapped guard page below it.
+#[derive(Debug)]
+pub struct KernelStack {
+    /// Start of the guard page.
+    start: VirtAddr,
+    /// Mapped pages, not counting the guard page.
+    pages: u64,
+}
+
+impl KernelStack {
+    /// Maps a stack of at least `size` bytes.
+    pub fn new(size: usize) -> Result<Self> {
+        let pages = (size as u64).div_ceil(PAGE_SIZE).max(1);
+        let start = without_interrupts(|| REGION.lock().reserve(pages + 1))?;
+        let mut stack = KernelStack {
+            start: VirtAddr::new(start),
+            pages: 0,
+        };
+        // on failure, dropping `stack` unmaps what was mapped so far
+        stack.map(pages)?;
+        unsafe {
+            core::ptr::write_bytes(stack.bottom().as_mut_ptr::<u8>(), 0, stack.size());
+        }
+        Ok(stack)
+    }
+
+    fn map(&mut self, pages: u64) -> Result<()> {
+        let mut mapper = paging::get_mapper()?;
+        let mut allocator = paging::get_frame_allocator()?;
+        let flags = PageTableFlags::PRESENT | PageTableFlags::WRITABLE;
+        let first = Page::<Size4KiB>::containing_address(self.bottom());
+        for i in 0..pages {
+            let frame = allocator
+                .allocate_frame()
+                .ok_or(ErrorKind::NoEnoughMemory)?;
+            let page = first + i;
+            let mapped = unsafe { mapper.map_to(page, frame, flags, &mut *allocator) };
+            match mapped {
+                Ok(flush) => flush.flush(),
+                Err(err) => {
+                    unsafe { allocator.deallocate_frame(frame) };
+                    return Err(err.into());
+                }
+            }
+            self.pages += 1;
+        }
+        Ok(())
+    }
+
+    /// Lowest usable address.
+    pub fn bottom(&self) -> VirtAddr {
+        self.start + PAGE_SIZE
+    }
+
+    /// One past the highest usable address, where the stack starts.
+    pub fn top(&self) -> VirtAddr {
+        self.bottom() + self.size()
+    }
+
+    pub fn size(&self) -> usize {
+        (self.pages * PAGE_SIZE) as usize
+    }
+
+    /// The guard page and the stack above it.
+    pub fn contains(&self, addr: VirtAddr) -> bool {
+        (self.start..self.top()).contains(&addr)
+    }
+}
+
+impl Drop for KernelStack {
+    fn drop(&mut self) {
+        // the allocator is released before the page table
+        if let Ok(mut mapper) = paging::get_mapper() {
+            if let Ok(mut allocator) = paging::get_frame_allocator() {
+                let first = Page::<Size4KiB>::containing_address(self.bottom());
+                for page in (0..self.pages).map(|i| first + i) {
+                    if let Ok((frame, flush)) = mapper.unmap(page) {
+                        flush.flush();
+                        unsafe { allocator.deallocate_frame(frame) };
+                    }
+                }
+            }
+        }
+        without_interrupts(|| REGION.lock().release(self.start.as_u64()));
+    }
+}
+
+/// Whether `addr` lies in the guard page of a stack. Called from the double
+/// fault handler, so it gives up if the region is locked.
+pub fn is_guard_page(addr: VirtAddr) -> bool {
+    let addr = addr.as_u64();
+    REGION
+        .try_lock()
+        .and_then(|region| region.find(addr))
+        .is_some_and(|range| addr < range.start + PAGE_SIZE)
+}
+
+#[test_case]
+fn test_kernel_stack() {
+    let stack = KernelStack::new(3 * 4096 + 1).unwrap();
+    assert_eq!(stack.size(), 4 * 4096);
+    unsafe {
+        stack.bottom().as_mut_ptr::<u8>().write_volatile(1);
+        (stack.top() - 1u64).as_mut_ptr::<u8>().write_volatile(1);
+    }
+    assert!(is_guard_page(stack.bottom() - 1u64));
+    assert!(!is_guard_page(stack.bottom()));
+
+    let start = stack.start;
+    drop(stack);
+    assert!(!is_guard_page(start));
+    // the range is reused for a stack of the same size, merged with any free
+    // range right below it
+    let again = KernelStack::new(4 * 4096).unwrap();
+    assert!(again.contains(start));
+}
+
+#[test_case]
+fn test_region_reuse() {
+    let mut region = StackRegion {
+        next: STACK_REGION_START,
+        used: BTreeMap::new(),
+        free: BTreeMap::new(),
+    };
+    let first = region.reserve(4).unwrap();
+    let second = region.reserve(2).unwrap();
+    let third = region.reserve(1).unwrap();
+    region.release(first);
+    region.release(second);
+    // the two freed ranges are merged, then split again
+    assert_eq!(region.reserve(5).unwrap(), first);
+    assert_eq!(region.reserve(1).unwrap(), first + 5 * PAGE_SIZE);
+    assert_eq!(region.reserve(1).unwrap(), third + PAGE_SIZE);
+}
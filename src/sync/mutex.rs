@@ -0,0 +1,189 @@
+use super::WaitQueue;
+use core::cell::UnsafeCell;
+use core::ops::{Deref, DerefMut};
+use core::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+
+/// A mutual exclusion lock that blocks the waiting thread.
+pub struct Mutex<T: ?Sized> {
+    locked: AtomicBool,
+    waiters: WaitQueue,
+    data: UnsafeCell<T>,
+}
+
+unsafe impl<T: ?Sized + Send> Send for Mutex<T> {}
+unsafe impl<T: ?Sized + Send> Sync for Mutex<T> {}
+
+impl<T> Mutex<T> {
+    pub const fn new(value: T) -> Self {
+        Self {
+            locked: AtomicBool::new(false),
+            waiters: WaitQueue::new(),
+            data: UnsafeCell::new(value),
+        }
+    }
+
+    pub fn into_inner(self) -> T {
+        self.data.into_inner()
+    }
+}
+
+impl<T: ?Sized> Mutex<T> {
+    fn acquire(&self) -> bool {
+        self.locked
+            .compare_exchange(false, true, Ordering::Acquire, Ordering::Relaxed)
+            .is_ok()
+    }
+
+    pub fn lock(&self) -> MutexGuard<'_, T> {
+        self.waiters.wait_until(|| self.acquire());
+        MutexGuard { mutex: self }
+    }
+
+    /// Like `lock`, but waits as an async task instead of blocking the
+    /// thread.
+    pub async fn lock_async(&self) -> MutexGuard<'_, T> {
+        self.waiters.wait_until_async(|| self.acquire()).await;
+        MutexGuard { mutex: self }
+    }
+
+    pub fn try_lock(&self) -> Option<MutexGuard<'_, T>> {
+        self.acquire().then(|| MutexGuard { mutex: self })
+    }
+
+    pub fn is_locked(&self) -> bool {
+        self.locked.load(Ordering::Relaxed)
+    }
+
+    pub fn get_mut(&mut self) -> &mut T {
+        self.data.get_mut()
+    }
+}
+
+impl<T: Default> Default for Mutex<T> {
+    fn default() -> Self {
+        Self::new(T::default())
+    }
+}
+
+pub struct MutexGuard<'a, T: ?Sized> {
+    mutex: &'a Mutex<T>,
+}
+
+impl<T: ?Sized> Deref for MutexGuard<'_, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        unsafe { &*self.mutex.data.get() }
+    }
+}
+
+impl<T: ?Sized> DerefMut for MutexGuard<'_, T> {
+    fn deref_mut(&mut self) -> &mut T {
+        unsafe { &mut *self.mutex.data.get() }
+    }
+}
+
+impl<T: ?Sized> Drop for MutexGuard<'_, T> {
+    fn drop(&mut self) {
+        self.mutex.locked.store(false, Ordering::Release);
+        self.mutex.waiters.notify_one();
+    }
+}
+
+/// A condition variable for use with `Mutex`.
+#[derive(Default)]
+pub struct Condvar {
+    /// Bumped by every notification, so a waiter can tell it was notified.
+    sequence: AtomicU64,
+    waiters: WaitQueue,
+}
+
+impl Condvar {
+    pub const fn new() -> Self {
+        Self {
+            sequence: AtomicU64::new(0),
+            waiters: WaitQueue::new(),
+        }
+    }
+
+    /// Unlocks `guard` and blocks until notified, then locks it again. May
+    /// return without a notification.
+    pub fn wait<'a, T: ?Sized>(&self, guard: MutexGuard<'a, T>) -> MutexGuard<'a, T> {
+        let mutex = guard.mutex;
+        let sequence = self.sequence.load(Ordering::Acquire);
+        drop(guard);
+        self.waiters
+            .wait_until(|| self.sequence.load(Ordering::Acquire) != sequence);
+        mutex.lock()
+    }
+
+    /// Waits until `condition` returns false.
+    pub fn wait_while<'a, T: ?Sized>(
+        &self,
+        mut guard: MutexGuard<'a, T>,
+        mut condition: impl FnMut(&mut T) -> bool,
+    ) -> MutexGuard<'a, T> {
+        while condition(&mut guard) {
+            guard = self.wait(guard);
+        }
+        guard
+    }
+
+    pub fn notify_one(&self) {
+        self.sequence.fetch_add(1, Ordering::Release);
+        self.waiters.notify_one();
+    }
+
+    pub fn notify_all(&self) {
+        self.sequence.fetch_add(1, Ordering::Release);
+        self.waiters.notify_all();
+    }
+}
+
+#[test_case]
+fn test_mutex_threads() {
+    use crate::thread::{self, Priority};
+    use alloc::{sync::Arc, vec::Vec};
+
+    let counter = Arc::new(Mutex::new(0));
+    let handles: Vec<_> = (0..4)
+        .map(|_| {
+            let counter = counter.clone();
+            thread::spawn("mutex test", Priority::Normal, move || {
+                for _ in 0..100 {
+                    let mut count = counter.lock();
+                    let value = *count;
+                    // give the other threads a chance to see the lock held
+                    thread::yield_now();
+                    *count = value + 1;
+                }
+            })
+            .unwrap()
+        })
+        .collect();
+    for handle in handles {
+        handle.join();
+    }
+    assert_eq!(*counter.lock(), 400);
+}
+
+#[test_case]
+fn test_condvar() {
+    use crate::thread::{self, Priority};
+    use alloc::sync::Arc;
+
+    let pair = Arc::new((Mutex::new(false), Condvar::new()));
+    let waiter = {
+        let pair = pair.clone();
+        thread::spawn("condvar test", Priority::Normal, move || {
+            let (ready, condvar) = &*pair;
+            let ready = condvar.wait_while(ready.lock(), |ready| !*ready);
+            assert!(*ready);
+        })
+        .unwrap()
+    };
+    thread::yield_now();
+    *pair.0.lock() = true;
+    pair.1.notify_all();
+    waiter.join();
+}
@@ -0,0 +1,66 @@
+use super::WaitQueue;
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+/// A counting semaphore that blocks the waiting thread.
+pub struct Semaphore {
+    permits: AtomicUsize,
+    waiters: WaitQueue,
+}
+
+impl Semaphore {
+    pub const fn new(permits: usize) -> Self {
+        Self {
+            permits: AtomicUsize::new(permits),
+            waiters: WaitQueue::new(),
+        }
+    }
+
+    pub fn try_acquire(&self) -> bool {
+        self.permits
+            .fetch_update(Ordering::Acquire, Ordering::Relaxed, |permits| {
+                permits.checked_sub(1)
+            })
+            .is_ok()
+    }
+
+    /// Takes a permit, blocking until one is available.
+    pub fn acquire(&self) {
+        self.waiters.wait_until(|| self.try_acquire());
+    }
+
+    /// Takes a permit, waiting asynchronously until one is available.
+    pub async fn acquire_async(&self) {
+        self.waiters.wait_until_async(|| self.try_acquire()).await
+    }
+
+    pub fn release(&self) {
+        self.permits.fetch_add(1, Ordering::Release);
+        self.waiters.notify_one();
+    }
+
+    pub fn available(&self) -> usize {
+        self.permits.load(Ordering::Relaxed)
+    }
+}
+
+#[test_case]
+fn test_semaphore() {
+    use crate::thread::{self, Priority};
+    use alloc::sync::Arc;
+
+    let semaphore = Arc::new(Semaphore::new(1));
+    semaphore.acquire();
+    assert!(!semaphore.try_acquire());
+
+    let releaser = {
+        let semaphore = semaphore.clone();
+        thread::spawn("semaphore test", Priority::Normal, move || {
+            semaphore.release()
+        })
+        .unwrap()
+    };
+    // blocks until the thread has run
+    semaphore.acquire();
+    releaser.join();
+    assert_eq!(semaphore.available(), 0);
+}
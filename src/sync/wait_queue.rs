@@ -0,0 +1,180 @@
+use super::IrqSpinLock;
+use crate::thread::{self, ThreadId};
+use alloc::collections::VecDeque;
+use core::future::Future;
+use core::pin::Pin;
+use core::task::{Context, Poll, Waker};
+
+enum Waiter {
+    Thread(ThreadId),
+    Task(Waker),
+}
+
+impl Waiter {
+    fn wake(self) {
+        match self {
+            Waiter::Thread(id) => thread::unblock(id),
+            Waiter::Task(waker) => waker.wake(),
+        }
+    }
+}
+
+/// Threads and async tasks waiting for a condition to become true.
+///
+/// Waiters recheck their condition whenever they are woken, so notifying more
+/// often than needed is harmless, but the condition must be made true before
+/// notifying.
+pub struct WaitQueue {
+    waiters: IrqSpinLock<VecDeque<Waiter>>,
+}
+
+impl Default for WaitQueue {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl WaitQueue {
+    pub const fn new() -> Self {
+        Self {
+            waiters: IrqSpinLock::new(VecDeque::new()),
+        }
+    }
+
+    /// Blocks the current thread until `condition` returns true.
+    pub fn wait_until(&self, mut condition: impl FnMut() -> bool) {
+        if condition() {
+            return;
+        }
+        let Some(id) = thread::current() else {
+            // before the scheduler runs there is nothing to switch to
+            while !condition() {
+                core::hint::spin_loop();
+            }
+            return;
+        };
+        loop {
+            {
+                let mut waiters = self.waiters.lock();
+                if !waiters
+                    .iter()
+                    .any(|waiter| matches!(waiter, Waiter::Thread(waiting) if *waiting == id))
+                {
+                    waiters.push_back(Waiter::Thread(id));
+                }
+            }
+            // checked again after queueing, so a notify in between is not lost
+            if condition() {
+                self.waiters
+                    .lock()
+                    .retain(|waiter| !matches!(waiter, Waiter::Thread(waiting) if *waiting == id));
+                return;
+            }
+            thread::block_current();
+        }
+    }
+
+    /// Polls `condition`, registering the task to be woken if it is false.
+    pub fn poll_until(&self, cx: &mut Context, mut condition: impl FnMut() -> bool) -> Poll<()> {
+        if condition() {
+            return Poll::Ready(());
+        }
+        {
+            let mut waiters = self.waiters.lock();
+            if !waiters
+                .iter()
+                .any(|waiter| matches!(waiter, Waiter::Task(waker) if waker.will_wake(cx.waker())))
+            {
+                waiters.push_back(Waiter::Task(cx.waker().clone()));
+            }
+        }
+        if condition() {
+            self.waiters.lock().retain(
+                |waiter| !matches!(waiter, Waiter::Task(waker) if waker.will_wake(cx.waker())),
+            );
+            return Poll::Ready(());
+        }
+        Poll::Pending
+    }
+
+    /// Completes once `condition` returns true.
+    pub fn wait_until_async<F: FnMut() -> bool>(&self, condition: F) -> WaitUntil<'_, F> {
+        WaitUntil {
+            queue: self,
+            condition,
+            waker: None,
+        }
+    }
+
+    /// Takes the task woken by `waker` out of the queue, returning whether it
+    /// was still there.
+    fn remove_task(&self, waker: &Waker) -> bool {
+        let mut waiters = self.waiters.lock();
+        let position = waiters
+            .iter()
+            .position(|waiter| matches!(waiter, Waiter::Task(queued) if queued.will_wake(waker)));
+        position.and_then(|i| waiters.remove(i)).is_some()
+    }
+
+    /// Wakes the longest waiting thread or task.
+    pub fn notify_one(&self) {
+        let waiter = self.waiters.lock().pop_front();
+        if let Some(waiter) = waiter {
+            waiter.wake();
+        }
+    }
+
+    pub fn notify_all(&self) {
+        let waiters = core::mem::take(&mut *self.waiters.lock());
+        for waiter in waiters {
+            waiter.wake();
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.waiters.lock().is_empty()
+    }
+}
+
+/// Future returned by `WaitQueue::wait_until_async`.
+///
+/// Dropping it before it completes takes its waker out of the queue, or, if
+/// a `notify_one` already took it, passes the notification on to the next
+/// waiter instead of losing it.
+pub struct WaitUntil<'a, F> {
+    queue: &'a WaitQueue,
+    condition: F,
+    /// Queued by the last poll that returned `Pending`.
+    waker: Option<Waker>,
+}
+
+// the condition is never pinned
+impl<F> Unpin for WaitUntil<'_, F> {}
+
+impl<F: FnMut() -> bool> Future for WaitUntil<'_, F> {
+    type Output = ();
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context) -> Poll<()> {
+        let this = self.get_mut();
+        let poll = this.queue.poll_until(cx, &mut this.condition);
+        if let Some(old) = this.waker.take() {
+            if poll.is_ready() || !old.will_wake(cx.waker()) {
+                this.queue.remove_task(&old);
+            }
+        }
+        if poll.is_pending() {
+            this.waker = Some(cx.waker().clone());
+        }
+        poll
+    }
+}
+
+impl<F> Drop for WaitUntil<'_, F> {
+    fn drop(&mut self) {
+        if let Some(waker) = self.waker.take() {
+            if !self.queue.remove_task(&waker) {
+                self.queue.notify_one();
+            }
+        }
+    }
+}
@@ -0,0 +1,324 @@
+//! Open files and the file descriptor tables that refer to them.
+//!
+//! An `OpenFile` is shared by the descriptors duplicated from the one that
+//! opened it, in the same table or in the tables of spawned processes, so
+//! they share its offset too. A pipe end is closed when the last descriptor
+//! referring to it is.
+
+use crate::error::{ErrorKind, Result};
+use crate::pipe::{PipeReader, PipeWriter};
+use crate::sync::Mutex as SleepMutex;
+use crate::{bail, fs, print, serial_print, terminal};
+use alloc::{string::String, sync::Arc, vec::Vec};
+
+/// Descriptors a table holds at most.
+pub const MAX_FILES: usize = 256;
+
+/// What a file descriptor refers to.
+pub enum OpenFile {
+    /// Reads lines typed at the keyboard, writes to the screen.
+    Console,
+    /// Reads lines like `Console`, since serial input reaches the terminal
+    /// too, and writes to the serial port.
+    Serial,
+    /// A file in `fs`, with the position the next read or write starts at.
+    File {
+        name: String,
+        offset: usize,
+    },
+    /// A directory listing, read a line per entry.
+    Directory {
+        entries: Vec<String>,
+        next: usize,
+    },
+    PipeRead(PipeReader),
+    PipeWrite(PipeWriter),
+}
+
+impl OpenFile {
+    pub fn read(&mut self, buf: &mut [u8]) -> Result<usize> {
+        match self {
+            OpenFile::Console | OpenFile::Serial => Ok(terminal::read_console(buf)),
+            OpenFile::File { name, offset } => {
+                let read = fs::handle_file_mut(
+                    |file| file.map(|file| file.read_at(*offset, buf)),
+                    fs::Path::from_str(name),
+                )
+                .map_err(|_| ErrorKind::NotFound)?;
+                *offset += read;
+                Ok(read)
+            }
+            OpenFile::Directory { entries, next } => {
+                let mut read = 0;
+                while let Some(entry) = entries.get(*next) {
+                    // whole entries only; one that does not fit is kept for
+                    // the next read, which fails if it cannot fit either
+                    let end = read + entry.len() + 1;
+                    if end > buf.len() {
+                        if read == 0 && !buf.is_empty() {
+                            bail!(ErrorKind::InvalidArgument);
+                        }
+                        break;
+                    }
+                    buf[read..end - 1].copy_from_slice(entry.as_bytes());
+                    buf[end - 1] = b'\n';
+                    read = end;
+                    *next += 1;
+                }
+                Ok(read)
+            }
+            OpenFile::PipeRead(reader) => reader.read(buf),
+            OpenFile::PipeWrite(_) => bail!(ErrorKind::BadFileDescriptor),
+        }
+    }
+
+    pub fn write(&mut self, data: &[u8]) -> Result<usize> {
+        match self {
+            OpenFile::Console => {
+                print!("{}", String::from_utf8_lossy(data));
+                Ok(data.len())
+            }
+            OpenFile::Serial => {
+                serial_print!("{}", String::from_utf8_lossy(data));
+                Ok(data.len())
+            }
+            OpenFile::File { name, offset } => {
+                fs::handle_file_mut(
+                    |file| file.map(|file| file.write_at(*offset, data)),
+                    fs::Path::from_str(name),
+                )
+                .map_err(|_| ErrorKind::NotFound)?;
+                *offset += data.len();
+                Ok(data.len())
+            }
+            OpenFile::Directory { .. } => bail!(ErrorKind::InvalidArgument),
+            OpenFile::PipeWrite(writer) => writer.write(data),
+            OpenFile::PipeRead(_) => bail!(ErrorKind::BadFileDescriptor),
+        }
+    }
+}
+
+/// Reads from `file` like `OpenFile::read`. The console and pipes, which may
+/// block for long, are read with `file` unlocked, since a thread waiting for
+/// the lock could not be woken by `kill`.
+pub fn read(file: &SleepMutex<OpenFile>, buf: &mut [u8]) -> Result<usize> {
+    let mut open = file.lock();
+    match &*open {
+        OpenFile::Console | OpenFile::Serial => {
+            drop(open);
+            Ok(terminal::read_console(buf))
+        }
+        OpenFile::PipeRead(reader) => {
+            let reader = reader.clone();
+            drop(open);
+            reader.read(buf)
+        }
+        _ => open.read(buf),
+    }
+}
+
+/// Writes to `file` like `OpenFile::write`, with `file` unlocked for pipes
+/// as in `read`.
+pub fn write(file: &SleepMutex<OpenFile>, data: &[u8]) -> Result<usize> {
+    let mut open = file.lock();
+    match &*open {
+        OpenFile::PipeWrite(writer) => {
+            let writer = writer.clone();
+            drop(open);
+            writer.write(data)
+        }
+        _ => open.write(data),
+    }
+}
+
+#[derive(Clone)]
+struct Descriptor {
+    file: Arc<SleepMutex<OpenFile>>,
+    /// Not passed on to spawned programs.
+    close_on_exec: bool,
+}
+
+/// File descriptors of a process, indexes into `files`.
+#[derive(Default)]
+pub struct FileTable {
+    files: Vec<Option<Descriptor>>,
+}
+
+impl FileTable {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// A table with standard input, output and error on the console.
+    pub fn with_console() -> Self {
+        let console = || {
+            Some(Descriptor {
+                file: Arc::new(SleepMutex::new(OpenFile::Console)),
+                close_on_exec: false,
+            })
+        };
+        Self {
+            files: alloc::vec![console(), console(), console()],
+        }
+    }
+
+    /// The descriptors a spawned program starts with: all but those closed
+    /// on exec, referring to the same open files.
+    pub fn inherit(&self) -> Self {
+        let files = self
+            .files
+            .iter()
+            .map(|fd| fd.clone().filter(|fd| !fd.close_on_exec))
+            .collect();
+        Self { files }
+    }
+
+    /// Adds `file` at the lowest free descriptor and returns the descriptor.
+    pub fn insert(&mut self, file: OpenFile, close_on_exec: bool) -> Result<usize> {
+        self.insert_shared(Arc::new(SleepMutex::new(file)), close_on_exec)
+    }
+
+    fn insert_shared(
+        &mut self,
+        file: Arc<SleepMutex<OpenFile>>,
+        close_on_exec: bool,
+    ) -> Result<usize> {
+        let descriptor = Some(Descriptor {
+            file,
+            close_on_exec,
+        });
+        match self.files.iter().position(Option::is_none) {
+            Some(fd) => {
+                self.files[fd] = descriptor;
+                Ok(fd)
+            }
+            None if self.files.len() < MAX_FILES => {
+                self.files.push(descriptor);
+                Ok(self.files.len() - 1)
+            }
+            None => bail!(ErrorKind::Full),
+        }
+    }
+
+    fn descriptor(&mut self, fd: usize) -> Result<&mut Descriptor> {
+        self.files
+            .get_mut(fd)
+            .and_then(Option::as_mut)
+            .ok_or(ErrorKind::BadFileDescriptor.into())
+    }
+
+    /// The open file `fd` refers to, which can be used once the table is
+    /// unlocked again.
+    pub fn get(&mut self, fd: usize) -> Result<Arc<SleepMutex<OpenFile>>> {
+        Ok(self.descriptor(fd)?.file.clone())
+    }
+
+    pub fn remove(&mut self, fd: usize) -> Result<Arc<SleepMutex<OpenFile>>> {
+        self.descriptor(fd)?;
+        Ok(self.files[fd].take().unwrap().file)
+    }
+
+    /// Makes the lowest free descriptor refer to the open file of `fd`.
+    pub fn dup(&mut self, fd: usize) -> Result<usize> {
+        let file = self.get(fd)?;
+        self.insert_shared(file, false)
+    }
+
+    /// Makes `new` refer to the open file of `fd`, closing what `new` referred
+    /// to before.
+    pub fn dup2(&mut self, fd: usize, new: usize) -> Result<usize> {
+        let file = self.get(fd)?;
+        if fd != new {
+            self.place(new, file)?;
+        }
+        Ok(new)
+    }
+
+    /// Makes `fd` refer to `file`, closing what it referred to before.
+    pub fn set(&mut self, fd: usize, file: OpenFile) -> Result<()> {
+        self.place(fd, Arc::new(SleepMutex::new(file)))
+    }
+
+    fn place(&mut self, fd: usize, file: Arc<SleepMutex<OpenFile>>) -> Result<()> {
+        if fd >= MAX_FILES {
+            bail!(ErrorKind::BadFileDescriptor);
+        }
+        if self.files.len() <= fd {
+            self.files.resize(fd + 1, None);
+        }
+        self.files[fd] = Some(Descriptor {
+            file,
+            close_on_exec: false,
+        });
+        Ok(())
+    }
+
+    pub fn close_on_exec(&mut self, fd: usize) -> Result<bool> {
+        Ok(self.descriptor(fd)?.close_on_exec)
+    }
+
+    pub fn set_close_on_exec(&mut self, fd: usize, close_on_exec: bool) -> Result<()> {
+        self.descriptor(fd)?.close_on_exec = close_on_exec;
+        Ok(())
+    }
+}
+
+#[test_case]
+fn test_file_table() {
+    let mut files = FileTable::with_console();
+    assert_eq!(files.insert(OpenFile::Console, false).unwrap(), 3);
+    files.remove(1).unwrap();
+    assert!(files.get(1).is_err());
+    // freed descriptors are reused, lowest first
+    assert_eq!(files.insert(OpenFile::Console, false).unwrap(), 1);
+    assert!(files.remove(7).is_err());
+}
+
+#[test_case]
+fn test_dup_and_inherit() {
+    let mut files = FileTable::new();
+    let file = OpenFile::File {
+        name: "dup_test".into(),
+        offset: 0,
+    };
+    let fd = files.insert(file, false).unwrap();
+    assert_eq!(files.dup(fd).unwrap(), 1);
+    assert_eq!(files.dup2(fd, 5).unwrap(), 5);
+    assert!(files.get(4).is_err());
+    // duplicates share the offset
+    if let OpenFile::File { offset, .. } = &mut *files.get(5).unwrap().lock() {
+        *offset = 3;
+    }
+    assert!(matches!(
+        *files.get(1).unwrap().lock(),
+        OpenFile::File { offset: 3, .. }
+    ));
+    assert!(files.dup2(9, 2).is_err());
+    assert!(files.dup2(fd, MAX_FILES).is_err());
+
+    files.set_close_on_exec(1, true).unwrap();
+    assert!(!files.close_on_exec(5).unwrap());
+    let mut inherited = files.inherit();
+    assert!(inherited.get(1).is_err());
+    assert!(Arc::ptr_eq(
+        &inherited.get(5).unwrap(),
+        &files.get(0).unwrap()
+    ));
+}
+
+#[test_case]
+fn test_read_directory() {
+    let mut dir = OpenFile::Directory {
+        entries: alloc::vec!["a".into(), "long name".into()],
+        next: 0,
+    };
+    let mut buf = [0; 4];
+    assert_eq!(dir.read(&mut buf).unwrap(), 2);
+    assert_eq!(&buf[..2], b"a\n");
+    // too long for the buffer, and not skipped
+    assert!(dir.read(&mut buf).is_err());
+    let mut buf = [0; 16];
+    assert_eq!(dir.read(&mut buf).unwrap(), 10);
+    assert_eq!(&buf[..10], b"long name\n");
+    assert_eq!(dir.read(&mut buf).unwrap(), 0);
+}
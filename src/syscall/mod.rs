@@ -0,0 +1,744 @@
+//! System calls from user mode.
+//!
+//! Programs enter the kernel with `syscall`, or `int 0x80` where that is
+//! unavailable, passing the system call number in `rax` and the arguments in
+//! `rdi`, `rsi`, `rdx`, `r10`, `r8` and `r9`. The result comes back in `rax`,
+//! a negated `errno` value on failure. Both entry points switch to the kernel
+//! stack of the calling thread and enable interrupts, so a system call can
+//! block and be preempted like any kernel code.
+
+use crate::error::{ErrorKind, Result};
+use crate::process::{self, Pid};
+use crate::thread;
+use crate::usermode::{AddressSpace, Protection};
+use crate::{fs, gdt, pipe, sync, time};
+use alloc::{sync::Arc, vec::Vec};
+use core::arch::asm;
+use x86_64::registers::model_specific::{Efer, EferFlags, LStar, SFMask, Star};
+use x86_64::registers::rflags::RFlags;
+use x86_64::VirtAddr;
+
+mod file;
+
+pub use file::{FileTable, OpenFile};
+
+pub const SYS_READ: u64 = 0;
+pub const SYS_WRITE: u64 = 1;
+pub const SYS_OPEN: u64 = 2;
+pub const SYS_CLOSE: u64 = 3;
+pub const SYS_EXIT: u64 = 4;
+pub const SYS_GETPID: u64 = 5;
+pub const SYS_SLEEP: u64 = 6;
+pub const SYS_SBRK: u64 = 7;
+pub const SYS_MMAP: u64 = 8;
+pub const SYS_SPAWN: u64 = 9;
+pub const SYS_WAIT: u64 = 10;
+pub const SYS_KILL: u64 = 11;
+pub const SYS_DUP: u64 = 12;
+pub const SYS_DUP2: u64 = 13;
+pub const SYS_FCNTL: u64 = 14;
+pub const SYS_PIPE: u64 = 15;
+
+/// `open` flag creating the file if it does not exist.
+pub const O_CREAT: u64 = 1;
+/// `open` and `pipe` flag closing the descriptors in spawned programs.
+pub const O_CLOEXEC: u64 = 2;
+/// `fcntl` command returning the descriptor flags.
+pub const F_GETFD: u64 = 1;
+/// `fcntl` command setting the descriptor flags.
+pub const F_SETFD: u64 = 2;
+/// Descriptor flag closing it in spawned programs.
+pub const FD_CLOEXEC: u64 = 1;
+/// `mmap` protection flag making the memory writable.
+pub const PROT_WRITE: u64 = 2;
+/// `mmap` protection flag making the memory executable.
+pub const PROT_EXEC: u64 = 4;
+
+pub const EPERM: i64 = 1;
+pub const ENOENT: i64 = 2;
+pub const ESRCH: i64 = 3;
+pub const EBADF: i64 = 9;
+pub const ECHILD: i64 = 10;
+pub const ENOMEM: i64 = 12;
+pub const EFAULT: i64 = 14;
+pub const EEXIST: i64 = 17;
+pub const EINVAL: i64 = 22;
+pub const EMFILE: i64 = 24;
+pub const EPIPE: i64 = 32;
+pub const ENOSYS: i64 = 38;
+
+type Handler = fn([u64; 6]) -> Result<u64>;
+
+/// Handlers indexed by system call number.
+static SYSCALLS: [Handler; 16] = [
+    sys_read, sys_write, sys_open, sys_close, sys_exit, sys_getpid, sys_sleep, sys_sbrk, sys_mmap,
+    sys_spawn, sys_wait, sys_kill, sys_dup, sys_dup2, sys_fcntl, sys_pipe,
+];
+
+/// Enables `syscall` and `sysret`.
+pub fn init() {
+    let selectors = gdt::selectors();
+    Star::write(
+        selectors.user_code_selector,
+        selectors.user_data_selector,
+        selectors.code_selector,
+        selectors.stack_selector,
+    )
+    .expect("segments out of order for sysret");
+    LStar::write(VirtAddr::new(syscall_entry as *const u8 as u64));
+    // entered with interrupts off, until the kernel stack is in place
+    SFMask::write(
+        RFlags::INTERRUPT_FLAG
+            | RFlags::TRAP_FLAG
+            | RFlags::DIRECTION_FLAG
+            | RFlags::ALIGNMENT_CHECK,
+    );
+    unsafe { Efer::update(|flags| *flags |= EferFlags::SYSTEM_CALL_EXTENSIONS) };
+}
+
+/// Runs system call `number` and returns the value for `rax`.
+pub fn dispatch(number: u64, args: [u64; 6]) -> u64 {
+    let result = match SYSCALLS.get(number as usize) {
+        Some(handler) => handler(args),
+        None => Err(ErrorKind::NotImplemented.into()),
+    };
+    match result {
+        Ok(value) => value,
+        Err(err) => (-errno(err.kind())) as u64,
+    }
+}
+
+fn errno(kind: &ErrorKind) -> i64 {
+    match kind {
+        ErrorKind::NotFound => ENOENT,
+        ErrorKind::BadFileDescriptor => EBADF,
+        ErrorKind::NoChild => ECHILD,
+        ErrorKind::NoSuchProcess => ESRCH,
+        ErrorKind::PermissionDenied => EPERM,
+        ErrorKind::NoEnoughMemory | ErrorKind::MapTo(_) => ENOMEM,
+        ErrorKind::BadAddress => EFAULT,
+        ErrorKind::AlreadyAllocated => EEXIST,
+        ErrorKind::Full => EMFILE,
+        ErrorKind::BrokenPipe => EPIPE,
+        ErrorKind::NotImplemented => ENOSYS,
+        _ => EINVAL,
+    }
+}
+
+/// Registers saved by both entry points, lowest address first.
+#[derive(Debug)]
+#[repr(C)]
+struct SyscallRegisters {
+    r9: u64,
+    r8: u64,
+    r10: u64,
+    rdx: u64,
+    rsi: u64,
+    rdi: u64,
+    rax: u64,
+}
+
+extern "C" fn syscall_handler(registers: &mut SyscallRegisters) {
+    let args = [
+        registers.rdi,
+        registers.rsi,
+        registers.rdx,
+        registers.r10,
+        registers.r8,
+        registers.r9,
+    ];
+    registers.rax = dispatch(registers.rax, args);
+    // killed while in the kernel, so not returning to user mode
+    if thread::is_killed() {
+        process::exit_current(process::EXIT_KILLED);
+    }
+}
+
+/// User stack pointer, kept while switching to the kernel stack. Interrupts
+/// are off until it is pushed.
+static mut USER_RSP: u64 = 0;
+
+#[naked]
+extern "C" fn syscall_entry() {
+    unsafe {
+        asm!(
+            "mov [rip + {user_rsp}], rsp",
+            "mov rsp, [rip + {kernel_stack}]",
+            "push qword ptr [rip + {user_rsp}]",
+            "push r11", // RFLAGS
+            "push rcx", // RIP
+            "push rax",
+            "push rdi",
+            "push rsi",
+            "push rdx",
+            "push r10",
+            "push r8",
+            "push r9",
+            //
+            "mov rdi, rsp",
+            "sti",
+            "call {handler}",
+            "cli",
+            //
+            "pop r9",
+            "pop r8",
+            "pop r10",
+            "pop rdx",
+            "pop rsi",
+            "pop rdi",
+            "pop rax",
+            "pop rcx",
+            "pop r11",
+            "pop rsp",
+            "sysretq",
+            user_rsp = sym USER_RSP,
+            kernel_stack = sym gdt::KERNEL_STACK,
+            handler = sym syscall_handler,
+            options(noreturn)
+        );
+    }
+}
+
+/// The `int 0x80` handler, which takes the same registers as `syscall`.
+#[naked]
+pub(crate) extern "C" fn int80_entry() {
+    unsafe {
+        asm!(
+            "push rax",
+            "push rdi",
+            "push rsi",
+            "push rdx",
+            "push r10",
+            "push r8",
+            "push r9",
+            //
+            // the frame and 7 registers leave the stack 16-byte aligned
+            "mov rdi, rsp",
+            "sti",
+            "call {handler}",
+            "cli",
+            //
+            "pop r9",
+            "pop r8",
+            "pop r10",
+            "pop rdx",
+            "pop rsi",
+            "pop rdi",
+            "pop rax",
+            "iretq",
+            handler = sym syscall_handler,
+            options(noreturn)
+        );
+    }
+}
+
+fn address_space() -> Result<Arc<AddressSpace>> {
+    Ok(thread::address_space().ok_or(ErrorKind::InvalidArgument)?)
+}
+
+fn files() -> Result<Arc<sync::Mutex<FileTable>>> {
+    Ok(process::files().ok_or(ErrorKind::InvalidArgument)?)
+}
+
+/// The user memory at `addr`, once the current address space is known to
+/// map it. The thread keeps the address space alive while it runs.
+fn user_slice<'a>(addr: u64, len: u64) -> Result<&'a [u8]> {
+    let start = VirtAddr::try_new(addr).map_err(|_| ErrorKind::BadAddress)?;
+    if !address_space()?.is_accessible(start, len as usize, false) {
+        return Err(ErrorKind::BadAddress.into());
+    }
+    Ok(unsafe { core::slice::from_raw_parts(start.as_ptr(), len as usize) })
+}
+
+fn user_slice_mut<'a>(addr: u64, len: u64) -> Result<&'a mut [u8]> {
+    let start = VirtAddr::try_new(addr).map_err(|_| ErrorKind::BadAddress)?;
+    if !address_space()?.is_accessible(start, len as usize, true) {
+        return Err(ErrorKind::BadAddress.into());
+    }
+    Ok(unsafe { core::slice::from_raw_parts_mut(start.as_mut_ptr(), len as usize) })
+}
+
+/// The open file `fd` refers to. The table is not kept locked, so other
+/// descriptors can be used while this one blocks.
+fn open_file(fd: u64) -> Result<Arc<sync::Mutex<OpenFile>>> {
+    files()?.lock().get(fd as usize)
+}
+
+fn sys_read([fd, buf, len, ..]: [u64; 6]) -> Result<u64> {
+    let buf = user_slice_mut(buf, len)?;
+    let read = file::read(&*open_file(fd)?, buf)?;
+    Ok(read as u64)
+}
+
+fn sys_write([fd, buf, len, ..]: [u64; 6]) -> Result<u64> {
+    let data = user_slice(buf, len)?;
+    let written = file::write(&*open_file(fd)?, data)?;
+    Ok(written as u64)
+}
+
+/// Opens a device, directory or file, in that order.
+fn sys_open([path, len, flags, ..]: [u64; 6]) -> Result<u64> {
+    let path = user_str(path, len)?;
+    let file = match path {
+        "/dev/console" => OpenFile::Console,
+        "/dev/serial" => OpenFile::Serial,
+        _ => match fs::read_dir(path) {
+            Some(entries) => OpenFile::Directory { entries, next: 0 },
+            None => open_regular(path, flags)?,
+        },
+    };
+    let fd = files()?.lock().insert(file, flags & O_CLOEXEC != 0)?;
+    Ok(fd as u64)
+}
+
+fn open_regular(path: &str, flags: u64) -> Result<OpenFile> {
+    let exists = fs::handle_file_mut(|file| file.is_ok(), fs::Path::from_str(path));
+    if !exists {
+        if flags & O_CREAT == 0 {
+            return Err(ErrorKind::NotFound.into());
+        }
+        fs::create_file(&mut fs::Path::from_str(path)).map_err(|_| ErrorKind::NotFound)?;
+    }
+    Ok(OpenFile::File {
+        name: path.into(),
+        offset: 0,
+    })
+}
+
+fn sys_close([fd, ..]: [u64; 6]) -> Result<u64> {
+    files()?.lock().remove(fd as usize)?;
+    Ok(0)
+}
+
+fn sys_dup([fd, ..]: [u64; 6]) -> Result<u64> {
+    Ok(files()?.lock().dup(fd as usize)? as u64)
+}
+
+fn sys_dup2([fd, new, ..]: [u64; 6]) -> Result<u64> {
+    let new = usize::try_from(new).map_err(|_| ErrorKind::BadFileDescriptor)?;
+    Ok(files()?.lock().dup2(fd as usize, new)? as u64)
+}
+
+fn sys_fcntl([fd, command, arg, ..]: [u64; 6]) -> Result<u64> {
+    let files = files()?;
+    let mut files = files.lock();
+    match command {
+        F_GETFD => Ok(if files.close_on_exec(fd as usize)? {
+            FD_CLOEXEC
+        } else {
+            0
+        }),
+        F_SETFD => {
+            files.set_close_on_exec(fd as usize, arg & FD_CLOEXEC != 0)?;
+            Ok(0)
+        }
+        _ => Err(ErrorKind::InvalidArgument.into()),
+    }
+}
+
+/// Creates a pipe and stores the descriptors of its reading and writing end
+/// as two 32-bit integers at `fds`.
+fn sys_pipe([fds, flags, ..]: [u64; 6]) -> Result<u64> {
+    let out = user_slice_mut(fds, 8)?;
+    let (reader, writer) = pipe::pipe();
+    let close_on_exec = flags & O_CLOEXEC != 0;
+    let files = files()?;
+    let mut files = files.lock();
+    let read_fd = files.insert(OpenFile::PipeRead(reader), close_on_exec)?;
+    let write_fd = match files.insert(OpenFile::PipeWrite(writer), close_on_exec) {
+        Ok(fd) => fd,
+        Err(err) => {
+            files.remove(read_fd)?;
+            return Err(err);
+        }
+    };
+    out[..4].copy_from_slice(&(read_fd as u32).to_le_bytes());
+    out[4..].copy_from_slice(&(write_fd as u32).to_le_bytes());
+    Ok(0)
+}
+
+fn sys_exit([code, ..]: [u64; 6]) -> Result<u64> {
+    process::exit_current(code as i32)
+}
+
+fn sys_getpid(_: [u64; 6]) -> Result<u64> {
+    Ok(current_process()?.as_u64())
+}
+
+fn current_process() -> Result<Pid> {
+    Ok(process::current().ok_or(ErrorKind::InvalidArgument)?)
+}
+
+fn user_str<'a>(addr: u64, len: u64) -> Result<&'a str> {
+    Ok(core::str::from_utf8(user_slice(addr, len)?).map_err(|_| ErrorKind::InvalidArgument)?)
+}
+
+fn sys_sleep([millis, ..]: [u64; 6]) -> Result<u64> {
+    thread::sleep(time::Duration::from_millis(millis));
+    Ok(0)
+}
+
+fn sys_sbrk([increment, ..]: [u64; 6]) -> Result<u64> {
+    Ok(address_space()?.sbrk(increment as i64)?.as_u64())
+}
+
+fn sys_mmap([len, prot, ..]: [u64; 6]) -> Result<u64> {
+    let protection = Protection {
+        write: prot & PROT_WRITE != 0,
+        execute: prot & PROT_EXEC != 0,
+    };
+    Ok(address_space()?
+        .map_anonymous(len as usize, protection)?
+        .as_u64())
+}
+
+/// Starts the program at `path` as a child process. `argv` points to `argc`
+/// pairs of string address and length.
+fn sys_spawn([path, path_len, argv, argc, ..]: [u64; 6]) -> Result<u64> {
+    let parent = current_process()?;
+    let path = user_str(path, path_len)?;
+    let pairs = user_slice(
+        argv,
+        argc.checked_mul(16).ok_or(ErrorKind::InvalidArgument)?,
+    )?;
+    let args = pairs
+        .chunks_exact(16)
+        .map(|pair| {
+            let addr = u64::from_le_bytes(pair[..8].try_into().unwrap());
+            let len = u64::from_le_bytes(pair[8..].try_into().unwrap());
+            user_str(addr, len)
+        })
+        .collect::<Result<Vec<_>>>()?;
+    Ok(process::spawn(Some(parent), path, &args)?.as_u64())
+}
+
+fn sys_wait([pid, ..]: [u64; 6]) -> Result<u64> {
+    let code = process::wait(Some(current_process()?), Pid::from_u64(pid))?;
+    Ok(code as i64 as u64)
+}
+
+/// Ends `pid`, which must be a descendant of the caller.
+fn sys_kill([pid, ..]: [u64; 6]) -> Result<u64> {
+    process::kill_descendant(current_process()?, Pid::from_u64(pid))?;
+    Ok(0)
+}
+
+#[cfg(test)]
+core::arch::global_asm!(
+    ".pushsection .rodata.syscall_test, \"a\"",
+    ".global syscall_test_start",
+    ".global syscall_test_end",
+    "syscall_test_start:",
+    // write(1, message, len)
+    "mov eax, {write}",
+    "mov edi, 1",
+    "lea rsi, [rip + .Lmessage]",
+    "mov edx, .Lmessage_end - .Lmessage",
+    "syscall",
+    "cmp rax, .Lmessage_end - .Lmessage",
+    "jne .Lfail",
+    // int 0x80 reaches the same handlers
+    "mov eax, {getpid}",
+    "syscall",
+    "mov rbx, rax",
+    "mov eax, {getpid}",
+    "int 0x80",
+    "cmp rax, rbx",
+    "jne .Lfail",
+    // heap and anonymous memory are writable
+    "mov eax, {sbrk}",
+    "mov edi, 4096",
+    "syscall",
+    "test rax, rax",
+    "js .Lfail",
+    "mov r12, rax",
+    "mov byte ptr [r12 + 4095], 1",
+    "mov eax, {mmap}",
+    "mov edi, 8192",
+    "mov esi, {prot_write}",
+    "syscall",
+    "test rax, rax",
+    "js .Lfail",
+    "mov r13, rax",
+    "mov byte ptr [r13 + 8191], 1",
+    // a file written, closed and read back
+    "mov eax, {open}",
+    "lea rdi, [rip + .Lname]",
+    "mov esi, .Lname_end - .Lname",
+    "mov edx, {o_creat}",
+    "syscall",
+    "test rax, rax",
+    "js .Lfail",
+    "mov r14, rax",
+    "mov eax, {write}",
+    "mov rdi, r14",
+    "lea rsi, [rip + .Lmessage]",
+    "mov edx, .Lmessage_end - .Lmessage",
+    "syscall",
+    "mov eax, {close}",
+    "mov rdi, r14",
+    "syscall",
+    "test rax, rax",
+    "jnz .Lfail",
+    "mov eax, {open}",
+    "lea rdi, [rip + .Lname]",
+    "mov esi, .Lname_end - .Lname",
+    "xor edx, edx",
+    "syscall",
+    "test rax, rax",
+    "js .Lfail",
+    "mov r14, rax",
+    "mov eax, {read}",
+    "mov rdi, r14",
+    "mov rsi, r12",
+    "mov edx, 4096",
+    "syscall",
+    "cmp rax, .Lmessage_end - .Lmessage",
+    "jne .Lfail",
+    "mov rax, [r12]",
+    "cmp rax, [rip + .Lmessage]",
+    "jne .Lfail",
+    // a closed descriptor is gone
+    "mov eax, {close}",
+    "mov rdi, r14",
+    "syscall",
+    "mov eax, {read}",
+    "mov rdi, r14",
+    "mov rsi, r12",
+    "mov edx, 1",
+    "syscall",
+    "cmp rax, -{ebadf}",
+    "jne .Lfail",
+    // a duplicate of standard output, with its own close-on-exec flag
+    "mov eax, {dup}",
+    "mov edi, 1",
+    "syscall",
+    "cmp rax, 3",
+    "jne .Lfail",
+    "mov eax, {dup2}",
+    "mov edi, 3",
+    "mov esi, 9",
+    "syscall",
+    "cmp rax, 9",
+    "jne .Lfail",
+    "mov eax, {write}",
+    "mov edi, 9",
+    "lea rsi, [rip + .Lmessage]",
+    "mov edx, .Lmessage_end - .Lmessage",
+    "syscall",
+    "cmp rax, .Lmessage_end - .Lmessage",
+    "jne .Lfail",
+    "mov eax, {fcntl}",
+    "mov edi, 9",
+    "mov esi, {f_setfd}",
+    "mov edx, {fd_cloexec}",
+    "syscall",
+    "mov eax, {fcntl}",
+    "mov edi, 9",
+    "mov esi, {f_getfd}",
+    "syscall",
+    "cmp rax, {fd_cloexec}",
+    "jne .Lfail",
+    "mov eax, {fcntl}",
+    "mov edi, 3",
+    "mov esi, {f_getfd}",
+    "syscall",
+    "test rax, rax",
+    "jnz .Lfail",
+    // the root directory lists its files
+    "mov eax, {open}",
+    "lea rdi, [rip + .Lroot]",
+    "mov esi, 1",
+    "xor edx, edx",
+    "syscall",
+    "test rax, rax",
+    "js .Lfail",
+    "mov rdi, rax",
+    "mov eax, {read}",
+    "mov rsi, r12",
+    "mov edx, 4096",
+    "syscall",
+    "test rax, rax",
+    "jle .Lfail",
+    // kernel memory cannot be passed in
+    "mov eax, {write}",
+    "mov edi, 1",
+    "mov rsi, 0x1000",
+    "mov edx, 1",
+    "syscall",
+    "cmp rax, -{efault}",
+    "jne .Lfail",
+    "mov eax, {sleep}",
+    "mov edi, 10",
+    "syscall",
+    // bytes written to a pipe come out of it, then the end of the data
+    "mov eax, {pipe}",
+    "mov rdi, r12",
+    "xor esi, esi",
+    "syscall",
+    "test rax, rax",
+    "jnz .Lfail",
+    "mov eax, {write}",
+    "mov edi, [r12 + 4]",
+    "lea rsi, [rip + .Lmessage]",
+    "mov edx, 5",
+    "syscall",
+    "cmp rax, 5",
+    "jne .Lfail",
+    "mov eax, {close}",
+    "mov edi, [r12 + 4]",
+    "syscall",
+    "mov eax, {read}",
+    "mov edi, [r12]",
+    "lea rsi, [r12 + 16]",
+    "mov edx, 16",
+    "syscall",
+    "cmp rax, 5",
+    "jne .Lfail",
+    "mov eax, {read}",
+    "mov edi, [r12]",
+    "lea rsi, [r12 + 16]",
+    "mov edx, 16",
+    "syscall",
+    "test rax, rax",
+    "jnz .Lfail",
+    "mov eax, {close}",
+    "mov edi, [r12]",
+    "syscall",
+    // and writing without a reader fails
+    "mov eax, {pipe}",
+    "mov rdi, r12",
+    "xor esi, esi",
+    "syscall",
+    "mov eax, {close}",
+    "mov edi, [r12]",
+    "syscall",
+    "mov eax, {write}",
+    "mov edi, [r12 + 4]",
+    "lea rsi, [rip + .Lmessage]",
+    "mov edx, 5",
+    "syscall",
+    "cmp rax, -{epipe}",
+    "jne .Lfail",
+    "mov eax, {close}",
+    "mov edi, [r12 + 4]",
+    "syscall",
+    // a child process, whose exit code is waited for once
+    "lea rax, [rip + .Lecho]",
+    "mov [r12], rax",
+    "mov qword ptr [r12 + 8], .Lecho_end - .Lecho",
+    "lea rax, [rip + .Lmessage]",
+    "mov [r12 + 16], rax",
+    "mov qword ptr [r12 + 24], 5",
+    "mov eax, {spawn}",
+    "lea rdi, [rip + .Lecho]",
+    "mov esi, .Lecho_end - .Lecho",
+    "mov rdx, r12",
+    "mov r10d, 2",
+    "syscall",
+    "test rax, rax",
+    "js .Lfail",
+    "mov rbx, rax",
+    "mov rdi, rax",
+    "mov eax, {wait}",
+    "syscall",
+    "test rax, rax",
+    "jnz .Lfail",
+    "mov rdi, rbx",
+    "mov eax, {wait}",
+    "syscall",
+    "cmp rax, -{echild}",
+    "jne .Lfail",
+    "mov eax, 0xffff",
+    "syscall",
+    "cmp rax, -{enosys}",
+    "jne .Lfail",
+    "mov eax, {exit}",
+    "mov edi, 42",
+    "syscall",
+    ".Lfail:",
+    "mov eax, {exit}",
+    "mov edi, 1",
+    "syscall",
+    ".Lmessage:",
+    ".ascii \"hello from user mode\\n\"",
+    ".Lmessage_end:",
+    ".Lname:",
+    ".ascii \"syscall_test\"",
+    ".Lname_end:",
+    ".Lroot:",
+    ".ascii \"/\"",
+    ".Lecho:",
+    ".ascii \"echo\"",
+    ".Lecho_end:",
+    "syscall_test_end:",
+    ".popsection",
+    read = const SYS_READ,
+    write = const SYS_WRITE,
+    open = const SYS_OPEN,
+    close = const SYS_CLOSE,
+    exit = const SYS_EXIT,
+    getpid = const SYS_GETPID,
+    sleep = const SYS_SLEEP,
+    sbrk = const SYS_SBRK,
+    mmap = const SYS_MMAP,
+    spawn = const SYS_SPAWN,
+    wait = const SYS_WAIT,
+    dup = const SYS_DUP,
+    dup2 = const SYS_DUP2,
+    fcntl = const SYS_FCNTL,
+    pipe = const SYS_PIPE,
+    o_creat = const O_CREAT,
+    prot_write = const PROT_WRITE,
+    f_getfd = const F_GETFD,
+    f_setfd = const F_SETFD,
+    fd_cloexec = const FD_CLOEXEC,
+    ebadf = const EBADF,
+    echild = const ECHILD,
+    epipe = const EPIPE,
+    efault = const EFAULT,
+    enosys = const ENOSYS,
+);
+
+#[test_case]
+fn test_syscalls() {
+    use crate::usermode::USER_START;
+
+    extern "C" {
+        static syscall_test_start: u8;
+        static syscall_test_end: u8;
+    }
+    let program = unsafe {
+        let start = core::ptr::addr_of!(syscall_test_start);
+        let len = core::ptr::addr_of!(syscall_test_end) as usize - start as usize;
+        core::slice::from_raw_parts(start, len)
+    };
+
+    let space = AddressSpace::new().unwrap();
+    let entry = VirtAddr::new(USER_START);
+    space
+        .map(entry, program.len(), Protection::READ_EXECUTE)
+        .unwrap();
+    space.write(entry, program).unwrap();
+    let stack_top = VirtAddr::new(USER_START + 0x10_0000);
+    space
+        .map(stack_top - 4096u64, 4096, Protection::READ_WRITE)
+        .unwrap();
+
+    crate::exec::install_programs();
+    let program = crate::exec::Program {
+        space,
+        entry,
+        stack_pointer: stack_top,
+    };
+    let pid = process::create(None, "syscall test", program, FileTable::with_console()).unwrap();
+    assert_eq!(process::wait(None, pid).unwrap(), 42);
+}
+
+#[test_case]
+fn test_dispatch_outside_user_mode() {
+    // kernel threads are no process, so have no address space or files
+    assert_eq!(dispatch(SYS_GETPID, [0; 6]) as i64, -EINVAL);
+    assert_eq!(dispatch(SYS_CLOSE, [0; 6]) as i64, -EINVAL);
+    assert_eq!(dispatch(1000, [0; 6]) as i64, -ENOSYS);
+}
@@ -1,51 +1,231 @@
-use crate::error::{ErrorKind, Result};
-
-use super::{Task, TaskId};
-use alloc::{collections::BTreeMap, sync::Arc, task::Wake};
-use core::task::{Context, Poll, Waker};
-use crossbeam_queue::ArrayQueue;
+use super::{Priority, Task, TaskId};
+use crate::interrupts;
+use crate::sync::IrqSpinLock;
+use alloc::{collections::BTreeMap, string::String, sync::Arc, task::Wake, vec::Vec};
+use core::cell::UnsafeCell;
+use core::ptr::NonNull;
+use core::sync::atomic::{AtomicBool, Ordering};
+use core::task::{Context, Waker};
 use spin::{Lazy, Mutex};
 
-static TASK_QUEUE: Lazy<Arc<ArrayQueue<TaskId>>> = Lazy::new(|| Arc::new(ArrayQueue::new(100)));
+/// Per-task state shared with its waker. Allocated when the task is spawned,
+/// so waking a task from an interrupt handler never allocates.
+struct TaskHeader {
+    id: TaskId,
+    priority: Priority,
+    /// Set while the task is in the ready queue, so it is queued at most once
+    /// however often it is woken.
+    scheduled: AtomicBool,
+    /// Next task in the ready queue, only accessed with `READY` locked.
+    next: UnsafeCell<Option<Arc<TaskHeader>>>,
+}
+
+unsafe impl Send for TaskHeader {}
+unsafe impl Sync for TaskHeader {}
+
+impl TaskHeader {
+    fn new(id: TaskId, priority: Priority) -> Self {
+        Self {
+            id,
+            priority,
+            scheduled: AtomicBool::new(false),
+            next: UnsafeCell::new(None),
+        }
+    }
+
+    fn schedule(self: &Arc<Self>) {
+        if !self.scheduled.swap(true, Ordering::AcqRel) {
+            READY.lock().queues[self.priority as usize].push(self.clone());
+        }
+    }
+}
+
+/// Tasks to be polled, linked through their headers.
+struct ReadyQueue {
+    head: Option<Arc<TaskHeader>>,
+    tail: Option<NonNull<TaskHeader>>,
+    len: usize,
+}
+
+unsafe impl Send for ReadyQueue {}
+
+impl ReadyQueue {
+    const fn new() -> Self {
+        Self {
+            head: None,
+            tail: None,
+            len: 0,
+        }
+    }
+
+    fn push(&mut self, header: Arc<TaskHeader>) {
+        let ptr = NonNull::from(&*header);
+        match self.tail {
+            // the tail is kept alive by the `next` link or `head` pointing to it
+            Some(tail) => unsafe { *tail.as_ref().next.get() = Some(header) },
+            None => self.head = Some(header),
+        }
+        self.tail = Some(ptr);
+        self.len += 1;
+    }
+
+    fn pop(&mut self) -> Option<Arc<TaskHeader>> {
+        let header = self.head.take()?;
+        self.head = unsafe { (*header.next.get()).take() };
+        if self.head.is_none() {
+            self.tail = None;
+        }
+        self.len -= 1;
+        Some(header)
+    }
+
+    fn is_empty(&self) -> bool {
+        self.head.is_none()
+    }
+}
+
+/// Polls of higher priority tasks a waiting queue lets through before it
+/// gets a turn.
+const STARVATION_LIMIT: u32 = 16;
+
+/// Tasks polled by `run_ready_tasks` before it returns, so kernel threads and
+/// the idle check get to run even if tasks keep waking each other.
+const POLL_BUDGET: usize = 64;
+
+struct ReadyQueues {
+    queues: [ReadyQueue; 3],
+    /// Per queue, polls of higher priority tasks since it last had a turn or
+    /// was empty.
+    waited: [u32; 3],
+}
+
+impl ReadyQueues {
+    fn pop(&mut self) -> Option<Arc<TaskHeader>> {
+        let ready = |i: &usize| !self.queues[*i].is_empty();
+        let highest = (0..self.queues.len()).rev().find(ready)?;
+        // of the queues that waited too long, the highest priority one
+        let level = (0..highest)
+            .rev()
+            .filter(ready)
+            .find(|&i| self.waited[i] >= STARVATION_LIMIT)
+            .unwrap_or(highest);
+        for (i, waited) in self.waited.iter_mut().enumerate() {
+            if i == level || self.queues[i].is_empty() {
+                *waited = 0;
+            } else if i < level {
+                *waited += 1;
+            }
+        }
+        self.queues[level].pop()
+    }
+
+    fn is_empty(&self) -> bool {
+        self.queues.iter().all(ReadyQueue::is_empty)
+    }
+}
 
-static TASKS: Lazy<Mutex<BTreeMap<TaskId, Task>>> = Lazy::new(|| Mutex::new(BTreeMap::new()));
+static READY: IrqSpinLock<ReadyQueues> = IrqSpinLock::new(ReadyQueues {
+    queues: [ReadyQueue::new(), ReadyQueue::new(), ReadyQueue::new()],
+    waited: [0; 3],
+});
 
-static WAKER_CACHE: Lazy<Mutex<BTreeMap<TaskId, Waker>>> =
-    Lazy::new(|| Mutex::new(BTreeMap::new()));
+struct Entry {
+    name: String,
+    priority: Priority,
+    /// Taken out while the task is being polled.
+    task: Option<Task>,
+    header: Arc<TaskHeader>,
+    waker: Waker,
+    polls: u64,
+    /// Timer ticks spent in `poll`.
+    ticks: u64,
+}
+
+/// Statistics of a task, for listing.
+#[derive(Debug, Clone)]
+pub struct TaskInfo {
+    pub id: TaskId,
+    pub name: String,
+    pub priority: Priority,
+    pub polls: u64,
+    pub ticks: u64,
+}
 
-pub fn spawn(task: Task) -> Result<()> {
-    TASK_QUEUE
-        .push(task.id)
-        .or(Err(ErrorKind::TaskQueueIsFull))?;
+static TASKS: Lazy<Mutex<BTreeMap<TaskId, Entry>>> = Lazy::new(|| Mutex::new(BTreeMap::new()));
 
-    if TASKS.lock().insert(task.id, task).is_some() {
-        panic!("task with same ID already in tasks");
+pub fn spawn(task: Task) {
+    let header = Arc::new(TaskHeader::new(task.id, task.priority));
+    let entry = Entry {
+        name: task.name.clone(),
+        priority: task.priority,
+        polls: 0,
+        ticks: 0,
+        task: Some(task),
+        header: header.clone(),
+        waker: Waker::from(Arc::new(TaskWaker {
+            header: header.clone(),
+        })),
+    };
+    TASKS.lock().insert(header.id, entry);
+    header.schedule();
+}
+
+/// Queues a task to be polled, as its waker would.
+pub(super) fn wake(task_id: TaskId) {
+    let header = TASKS.lock().get(&task_id).map(|entry| entry.header.clone());
+    if let Some(header) = header {
+        header.schedule();
     }
+}
 
-    Ok(())
+/// The tasks that have not completed.
+pub fn tasks() -> Vec<TaskInfo> {
+    TASKS
+        .lock()
+        .iter()
+        .map(|(&id, entry)| TaskInfo {
+            id,
+            name: entry.name.clone(),
+            priority: entry.priority,
+            polls: entry.polls,
+            ticks: entry.ticks,
+        })
+        .collect()
 }
 
+/// Polls ready tasks, highest priority first, until none are left or
+/// `POLL_BUDGET` polls were made.
 pub fn run_ready_tasks() {
-    while let Some(task_id) = TASK_QUEUE.pop() {
-        let mut task = match { TASKS.lock().remove(&task_id) } {
-            Some(task) => task,
-            None => continue,
+    for _ in 0..POLL_BUDGET {
+        let Some(header) = READY.lock().pop() else {
+            break;
         };
+        // wakeups from now on queue the task again
+        header.scheduled.store(false, Ordering::Release);
 
-        let mut waker_cache = { WAKER_CACHE.lock() };
-
-        let waker = waker_cache
-            .entry(task_id)
-            .or_insert_with(|| TaskWaker::new(task_id, TASK_QUEUE.clone()));
+        let (mut task, waker) = {
+            let mut tasks = TASKS.lock();
+            let Some(entry) = tasks.get_mut(&header.id) else {
+                continue;
+            };
+            let Some(task) = entry.task.take() else {
+                continue;
+            };
+            (task, entry.waker.clone())
+        };
 
         let mut context = Context::from_waker(&waker);
-        match task.poll(&mut context) {
-            Poll::Ready(()) => {
-                waker_cache.remove(&task_id);
-            }
-            Poll::Pending => {
-                TASKS.lock().insert(task_id, task);
-            }
+        let start = interrupts::ticks();
+        let ready = task.poll(&mut context).is_ready();
+        let elapsed = interrupts::ticks() - start;
+
+        let mut tasks = TASKS.lock();
+        if ready {
+            tasks.remove(&header.id);
+        } else if let Some(entry) = tasks.get_mut(&header.id) {
+            entry.task = Some(task);
+            entry.polls += 1;
+            entry.ticks += elapsed;
         }
     }
 }
@@ -53,8 +233,12 @@ pub fn run_ready_tasks() {
 fn sleep_if_idle() {
     use x86_64::instructions::interrupts::{self, enable_and_hlt};
 
+    // let kernel threads run before putting the CPU to sleep
+    if crate::thread::yield_if_others_ready() {
+        return;
+    }
     interrupts::disable();
-    if TASK_QUEUE.is_empty() {
+    if READY.lock().is_empty() {
         enable_and_hlt();
     } else {
         interrupts::enable();
@@ -68,31 +252,120 @@ pub fn run() -> ! {
     }
 }
 
-#[allow(dead_code)]
 struct TaskWaker {
-    task_id: TaskId,
-    task_queue: Arc<ArrayQueue<TaskId>>,
+    header: Arc<TaskHeader>,
 }
 
-impl TaskWaker {
-    fn new(task_id: TaskId, task_queue: Arc<ArrayQueue<TaskId>>) -> Waker {
-        Waker::from(Arc::new(TaskWaker {
-            task_id,
-            task_queue,
-        }))
+impl Wake for TaskWaker {
+    fn wake(self: Arc<Self>) {
+        self.header.schedule()
+    }
+
+    fn wake_by_ref(self: &Arc<Self>) {
+        self.header.schedule()
     }
+}
+
+#[test_case]
+fn test_many_tasks() {
+    use core::sync::atomic::AtomicUsize;
+    use core::task::Poll;
+
+    static DONE: AtomicUsize = AtomicUsize::new(0);
+    const TASK_COUNT: usize = 5000;
 
-    fn wake_task(&self) {
-        self.task_queue.push(self.task_id).expect("task_queue full");
+    for _ in 0..TASK_COUNT {
+        spawn(Task::with_name("stress", async {
+            // wake itself once, so every task is queued twice
+            let mut yielded = false;
+            core::future::poll_fn(|cx| {
+                if yielded {
+                    return Poll::Ready(());
+                }
+                yielded = true;
+                cx.waker().wake_by_ref();
+                Poll::Pending
+            })
+            .await;
+            DONE.fetch_add(1, Ordering::Relaxed);
+        }));
     }
+    while !READY.lock().is_empty() {
+        run_ready_tasks();
+    }
+    assert_eq!(DONE.load(Ordering::Relaxed), TASK_COUNT);
+    assert!(tasks().iter().all(|task| task.name != "stress"));
 }
 
-impl Wake for TaskWaker {
-    fn wake(self: Arc<Self>) {
-        self.wake_task()
+#[test_case]
+fn test_wakeups_deduplicated() {
+    use core::task::Poll;
+
+    let polled = Arc::new(AtomicBool::new(false));
+    let task = Task::with_name("dedup", {
+        let polled = polled.clone();
+        core::future::poll_fn(move |_| {
+            polled.store(true, Ordering::Relaxed);
+            Poll::<()>::Pending
+        })
+    });
+    let id = task.id;
+    spawn(task);
+    run_ready_tasks();
+    assert!(polled.load(Ordering::Relaxed));
+
+    let task_waker = TASKS.lock()[&id].waker.clone();
+    for _ in 0..10_000 {
+        task_waker.wake_by_ref();
     }
+    let queued: usize = READY.lock().queues.iter().map(|queue| queue.len).sum();
+    assert_eq!(queued, 1);
+    run_ready_tasks();
+    assert!(READY.lock().is_empty());
+    TASKS.lock().remove(&id);
+}
 
-    fn wake_by_ref(self: &Arc<Self>) {
-        self.wake_task()
+#[test_case]
+fn test_priorities_without_starvation() {
+    use alloc::vec;
+    use core::task::Poll;
+
+    static ORDER: spin::Mutex<Vec<Priority>> = spin::Mutex::new(Vec::new());
+
+    let spawn_recorder = |priority, polls: usize| {
+        let mut left = polls;
+        spawn(
+            Task::with_name(
+                "priority test",
+                core::future::poll_fn(move |cx| {
+                    ORDER.lock().push(priority);
+                    left -= 1;
+                    if left == 0 {
+                        return Poll::Ready(());
+                    }
+                    cx.waker().wake_by_ref();
+                    Poll::Pending
+                }),
+            )
+            .with_priority(priority),
+        )
+    };
+    // the high and low priority tasks keep waking themselves
+    spawn_recorder(Priority::Low, 40);
+    spawn_recorder(Priority::High, 40);
+    spawn_recorder(Priority::Normal, 1);
+    while !READY.lock().is_empty() {
+        run_ready_tasks();
     }
+
+    let order = ORDER.lock();
+    assert_eq!(order.len(), 81);
+    // the lower priority tasks run before the high priority one is done
+    let first_low = order.iter().position(|&p| p == Priority::Low).unwrap();
+    let normal = order.iter().position(|&p| p == Priority::Normal).unwrap();
+    let last_high = order.iter().rposition(|&p| p == Priority::High).unwrap();
+    let last_low = order.iter().rposition(|&p| p == Priority::Low).unwrap();
+    assert!(first_low < last_high);
+    assert!(normal < last_high && normal < last_low);
+    assert_eq!(order[..2], vec![Priority::High; 2]);
 }
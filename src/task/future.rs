@@ -0,0 +1,77 @@
+//! Future combinators.
+
+use crate::error::{ErrorKind, Result};
+use crate::time::{self, Duration};
+use core::{
+    future::{poll_fn, Future},
+    pin::pin,
+    task::Poll,
+};
+
+/// Output of `select`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Either<A, B> {
+    Left(A),
+    Right(B),
+}
+
+/// Waits for whichever of `left` and `right` completes first and drops the
+/// other. `left` is polled first, so it wins if both are ready.
+pub async fn select<A: Future, B: Future>(left: A, right: B) -> Either<A::Output, B::Output> {
+    let mut left = pin!(left);
+    let mut right = pin!(right);
+    poll_fn(|cx| {
+        if let Poll::Ready(output) = left.as_mut().poll(cx) {
+            return Poll::Ready(Either::Left(output));
+        }
+        if let Poll::Ready(output) = right.as_mut().poll(cx) {
+            return Poll::Ready(Either::Right(output));
+        }
+        Poll::Pending
+    })
+    .await
+}
+
+/// Runs `future` for at most `duration`, failing with `ErrorKind::TimedOut`
+/// if it has not completed by then.
+pub async fn timeout<F: Future>(duration: Duration, future: F) -> Result<F::Output> {
+    match select(future, time::sleep(duration)).await {
+        Either::Left(output) => Ok(output),
+        Either::Right(()) => Err(ErrorKind::TimedOut.into()),
+    }
+}
+
+#[cfg(test)]
+pub(super) fn block_on<F: Future>(future: F) -> F::Output {
+    use super::simple_executor::dummy_waker;
+    use core::task::Context;
+
+    let mut future = pin!(future);
+    let waker = dummy_waker();
+    let mut context = Context::from_waker(&waker);
+    loop {
+        if let Poll::Ready(output) = future.as_mut().poll(&mut context) {
+            return output;
+        }
+        x86_64::instructions::hlt();
+    }
+}
+
+#[test_case]
+fn test_select() {
+    let ready = block_on(select(core::future::pending::<()>(), async { 7 }));
+    assert_eq!(ready, Either::Right(7));
+    let both = block_on(select(async { 1 }, async { 2 }));
+    assert_eq!(both, Either::Left(1));
+}
+
+#[test_case]
+fn test_timeout() {
+    let expired = block_on(timeout(
+        Duration::from_millis(5),
+        core::future::pending::<()>(),
+    ));
+    assert!(matches!(expired.unwrap_err().kind(), ErrorKind::TimedOut));
+    let done = block_on(timeout(Duration::from_secs(1), async { 3 }));
+    assert_eq!(done.unwrap(), 3);
+}
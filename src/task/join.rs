@@ -0,0 +1,151 @@
+use super::{executor, TaskId};
+use crate::error::{ErrorKind, Result};
+use alloc::{boxed::Box, sync::Arc};
+use core::{
+    future::Future,
+    pin::Pin,
+    task::{Context, Poll, Waker},
+};
+use spin::Mutex;
+
+enum Stage<T> {
+    Running,
+    Finished(T),
+    Aborted,
+    /// The output was taken by the `JoinHandle`.
+    Consumed,
+}
+
+struct State<T> {
+    stage: Stage<T>,
+    abort_requested: bool,
+    /// The task awaiting the `JoinHandle`.
+    waker: Option<Waker>,
+}
+
+type Shared<T> = Arc<Mutex<State<T>>>;
+
+/// Wraps a spawned future to store its output for the `JoinHandle`, and to
+/// stop polling it once aborted.
+pub(super) struct Joinable<F: Future> {
+    future: Pin<Box<F>>,
+    state: Shared<F::Output>,
+}
+
+impl<F: Future> Future for Joinable<F> {
+    type Output = ();
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<()> {
+        let stage = if self.state.lock().abort_requested {
+            Stage::Aborted
+        } else {
+            match self.future.as_mut().poll(cx) {
+                Poll::Ready(output) => Stage::Finished(output),
+                Poll::Pending => return Poll::Pending,
+            }
+        };
+        let waker = {
+            let mut state = self.state.lock();
+            state.stage = stage;
+            state.waker.take()
+        };
+        if let Some(waker) = waker {
+            waker.wake();
+        }
+        Poll::Ready(())
+    }
+}
+
+/// Owns a spawned task's output. Awaiting it gives the output, or an
+/// `ErrorKind::Cancelled` error if the task was aborted.
+pub struct JoinHandle<T> {
+    id: Option<TaskId>,
+    state: Shared<T>,
+}
+
+pub(super) fn joinable<F: Future>(future: F) -> (Joinable<F>, JoinHandle<F::Output>) {
+    let state = Arc::new(Mutex::new(State {
+        stage: Stage::Running,
+        abort_requested: false,
+        waker: None,
+    }));
+    let joinable = Joinable {
+        future: Box::pin(future),
+        state: state.clone(),
+    };
+    (joinable, JoinHandle { id: None, state })
+}
+
+impl<T> JoinHandle<T> {
+    pub(super) fn with_id(mut self, id: TaskId) -> Self {
+        self.id = Some(id);
+        self
+    }
+
+    pub fn id(&self) -> TaskId {
+        self.id.expect("join handle of an unspawned task")
+    }
+
+    pub fn is_finished(&self) -> bool {
+        !matches!(self.state.lock().stage, Stage::Running)
+    }
+
+    /// Stops the task before it is polled again, dropping its future.
+    pub fn abort(&self) {
+        {
+            let mut state = self.state.lock();
+            if !matches!(state.stage, Stage::Running) {
+                return;
+            }
+            state.abort_requested = true;
+        }
+        if let Some(id) = self.id {
+            executor::wake(id);
+        }
+    }
+}
+
+impl<T> Future for JoinHandle<T> {
+    type Output = Result<T>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Result<T>> {
+        let mut state = self.state.lock();
+        match core::mem::replace(&mut state.stage, Stage::Consumed) {
+            Stage::Finished(output) => Poll::Ready(Ok(output)),
+            Stage::Aborted => Poll::Ready(Err(ErrorKind::Cancelled.into())),
+            Stage::Consumed => panic!("join handle polled after completion"),
+            Stage::Running => {
+                state.stage = Stage::Running;
+                state.waker = Some(cx.waker().clone());
+                Poll::Pending
+            }
+        }
+    }
+}
+
+#[test_case]
+fn test_join_handle() {
+    use super::future::block_on;
+
+    let handle = super::spawn("join test", async { 6 * 7 });
+    assert!(!handle.is_finished());
+    executor::run_ready_tasks();
+    assert!(handle.is_finished());
+    assert_eq!(block_on(handle).unwrap(), 42);
+}
+
+#[test_case]
+fn test_abort() {
+    use super::future::block_on;
+
+    let handle = super::spawn("abort test", core::future::pending::<()>());
+    executor::run_ready_tasks();
+    assert!(executor::tasks()
+        .iter()
+        .any(|task| task.id == handle.id() && task.name == "abort test"));
+    handle.abort();
+    executor::run_ready_tasks();
+    assert!(handle.is_finished());
+    let result = block_on(handle);
+    assert!(matches!(result.unwrap_err().kind(), ErrorKind::Cancelled));
+}
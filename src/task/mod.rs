@@ -1,5 +1,6 @@
-use alloc::boxed::Box;
+use alloc::{boxed::Box, string::String};
 use core::{
+    fmt,
     future::Future,
     pin::Pin,
     sync::atomic::{AtomicU64, Ordering},
@@ -7,13 +8,66 @@ use core::{
 };
 
 pub mod executor;
+pub mod future;
+pub mod join;
 pub mod simple_executor;
+pub mod sync;
 
-#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
-struct TaskId(u64);
+pub use future::{select, timeout, Either};
+pub use join::JoinHandle;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct TaskId(u64);
+
+impl fmt::Display for TaskId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Display::fmt(&self.0, f)
+    }
+}
+
+/// Order in which ready tasks are polled.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Default)]
+pub enum Priority {
+    Low,
+    #[default]
+    Normal,
+    High,
+}
+
+impl Priority {
+    pub fn name(self) -> &'static str {
+        match self {
+            Priority::Low => "low",
+            Priority::Normal => "normal",
+            Priority::High => "high",
+        }
+    }
+}
 
 pub fn add(task: Task) {
-    executor::spawn(task).expect("failed to add a task");
+    executor::spawn(task);
+}
+
+/// Runs `future` on the executor under `name`. The returned handle can be
+/// awaited for its output; dropping it lets the task run on.
+pub fn spawn<F>(name: &str, future: F) -> JoinHandle<F::Output>
+where
+    F: Future + Send + 'static,
+    F::Output: Send + 'static,
+{
+    spawn_with_priority(name, Priority::Normal, future)
+}
+
+pub fn spawn_with_priority<F>(name: &str, priority: Priority, future: F) -> JoinHandle<F::Output>
+where
+    F: Future + Send + 'static,
+    F::Output: Send + 'static,
+{
+    let (future, handle) = join::joinable(future);
+    let task = Task::with_name(name, future).with_priority(priority);
+    let handle = handle.with_id(task.id);
+    add(task);
+    handle
 }
 
 pub fn run() -> ! {
@@ -29,17 +83,42 @@ impl TaskId {
 
 pub struct Task {
     id: TaskId,
+    name: String,
+    priority: Priority,
     future: Pin<Box<dyn Future<Output = ()> + Send>>,
 }
 
 impl Task {
     pub fn new(future: impl Future<Output = ()> + 'static + Send) -> Task {
+        Task::with_name("task", future)
+    }
+
+    pub fn with_name(name: &str, future: impl Future<Output = ()> + 'static + Send) -> Task {
         Task {
             id: TaskId::new(),
+            name: name.into(),
+            priority: Priority::Normal,
             future: Box::pin(future),
         }
     }
 
+    pub fn with_priority(mut self, priority: Priority) -> Task {
+        self.priority = priority;
+        self
+    }
+
+    pub fn id(&self) -> TaskId {
+        self.id
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn priority(&self) -> Priority {
+        self.priority
+    }
+
     fn poll(&mut self, context: &mut Context) -> Poll<()> {
         self.future.as_mut().poll(context)
     }
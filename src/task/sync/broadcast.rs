@@ -0,0 +1,182 @@
+//! Channels that deliver every value to every receiver.
+
+use crate::sync::{IrqSpinLock, WaitQueue};
+use alloc::{collections::VecDeque, sync::Arc};
+use core::fmt;
+
+/// There were no receivers; the value is returned.
+#[derive(PartialEq, Eq)]
+pub struct SendError<T>(pub T);
+
+impl<T> fmt::Debug for SendError<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("SendError(..)")
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RecvError {
+    /// Every sender is gone and all values were received.
+    Closed,
+    /// The receiver fell behind and this many values were dropped for it;
+    /// receiving continues with the oldest value still buffered.
+    Lagged(u64),
+}
+
+struct State<T> {
+    /// The most recent values, with the sequence number of the first one.
+    buffer: VecDeque<T>,
+    first: u64,
+    senders: usize,
+    receivers: usize,
+}
+
+struct Shared<T> {
+    state: IrqSpinLock<State<T>>,
+    capacity: usize,
+    waiters: WaitQueue,
+}
+
+/// A channel keeping the last `capacity` values for receivers that fall behind.
+pub fn channel<T: Clone>(capacity: usize) -> (Sender<T>, Receiver<T>) {
+    assert!(capacity > 0, "channel capacity must not be zero");
+    let shared = Arc::new(Shared {
+        state: IrqSpinLock::new(State {
+            buffer: VecDeque::with_capacity(capacity),
+            first: 0,
+            senders: 1,
+            receivers: 1,
+        }),
+        capacity,
+        waiters: WaitQueue::new(),
+    });
+    (
+        Sender {
+            shared: shared.clone(),
+        },
+        Receiver { shared, next: 0 },
+    )
+}
+
+pub struct Sender<T> {
+    shared: Arc<Shared<T>>,
+}
+
+impl<T: Clone> Sender<T> {
+    /// Sends `value` to every receiver, returning how many there are.
+    pub fn send(&self, value: T) -> Result<usize, SendError<T>> {
+        let receivers = {
+            let mut state = self.shared.state.lock();
+            if state.receivers == 0 {
+                return Err(SendError(value));
+            }
+            if state.buffer.len() == self.shared.capacity {
+                state.buffer.pop_front();
+                state.first += 1;
+            }
+            state.buffer.push_back(value);
+            state.receivers
+        };
+        self.shared.waiters.notify_all();
+        Ok(receivers)
+    }
+
+    /// A receiver for the values sent from now on.
+    pub fn subscribe(&self) -> Receiver<T> {
+        let mut state = self.shared.state.lock();
+        state.receivers += 1;
+        Receiver {
+            shared: self.shared.clone(),
+            next: state.first + state.buffer.len() as u64,
+        }
+    }
+}
+
+impl<T> Clone for Sender<T> {
+    fn clone(&self) -> Self {
+        self.shared.state.lock().senders += 1;
+        Sender {
+            shared: self.shared.clone(),
+        }
+    }
+}
+
+impl<T> Drop for Sender<T> {
+    fn drop(&mut self) {
+        let last = {
+            let mut state = self.shared.state.lock();
+            state.senders -= 1;
+            state.senders == 0
+        };
+        if last {
+            self.shared.waiters.notify_all();
+        }
+    }
+}
+
+pub struct Receiver<T> {
+    shared: Arc<Shared<T>>,
+    /// Sequence number of the next value to receive.
+    next: u64,
+}
+
+impl<T: Clone> Receiver<T> {
+    /// The next value, or `None` if nothing new was sent yet.
+    pub fn try_recv(&mut self) -> Option<Result<T, RecvError>> {
+        let state = self.shared.state.lock();
+        if self.next < state.first {
+            let lagged = state.first - self.next;
+            self.next = state.first;
+            return Some(Err(RecvError::Lagged(lagged)));
+        }
+        match state.buffer.get((self.next - state.first) as usize) {
+            Some(value) => {
+                self.next += 1;
+                Some(Ok(value.clone()))
+            }
+            None if state.senders == 0 => Some(Err(RecvError::Closed)),
+            None => None,
+        }
+    }
+
+    pub async fn recv(&mut self) -> Result<T, RecvError> {
+        let shared = self.shared.clone();
+        let mut received = None;
+        shared
+            .waiters
+            .wait_until_async(|| {
+                received = self.try_recv();
+                received.is_some()
+            })
+            .await;
+        received.unwrap()
+    }
+}
+
+impl<T> Drop for Receiver<T> {
+    fn drop(&mut self) {
+        self.shared.state.lock().receivers -= 1;
+    }
+}
+
+#[test_case]
+fn test_broadcast() {
+    use crate::task::future::block_on;
+
+    let (tx, mut first) = channel(2);
+    let mut second = tx.subscribe();
+    assert_eq!(tx.send(1), Ok(2));
+    assert_eq!(block_on(first.recv()), Ok(1));
+    tx.send(2).unwrap();
+    tx.send(3).unwrap();
+    assert_eq!(block_on(first.recv()), Ok(2));
+
+    // `second` missed 1, which no longer fits in the buffer
+    assert_eq!(block_on(second.recv()), Err(RecvError::Lagged(1)));
+    assert_eq!(block_on(second.recv()), Ok(2));
+    assert_eq!(block_on(second.recv()), Ok(3));
+
+    drop(tx);
+    assert_eq!(block_on(first.recv()), Ok(3));
+    assert_eq!(block_on(first.recv()), Err(RecvError::Closed));
+}
@@ -0,0 +1,192 @@
+//! Synchronization between executor tasks.
+//!
+//! Everything here waits by returning `Poll::Pending` and registering the
+//! task's waker, so waiting tasks leave the executor free to run others.
+
+use crate::sync::WaitQueue;
+use core::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+
+pub mod broadcast;
+pub mod mpsc;
+pub mod oneshot;
+
+pub use crate::sync::{MutexGuard, Semaphore};
+
+/// A mutual exclusion lock for tasks, which may be held across `.await`.
+///
+/// The same lock as `crate::sync::Mutex`, with `lock` waiting as a task.
+#[derive(Default)]
+pub struct Mutex<T: ?Sized> {
+    inner: crate::sync::Mutex<T>,
+}
+
+impl<T> Mutex<T> {
+    pub const fn new(value: T) -> Self {
+        Self {
+            inner: crate::sync::Mutex::new(value),
+        }
+    }
+
+    pub fn into_inner(self) -> T {
+        self.inner.into_inner()
+    }
+}
+
+impl<T: ?Sized> Mutex<T> {
+    pub async fn lock(&self) -> MutexGuard<'_, T> {
+        self.inner.lock_async().await
+    }
+
+    pub fn try_lock(&self) -> Option<MutexGuard<'_, T>> {
+        self.inner.try_lock()
+    }
+
+    pub fn get_mut(&mut self) -> &mut T {
+        self.inner.get_mut()
+    }
+}
+
+/// Wakes tasks waiting for an event.
+///
+/// `notify_one` leaves a permit behind when nobody is waiting, so the next
+/// `notified` completes at once; `notify_waiters` only wakes tasks that are
+/// already waiting.
+#[derive(Default)]
+pub struct Notify {
+    permit: AtomicBool,
+    /// Bumped by `notify_waiters`.
+    generation: AtomicU64,
+    waiters: WaitQueue,
+}
+
+impl Notify {
+    pub const fn new() -> Self {
+        Self {
+            permit: AtomicBool::new(false),
+            generation: AtomicU64::new(0),
+            waiters: WaitQueue::new(),
+        }
+    }
+
+    /// Completes once notified.
+    pub async fn notified(&self) {
+        let generation = self.generation.load(Ordering::Acquire);
+        self.waiters
+            .wait_until_async(|| {
+                self.permit.swap(false, Ordering::AcqRel)
+                    || self.generation.load(Ordering::Acquire) != generation
+            })
+            .await
+    }
+
+    pub fn notify_one(&self) {
+        self.permit.store(true, Ordering::Release);
+        self.waiters.notify_one();
+    }
+
+    pub fn notify_waiters(&self) {
+        self.generation.fetch_add(1, Ordering::Release);
+        self.waiters.notify_all();
+    }
+}
+
+#[test_case]
+fn test_mutex_across_await() {
+    use super::executor;
+    use crate::task::future::block_on;
+    use alloc::{sync::Arc, vec::Vec};
+
+    let shared = Arc::new(Mutex::new(Vec::new()));
+    let handles: Vec<_> = (0..3)
+        .map(|i| {
+            let shared = shared.clone();
+            super::spawn("mutex test", async move {
+                let mut values = shared.lock().await;
+                values.push(i);
+                // the lock is held while this task is suspended
+                crate::time::sleep(crate::time::Duration::from_millis(1)).await;
+                values.push(i);
+            })
+        })
+        .collect();
+    for handle in handles {
+        while !handle.is_finished() {
+            executor::run_ready_tasks();
+        }
+        block_on(handle).unwrap();
+    }
+    let values = block_on(shared.lock());
+    assert!(values.chunks(2).all(|pair| pair[0] == pair[1]));
+    assert_eq!(values.len(), 6);
+}
+
+#[test_case]
+fn test_abandoned_lock_keeps_waiters() {
+    use super::{executor, select, spawn, timeout, Either};
+    use crate::time::Duration;
+    use alloc::sync::Arc;
+
+    let mutex = Arc::new(Mutex::new(()));
+    let guard = mutex.try_lock().unwrap();
+    let gives_up = {
+        let mutex = mutex.clone();
+        spawn("mutex test", async move {
+            assert!(timeout(Duration::from_millis(1), mutex.lock())
+                .await
+                .is_err());
+        })
+    };
+    let waiter = {
+        let mutex = mutex.clone();
+        spawn("mutex test", async move { drop(mutex.lock().await) })
+    };
+    while !gives_up.is_finished() {
+        executor::run_ready_tasks();
+    }
+    // the waker of the task that gave up is no longer queued
+    drop(guard);
+    executor::run_ready_tasks();
+    assert!(waiter.is_finished());
+
+    // gives up after being picked by the unlock, which passes it on
+    let notify = Arc::new(Notify::new());
+    let guard = mutex.try_lock().unwrap();
+    let gives_up = {
+        let (mutex, notify) = (mutex.clone(), notify.clone());
+        spawn("mutex test", async move {
+            let picked = select(notify.notified(), mutex.lock()).await;
+            assert!(matches!(picked, Either::Left(())));
+        })
+    };
+    let waiter = {
+        let mutex = mutex.clone();
+        spawn("mutex test", async move { drop(mutex.lock().await) })
+    };
+    executor::run_ready_tasks();
+    notify.notify_one();
+    drop(guard);
+    executor::run_ready_tasks();
+    assert!(gives_up.is_finished());
+    assert!(waiter.is_finished());
+}
+
+#[test_case]
+fn test_notify() {
+    use super::executor;
+    use alloc::sync::Arc;
+
+    let notify = Arc::new(Notify::new());
+    // the permit is kept for a later waiter
+    notify.notify_one();
+    crate::task::future::block_on(notify.notified());
+
+    let waiter = {
+        let notify = notify.clone();
+        super::spawn("notify test", async move { notify.notified().await })
+    };
+    executor::run_ready_tasks();
+    assert!(!waiter.is_finished());
+    notify.notify_waiters();
+    executor::run_ready_tasks();
+    assert!(waiter.is_finished());
+}
@@ -0,0 +1,232 @@
+//! Multi-producer, single-consumer channels.
+
+use crate::sync::{IrqSpinLock, WaitQueue};
+use alloc::{collections::VecDeque, sync::Arc};
+use core::fmt;
+use core::pin::Pin;
+use core::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use core::task::{Context, Poll};
+use futures_util::stream::Stream;
+
+/// The channel was closed; the value that could not be sent is returned.
+#[derive(PartialEq, Eq)]
+pub struct SendError<T>(pub T);
+
+impl<T> fmt::Debug for SendError<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("SendError(..)")
+    }
+}
+
+#[derive(PartialEq, Eq)]
+pub enum TrySendError<T> {
+    Full(T),
+    Closed(T),
+}
+
+impl<T> fmt::Debug for TrySendError<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TrySendError::Full(_) => f.write_str("Full(..)"),
+            TrySendError::Closed(_) => f.write_str("Closed(..)"),
+        }
+    }
+}
+
+struct Chan<T> {
+    queue: IrqSpinLock<VecDeque<T>>,
+    /// `None` for an unbounded channel.
+    capacity: Option<usize>,
+    senders: AtomicUsize,
+    receiver_closed: AtomicBool,
+    receiver: WaitQueue,
+    senders_waiting: WaitQueue,
+}
+
+impl<T> Chan<T> {
+    fn try_send(&self, value: T) -> Result<(), TrySendError<T>> {
+        if self.receiver_closed.load(Ordering::Acquire) {
+            return Err(TrySendError::Closed(value));
+        }
+        {
+            let mut queue = self.queue.lock();
+            if self
+                .capacity
+                .is_some_and(|capacity| queue.len() >= capacity)
+            {
+                return Err(TrySendError::Full(value));
+            }
+            queue.push_back(value);
+        }
+        self.receiver.notify_one();
+        Ok(())
+    }
+
+    fn try_recv(&self) -> Option<T> {
+        let value = self.queue.lock().pop_front();
+        if value.is_some() {
+            self.senders_waiting.notify_one();
+        }
+        value
+    }
+
+    fn is_closed(&self) -> bool {
+        self.senders.load(Ordering::Acquire) == 0
+    }
+}
+
+fn new<T>(capacity: Option<usize>) -> (Sender<T>, Receiver<T>) {
+    let chan = Arc::new(Chan {
+        queue: IrqSpinLock::new(VecDeque::with_capacity(capacity.unwrap_or(0))),
+        capacity,
+        senders: AtomicUsize::new(1),
+        receiver_closed: AtomicBool::new(false),
+        receiver: WaitQueue::new(),
+        senders_waiting: WaitQueue::new(),
+    });
+    (Sender { chan: chan.clone() }, Receiver { chan })
+}
+
+/// A channel holding up to `capacity` values. The buffer is allocated up
+/// front, so `try_send` can be used from interrupt handlers.
+pub fn channel<T>(capacity: usize) -> (Sender<T>, Receiver<T>) {
+    assert!(capacity > 0, "channel capacity must not be zero");
+    new(Some(capacity))
+}
+
+/// A channel whose senders never wait.
+pub fn unbounded_channel<T>() -> (Sender<T>, Receiver<T>) {
+    new(None)
+}
+
+pub struct Sender<T> {
+    chan: Arc<Chan<T>>,
+}
+
+impl<T> Sender<T> {
+    /// Sends `value`, waiting while the channel is full.
+    pub async fn send(&self, value: T) -> Result<(), SendError<T>> {
+        let mut value = Some(value);
+        let mut closed = None;
+        self.chan
+            .senders_waiting
+            .wait_until_async(|| match self.chan.try_send(value.take().unwrap()) {
+                Ok(()) => true,
+                Err(TrySendError::Full(rejected)) => {
+                    value = Some(rejected);
+                    false
+                }
+                Err(TrySendError::Closed(rejected)) => {
+                    closed = Some(rejected);
+                    true
+                }
+            })
+            .await;
+        match closed {
+            Some(value) => Err(SendError(value)),
+            None => Ok(()),
+        }
+    }
+
+    pub fn try_send(&self, value: T) -> Result<(), TrySendError<T>> {
+        self.chan.try_send(value)
+    }
+
+    pub fn is_closed(&self) -> bool {
+        self.chan.receiver_closed.load(Ordering::Acquire)
+    }
+}
+
+impl<T> Clone for Sender<T> {
+    fn clone(&self) -> Self {
+        self.chan.senders.fetch_add(1, Ordering::Relaxed);
+        Sender {
+            chan: self.chan.clone(),
+        }
+    }
+}
+
+impl<T> Drop for Sender<T> {
+    fn drop(&mut self) {
+        if self.chan.senders.fetch_sub(1, Ordering::AcqRel) == 1 {
+            self.chan.receiver.notify_all();
+        }
+    }
+}
+
+pub struct Receiver<T> {
+    chan: Arc<Chan<T>>,
+}
+
+impl<T> Receiver<T> {
+    /// Receives the next value, or `None` once every sender is gone and the
+    /// channel is empty.
+    pub async fn recv(&mut self) -> Option<T> {
+        core::future::poll_fn(|cx| self.poll_recv(cx)).await
+    }
+
+    pub fn try_recv(&mut self) -> Option<T> {
+        self.chan.try_recv()
+    }
+
+    pub fn poll_recv(&mut self, cx: &mut Context) -> Poll<Option<T>> {
+        let mut value = None;
+        let chan = &self.chan;
+        chan.receiver
+            .poll_until(cx, || {
+                value = chan.try_recv();
+                value.is_some() || chan.is_closed()
+            })
+            .map(|()| value.or_else(|| chan.try_recv()))
+    }
+
+    /// Stops the senders; values already sent can still be received.
+    pub fn close(&mut self) {
+        self.chan.receiver_closed.store(true, Ordering::Release);
+        self.chan.senders_waiting.notify_all();
+    }
+}
+
+impl<T> Drop for Receiver<T> {
+    fn drop(&mut self) {
+        self.close();
+    }
+}
+
+impl<T> Stream for Receiver<T> {
+    type Item = T;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Option<T>> {
+        self.get_mut().poll_recv(cx)
+    }
+}
+
+#[test_case]
+fn test_mpsc() {
+    use crate::task::{executor, future::block_on, spawn};
+
+    let (tx, mut rx) = channel(2);
+    let producer = spawn("mpsc test", async move {
+        for i in 0..10 {
+            tx.send(i).await.unwrap();
+        }
+    });
+    let consumer = spawn("mpsc test", async move {
+        let mut sum = 0;
+        while let Some(value) = rx.recv().await {
+            sum += value;
+        }
+        sum
+    });
+    while !consumer.is_finished() {
+        executor::run_ready_tasks();
+    }
+    assert!(producer.is_finished());
+    assert_eq!(block_on(consumer).unwrap(), 45);
+
+    let (tx, rx) = channel(1);
+    assert_eq!(tx.try_send(1), Ok(()));
+    assert_eq!(tx.try_send(2), Err(TrySendError::Full(2)));
+    drop(rx);
+    assert_eq!(tx.try_send(3), Err(TrySendError::Closed(3)));
+}
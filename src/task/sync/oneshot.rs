@@ -0,0 +1,122 @@
+//! Channels for sending a single value.
+
+use crate::sync::IrqSpinLock;
+use alloc::sync::Arc;
+use core::future::Future;
+use core::pin::Pin;
+use core::task::{Context, Poll, Waker};
+
+/// The sender was dropped without sending.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RecvError;
+
+struct State<T> {
+    value: Option<T>,
+    sender_dropped: bool,
+    receiver_dropped: bool,
+    waker: Option<Waker>,
+}
+
+pub fn channel<T>() -> (Sender<T>, Receiver<T>) {
+    let state = Arc::new(IrqSpinLock::new(State {
+        value: None,
+        sender_dropped: false,
+        receiver_dropped: false,
+        waker: None,
+    }));
+    (
+        Sender {
+            state: state.clone(),
+        },
+        Receiver { state },
+    )
+}
+
+pub struct Sender<T> {
+    state: Arc<IrqSpinLock<State<T>>>,
+}
+
+impl<T> Sender<T> {
+    /// Sends `value`, handing it back if the receiver is gone.
+    pub fn send(self, value: T) -> Result<(), T> {
+        let waker = {
+            let mut state = self.state.lock();
+            if state.receiver_dropped {
+                return Err(value);
+            }
+            state.value = Some(value);
+            state.waker.take()
+        };
+        if let Some(waker) = waker {
+            waker.wake();
+        }
+        Ok(())
+    }
+
+    pub fn is_closed(&self) -> bool {
+        self.state.lock().receiver_dropped
+    }
+}
+
+impl<T> Drop for Sender<T> {
+    fn drop(&mut self) {
+        let waker = {
+            let mut state = self.state.lock();
+            state.sender_dropped = true;
+            state.waker.take()
+        };
+        if let Some(waker) = waker {
+            waker.wake();
+        }
+    }
+}
+
+/// Completes with the sent value.
+pub struct Receiver<T> {
+    state: Arc<IrqSpinLock<State<T>>>,
+}
+
+impl<T> Receiver<T> {
+    pub fn try_recv(&mut self) -> Option<T> {
+        self.state.lock().value.take()
+    }
+}
+
+impl<T> Future for Receiver<T> {
+    type Output = Result<T, RecvError>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Self::Output> {
+        let mut state = self.state.lock();
+        if let Some(value) = state.value.take() {
+            return Poll::Ready(Ok(value));
+        }
+        if state.sender_dropped {
+            return Poll::Ready(Err(RecvError));
+        }
+        state.waker = Some(cx.waker().clone());
+        Poll::Pending
+    }
+}
+
+impl<T> Drop for Receiver<T> {
+    fn drop(&mut self) {
+        self.state.lock().receiver_dropped = true;
+    }
+}
+
+#[test_case]
+fn test_oneshot() {
+    use crate::task::future::block_on;
+
+    let (tx, rx) = channel();
+    tx.send(5).unwrap();
+    assert_eq!(block_on(rx), Ok(5));
+
+    let (tx, rx) = channel::<()>();
+    drop(tx);
+    assert_eq!(block_on(rx), Err(RecvError));
+
+    let (tx, rx) = channel();
+    drop(rx);
+    assert_eq!(tx.send(1), Err(1));
+}
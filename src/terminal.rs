@@ -1,6 +1,16 @@
 use crate::fs;
 
-use alloc::string::String;
+use crate::error::Result;
+use crate::pipe::{self, PipeReader};
+use crate::process::{self, Pid};
+use crate::sync::WaitQueue;
+use crate::syscall::{FileTable, OpenFile};
+use crate::thread::{self, Priority};
+use alloc::{
+    collections::{BTreeMap, VecDeque},
+    string::String,
+    vec::Vec,
+};
 use pc_keyboard::DecodedKey;
 use spin::Mutex;
 
@@ -10,18 +20,119 @@ static _ONLY_HLT: &[u8] = include_bytes!("../onlyhlt");
 
 static TERMINAL: Mutex<Terminal> = Mutex::new(Terminal::new());
 
+/// Console input of programs, handed over a line at a time.
+struct ProgramInput {
+    line: String,
+    /// Completed lines not read yet.
+    pending: VecDeque<u8>,
+}
+
+impl ProgramInput {
+    /// Drops input typed for the foreground job that it never read, so the
+    /// next job does not get it.
+    fn clear(&mut self) {
+        self.line.clear();
+        self.pending.clear();
+    }
+}
+
+static PROGRAM_INPUT: Mutex<ProgramInput> = Mutex::new(ProgramInput {
+    line: String::new(),
+    pending: VecDeque::new(),
+});
+static INPUT_READY: WaitQueue = WaitQueue::new();
+
+/// A command line the shell runs: one process, or several connected by pipes.
+struct Job {
+    processes: Vec<Pid>,
+    command: String,
+}
+
+/// The job typed characters go to instead of the shell, which shows no
+/// prompt meanwhile.
+static FOREGROUND: Mutex<Option<Pid>> = Mutex::new(None);
+/// Jobs started by the shell, by the PID of their first process.
+static JOBS: Mutex<BTreeMap<Pid, Job>> = Mutex::new(BTreeMap::new());
+
+/// Blocks until a line was typed and copies what fits of it into `buf`.
+/// Returns 0 if the thread is killed meanwhile.
+pub fn read_console(buf: &mut [u8]) -> usize {
+    INPUT_READY.wait_until(|| !PROGRAM_INPUT.lock().pending.is_empty() || thread::is_killed());
+    let mut input = PROGRAM_INPUT.lock();
+    let len = input.pending.len().min(buf.len());
+    for (byte, pending) in buf.iter_mut().zip(input.pending.drain(..len)) {
+        *byte = pending;
+    }
+    len
+}
+
+/// Hands `character` to the foreground job, if there is one.
+fn forward_to_program(character: char) -> bool {
+    let Some(pid) = *FOREGROUND.lock() else {
+        return false;
+    };
+    let mut input = PROGRAM_INPUT.lock();
+    match character {
+        // Ctrl+C
+        '\u{3}' => {
+            print!("^C");
+            kill_job(pid);
+        }
+        // Ctrl+Z
+        '\u{1a}' => {
+            *FOREGROUND.lock() = None;
+            input.clear();
+            print!("^Z\n[{}] continues in the background\n>", pid);
+        }
+        '\u{8}' => {
+            if input.line.pop().is_some() {
+                crate::vga_buffer::backspace();
+            }
+        }
+        '\n' => {
+            print!("\n");
+            let line = core::mem::take(&mut input.line);
+            input.pending.extend(line.bytes().chain(Some(b'\n')));
+            drop(input);
+            INPUT_READY.notify_all();
+        }
+        _ if character.is_control() => {}
+        _ => {
+            print!("{}", character);
+            input.line.push(character);
+        }
+    }
+    true
+}
+
 pub fn push_key(key: DecodedKey) {
+    if let DecodedKey::Unicode(character) = key {
+        if forward_to_program(character) {
+            return;
+        }
+    }
     let mut terminal = { TERMINAL.lock() };
     match key {
-        DecodedKey::Unicode(character) => match character as u8 {
-            8 => {
+        DecodedKey::Unicode(character) => match character {
+            '\u{8}' => {
                 crate::vga_buffer::backspace();
                 terminal.pop();
             }
-            10 => {
+            '\n' => {
                 print!("\n");
                 terminal.run();
             }
+            // Ctrl+C
+            '\u{3}' => {
+                print!("^C\n>");
+                terminal.buffer_clear();
+            }
+            // Ctrl+L
+            '\u{c}' => {
+                crate::vga_buffer::clear_screen();
+                print!(">{}", terminal.buffer);
+            }
+            _ if character.is_control() => {}
             _ => {
                 print!("{}", character);
                 terminal.push(character);
@@ -58,7 +169,10 @@ impl Terminal {
     pub fn run(&mut self) {
         self.execute();
         self.buffer_clear();
-        print!("\n>");
+        // the prompt comes back once the foreground job ends
+        if FOREGROUND.lock().is_none() {
+            print!("\n>");
+        }
     }
 
     pub fn buffer_clear(&mut self) {
@@ -75,9 +189,24 @@ impl Terminal {
 
         match command {
             "echo" => echo(commands),
+            "clear" => crate::vga_buffer::clear_screen(),
             "touch" => touch(commands),
             "find" => find(commands),
-            "onlyhlt" => exec(commands),
+            "dmesg" => dmesg(),
+            "loglevel" => loglevel(commands),
+            "kbdlayout" => kbdlayout(commands),
+            "acpi" => acpi(),
+            "date" => print!("{}", crate::rtc::now()),
+            "uptime" => uptime(),
+            "ps" => ps(),
+            "tasks" => tasks(),
+            "shutdown" => crate::power::shutdown(),
+            "reboot" => crate::power::reboot(),
+            "onlyhlt" => onlyhlt(),
+            "exec" => exec(commands),
+            "jobs" => jobs(),
+            "fg" => fg(commands),
+            "kill" => kill(commands),
             _ => print!("command not found: {}", command),
         }
     }
@@ -104,7 +233,11 @@ fn find<'a>(mut commands: impl Iterator<Item = &'a str>) {
                 let path = fs::Path::from_str(fname);
                 fs::handle_file(
                     |file| match file {
-                        Ok(file) => print!("{}", file.name()),
+                        Ok(file) => print!(
+                            "{} {}",
+                            file.name(),
+                            crate::rtc::DateTime::from_unix(file.created())
+                        ),
                         Err(_) => print!("`{}`: No such file", fname),
                     },
                     path,
@@ -135,7 +268,298 @@ fn find<'a>(mut commands: impl Iterator<Item = &'a str>) {
     }
 }
 
-fn exec<'a>(mut _commands: impl Iterator<Item = &'a str>) {
+fn dmesg() {
+    crate::log::for_each_record(|record| print!("{}\n", record));
+}
+
+fn loglevel<'a>(mut commands: impl Iterator<Item = &'a str>) {
+    use crate::log::{self, Level, Sink};
+
+    let usage = "usage: loglevel [console|serial|<module>] error|warn|info|debug|trace|off";
+    let (target, level) = match (commands.next(), commands.next()) {
+        (Some(level), None) => (None, level),
+        (Some(target), Some(level)) => (Some(target), level),
+        _ => return print!("{}", usage),
+    };
+    let level = match level {
+        "off" => None,
+        level => match level.parse::<Level>() {
+            Ok(level) => Some(level),
+            Err(()) => return print!("{}", usage),
+        },
+    };
+    match target {
+        Some("console") => log::set_sink_level(Sink::Console, level),
+        Some("serial") => log::set_sink_level(Sink::Serial, level),
+        target => log::set_level(target, level),
+    }
+}
+
+fn kbdlayout<'a>(mut commands: impl Iterator<Item = &'a str>) {
+    use crate::keyboard::{self, Layout};
+
+    match commands.next() {
+        Some(name) => match Layout::from_name(name) {
+            Some(layout) => keyboard::set_layout(layout),
+            None => print!("unknown layout: {}", name),
+        },
+        None => {
+            print!("current: {}\navailable:", keyboard::layout().name());
+            for layout in Layout::ALL {
+                print!(" {}", layout.name());
+            }
+        }
+    }
+}
+
+fn uptime() {
+    let seconds = crate::time::uptime().as_secs();
+    let (days, hours) = (seconds / 86_400, seconds / 3600 % 24);
+    let (minutes, seconds) = (seconds / 60 % 60, seconds % 60);
+    if days > 0 {
+        print!("up {} days, ", days);
+    } else {
+        print!("up ");
+    }
+    print!("{:02}:{:02}:{:02}", hours, minutes, seconds);
+}
+
+fn ps() {
+    print!(
+        "{:>4} {:<16} {:<6} {:<8} {:>8}",
+        "ID", "NAME", "PRIO", "STATE", "TICKS"
+    );
+    for thread in crate::thread::threads() {
+        print!(
+            "\n{:>4} {:<16} {:<6} {:<8} {:>8}",
+            thread.id,
+            thread.name,
+            thread.priority.name(),
+            thread.state.name(),
+            thread.ticks
+        );
+    }
+}
+
+fn tasks() {
+    print!(
+        "{:>4} {:<16} {:<6} {:>8} {:>8}",
+        "ID", "NAME", "PRIO", "POLLS", "TICKS"
+    );
+    for task in crate::task::executor::tasks() {
+        print!(
+            "\n{:>4} {:<16} {:<6} {:>8} {:>8}",
+            task.id,
+            task.name,
+            task.priority.name(),
+            task.polls,
+            task.ticks
+        );
+    }
+}
+
+fn acpi() {
+    let tables = crate::acpi::tables();
+    if tables.is_empty() {
+        return print!("no ACPI tables found");
+    }
+    print!("SIG  ADDRESS     LENGTH REV OEM");
+    for table in tables {
+        let header = &table.header;
+        let length = header.length;
+        print!(
+            "\n{} {:#010x} {:>6} {:>3} {}",
+            header.signature(),
+            table.address,
+            length,
+            header.revision,
+            header.oem_id()
+        );
+        if !table.checksum_ok {
+            print!(" (bad checksum)");
+        }
+    }
+}
+
+/// Halts forever in a thread of its own, so the shell keeps running.
+fn onlyhlt() {
     let f = crate::exec::compile_onlyhlt();
-    f();
+    if let Err(err) = thread::spawn("onlyhlt", Priority::Low, f) {
+        print!("onlyhlt: {}", err.kind());
+    }
+}
+
+/// Starts a program from the filesystem as a job, in the foreground unless
+/// the command ends with `&`. Programs separated by `|` run at once, each
+/// reading what the one before writes.
+fn exec<'a>(commands: impl Iterator<Item = &'a str>) {
+    let mut words: Vec<&str> = commands.filter(|word| !word.is_empty()).collect();
+    let background = words.last() == Some(&"&");
+    if background {
+        words.pop();
+    }
+    let pipeline: Vec<&[&str]> = words.split(|&word| word == "|").collect();
+    if pipeline.iter().any(|args| args.is_empty()) {
+        print!("usage: exec path [args...] [| path [args...]]... [&]");
+        return;
+    }
+
+    let (processes, result) = spawn_pipeline(&pipeline);
+    let Some(&id) = processes.first() else {
+        if let Err(err) = result {
+            print!("exec: {}", err.kind());
+        }
+        return;
+    };
+    if let Err(err) = result {
+        // the rest would wait for the missing one forever
+        print!("exec: {}", err.kind());
+        for &pid in &processes {
+            let _ = process::kill(pid);
+        }
+    }
+    let job = Job {
+        processes,
+        command: words.join(" "),
+    };
+    JOBS.lock().insert(id, job);
+    if background {
+        print!("[{}]", id);
+    } else {
+        *FOREGROUND.lock() = Some(id);
+    }
+    crate::task::spawn("job", wait_for_job(id));
+}
+
+/// Starts the processes of a pipeline, up to the first that fails.
+fn spawn_pipeline(pipeline: &[&[&str]]) -> (Vec<Pid>, Result<()>) {
+    let mut processes = Vec::new();
+    let mut input = None;
+    for (i, args) in pipeline.iter().enumerate() {
+        let last = i + 1 == pipeline.len();
+        match spawn_stage(args, input.take(), last) {
+            Ok((pid, output)) => {
+                processes.push(pid);
+                input = output;
+            }
+            Err(err) => return (processes, Err(err)),
+        }
+    }
+    (processes, Ok(()))
+}
+
+/// Starts one process of a pipeline, reading from `input` if given, and
+/// returns the end of the pipe it writes to unless it is the `last`.
+fn spawn_stage(
+    args: &[&str],
+    input: Option<PipeReader>,
+    last: bool,
+) -> Result<(Pid, Option<PipeReader>)> {
+    let mut files = FileTable::with_console();
+    if let Some(reader) = input {
+        files.set(0, OpenFile::PipeRead(reader))?;
+    }
+    let mut output = None;
+    if !last {
+        let (reader, writer) = pipe::pipe();
+        files.set(1, OpenFile::PipeWrite(writer))?;
+        output = Some(reader);
+    }
+    let pid = process::spawn_with_files(None, args[0], args, files)?;
+    Ok((pid, output))
+}
+
+/// Reaps the processes of a job once they exit, and gives the console back to
+/// the shell if it was in the foreground.
+async fn wait_for_job(id: Pid) {
+    let processes = match JOBS.lock().get(&id) {
+        Some(job) => job.processes.clone(),
+        None => return,
+    };
+    // the status of a pipeline is that of its last process
+    let mut code = 0;
+    for pid in processes {
+        if let Ok(status) = process::wait_async(None, pid).await {
+            code = status;
+        }
+    }
+    let command = JOBS
+        .lock()
+        .remove(&id)
+        .map(|job| job.command)
+        .unwrap_or_default();
+    let foreground = {
+        let mut foreground = FOREGROUND.lock();
+        let was = *foreground == Some(id);
+        if was {
+            *foreground = None;
+        }
+        was
+    };
+    if foreground {
+        PROGRAM_INPUT.lock().clear();
+        if code != 0 {
+            print!("\nexit code {}", code);
+        }
+        print!("\n>");
+    } else {
+        let terminal = TERMINAL.lock();
+        print!(
+            "\n[{}] done ({}) {}\n>{}",
+            id, code, command, terminal.buffer
+        );
+    }
+}
+
+fn kill_job(id: Pid) {
+    let processes = match JOBS.lock().get(&id) {
+        Some(job) => job.processes.clone(),
+        None => return,
+    };
+    for pid in processes {
+        // those already exited are only waiting to be reaped
+        let _ = process::kill(pid);
+    }
+}
+
+fn jobs() {
+    let foreground = *FOREGROUND.lock();
+    let jobs = JOBS.lock();
+    if jobs.is_empty() {
+        return print!("no jobs");
+    }
+    let lines: Vec<String> = jobs
+        .iter()
+        .map(|(&id, job)| {
+            let place = if Some(id) == foreground { "fg" } else { "bg" };
+            alloc::format!("[{}] {} {}", id, place, job.command)
+        })
+        .collect();
+    print!("{}", lines.join("\n"));
+}
+
+fn job_argument<'a>(mut commands: impl Iterator<Item = &'a str>, usage: &str) -> Option<Pid> {
+    let Some(id) = commands.next().and_then(|id| id.parse().ok()) else {
+        print!("usage: {} job", usage);
+        return None;
+    };
+    let id = Pid::from_u64(id);
+    if !JOBS.lock().contains_key(&id) {
+        print!("{}: no such job: {}", usage, id);
+        return None;
+    }
+    Some(id)
+}
+
+/// Moves a background job to the foreground.
+fn fg<'a>(commands: impl Iterator<Item = &'a str>) {
+    if let Some(id) = job_argument(commands, "fg") {
+        *FOREGROUND.lock() = Some(id);
+    }
+}
+
+fn kill<'a>(commands: impl Iterator<Item = &'a str>) {
+    if let Some(id) = job_argument(commands, "kill") {
+        kill_job(id);
+    }
 }
@@ -0,0 +1,867 @@
+//! Preemptive kernel threads.
+//!
+//! Threads are `ContextTask`s kept in a run queue per priority level. The
+//! highest priority level with a runnable thread runs round-robin, each thread
+//! for up to `TIME_SLICE` timer ticks before the timer interrupt switches to
+//! the next one. A level passed over `STARVATION_LIMIT` times still gets a
+//! turn, so a lower priority thread holding a spinlock that a higher priority
+//! one spins on gets to release it. The boot thread, which runs the async
+//! executor, becomes the `main` thread in `init`.
+
+use crate::error::Result;
+use crate::process::Pid;
+use crate::usermode::{self, AddressSpace};
+use crate::{gdt, interrupts, stack, switch_task, ContextTask, TaskContext};
+use alloc::{
+    boxed::Box,
+    collections::{BTreeMap, VecDeque},
+    string::String,
+    sync::Arc,
+    vec::Vec,
+};
+use core::arch::asm;
+use core::fmt;
+use core::sync::atomic::{AtomicU64, Ordering};
+use spin::Mutex;
+use x86_64::instructions::interrupts::without_interrupts;
+use x86_64::registers::control::Cr3;
+use x86_64::VirtAddr;
+
+/// Timer ticks a thread runs before others of the same priority get a turn.
+const TIME_SLICE: u64 = 10;
+
+/// Switches to higher priority threads a level with runnable threads lets
+/// through before one of its own gets a time slice. The idle level waits
+/// regardless.
+const STARVATION_LIMIT: u32 = 8;
+
+/// Stack size of threads started with `spawn`.
+pub const DEFAULT_STACK_SIZE: usize = 64 * 1024;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct ThreadId(u64);
+
+impl ThreadId {
+    fn new() -> Self {
+        static NEXT_ID: AtomicU64 = AtomicU64::new(0);
+        ThreadId(NEXT_ID.fetch_add(1, Ordering::Relaxed))
+    }
+
+    pub fn as_u64(self) -> u64 {
+        self.0
+    }
+}
+
+impl fmt::Display for ThreadId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Display::fmt(&self.0, f)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Priority {
+    /// Only runs when nothing else can.
+    Idle,
+    Low,
+    Normal,
+    High,
+}
+
+const PRIORITY_LEVELS: usize = 4;
+
+impl Priority {
+    pub fn name(self) -> &'static str {
+        match self {
+            Priority::Idle => "idle",
+            Priority::Low => "low",
+            Priority::Normal => "normal",
+            Priority::High => "high",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum State {
+    Running,
+    Ready,
+    /// Waiting for the tick count to reach the given value.
+    Sleeping(u64),
+    Blocked,
+    Exited,
+}
+
+impl State {
+    pub fn name(self) -> &'static str {
+        match self {
+            State::Running => "running",
+            State::Ready => "ready",
+            State::Sleeping(_) => "sleeping",
+            State::Blocked => "blocked",
+            State::Exited => "exited",
+        }
+    }
+}
+
+struct Thread {
+    name: String,
+    priority: Priority,
+    state: State,
+    task: ContextTask,
+    /// Timer ticks spent running.
+    ticks: u64,
+    /// Set by `unblock` on a thread that was not blocked yet, so its next
+    /// `block_current` returns right away.
+    wakeup: bool,
+    joiner: Option<ThreadId>,
+    detached: bool,
+    /// The address space of a user thread, kept alive while it runs.
+    address_space: Option<Arc<AddressSpace>>,
+    /// The process a user thread belongs to.
+    process: Option<Pid>,
+    /// Set by `kill`; the thread ends once it is about to return to user mode.
+    killed: bool,
+    /// Passed to `exit_with`, returned by `JoinHandle::join`.
+    exit_code: i32,
+}
+
+/// A snapshot of a thread, for listing.
+#[derive(Debug, Clone)]
+pub struct ThreadInfo {
+    pub id: ThreadId,
+    pub name: String,
+    pub priority: Priority,
+    pub state: State,
+    pub ticks: u64,
+}
+
+struct Scheduler {
+    threads: BTreeMap<ThreadId, Thread>,
+    ready: [VecDeque<ThreadId>; PRIORITY_LEVELS],
+    current: ThreadId,
+    slice_left: u64,
+    /// Per level, switches to higher priority threads since one of its own
+    /// last ran or it had none ready.
+    waited: [u32; PRIORITY_LEVELS],
+    /// The current thread got its turn for having waited, so higher priority
+    /// threads do not preempt it before its slice is used up.
+    aged: bool,
+    /// Exited, detached threads whose stacks are freed once another thread runs.
+    dead: Vec<ThreadId>,
+    /// Page table of kernel threads, whichever address space spawns them.
+    kernel_cr3: u64,
+}
+
+static SCHEDULER: Mutex<Option<Scheduler>> = Mutex::new(None);
+
+impl Scheduler {
+    fn make_ready(&mut self, id: ThreadId) {
+        if let Some(thread) = self.threads.get_mut(&id) {
+            thread.state = State::Ready;
+            self.ready[thread.priority as usize].push_back(id);
+        }
+    }
+
+    fn unblock(&mut self, id: ThreadId) {
+        let Some(thread) = self.threads.get_mut(&id) else {
+            return;
+        };
+        match thread.state {
+            State::Blocked => self.make_ready(id),
+            State::Exited => {}
+            _ => thread.wakeup = true,
+        }
+    }
+
+    fn pick_next(&mut self) -> Option<ThreadId> {
+        // threads that stopped being ready while queued
+        let threads = &self.threads;
+        for queue in &mut self.ready {
+            queue.retain(|id| threads.get(id).is_some_and(|t| t.state == State::Ready));
+        }
+
+        let ready = |level: &usize| !self.ready[*level].is_empty();
+        let highest = (0..PRIORITY_LEVELS).rev().find(ready)?;
+        let level = (Priority::Low as usize..highest)
+            .rev()
+            .filter(ready)
+            .find(|&level| self.waited[level] >= STARVATION_LIMIT)
+            .unwrap_or(highest);
+        for (i, waited) in self.waited.iter_mut().enumerate() {
+            if i == level || self.ready[i].is_empty() {
+                *waited = 0;
+            } else if i < level {
+                *waited = waited.saturating_add(1);
+            }
+        }
+        self.aged = level != highest;
+        self.ready[level].pop_front()
+    }
+
+    fn higher_priority_ready(&self) -> bool {
+        let current = self.threads[&self.current].priority as usize;
+        self.ready[current + 1..]
+            .iter()
+            .any(|queue| !queue.is_empty())
+    }
+
+    /// Leaves the current thread in `state` and makes the next runnable thread
+    /// current. Returns the contexts to switch between, or `None` if the
+    /// current thread keeps running.
+    fn switch(&mut self, state: State) -> Option<(*mut TaskContext, *const TaskContext)> {
+        let current = self.current;
+        if state == State::Ready {
+            self.make_ready(current);
+        } else {
+            self.threads.get_mut(&current)?.state = state;
+        }
+        // the idle thread is always ready, unless it is the one running
+        self.aged = false;
+        let next = self.pick_next().unwrap_or(current);
+        self.slice_left = TIME_SLICE;
+
+        let next_thread = self.threads.get_mut(&next)?;
+        next_thread.state = State::Running;
+        if next == current {
+            return None;
+        }
+        // interrupts in user mode arrive on the kernel stack of the thread
+        if let Some(stack) = &next_thread.task.stack {
+            gdt::set_kernel_stack(stack.top());
+        }
+        let next_context = &*next_thread.task.ctx as *const TaskContext;
+        let current_context = &mut *self.threads.get_mut(&current)?.task.ctx as *mut TaskContext;
+        self.current = next;
+        Some((current_context, next_context))
+    }
+
+    fn wake_sleepers(&mut self, now: u64) {
+        let due: Vec<ThreadId> = self
+            .threads
+            .iter()
+            .filter(|(_, thread)| matches!(thread.state, State::Sleeping(until) if until <= now))
+            .map(|(&id, _)| id)
+            .collect();
+        for id in due {
+            self.make_ready(id);
+        }
+    }
+}
+
+/// Turns the running code into the `main` thread and starts the idle thread.
+pub fn init() -> Result<()> {
+    let main = ThreadId::new();
+    let mut threads = BTreeMap::new();
+    threads.insert(
+        main,
+        Thread {
+            name: "main".into(),
+            priority: Priority::Normal,
+            state: State::Running,
+            // saved into on the first switch away
+            task: ContextTask {
+                ctx: Box::new(unsafe { core::mem::zeroed() }),
+                stack: None,
+            },
+            ticks: 0,
+            wakeup: false,
+            joiner: None,
+            detached: true,
+            address_space: None,
+            process: None,
+            killed: false,
+            exit_code: 0,
+        },
+    );
+
+    without_interrupts(|| {
+        *SCHEDULER.lock() = Some(Scheduler {
+            threads,
+            ready: Default::default(),
+            current: main,
+            slice_left: TIME_SLICE,
+            waited: [0; PRIORITY_LEVELS],
+            aged: false,
+            dead: Vec::new(),
+            kernel_cr3: Cr3::read().0.start_address().as_u64(),
+        })
+    });
+
+    spawn("idle", Priority::Idle, || loop {
+        x86_64::instructions::hlt();
+        // hand the CPU to whatever the interrupt made runnable
+        yield_now();
+    })?;
+    Ok(())
+}
+
+/// Owns a thread; dropping it detaches the thread.
+pub struct JoinHandle {
+    id: ThreadId,
+}
+
+impl JoinHandle {
+    pub fn id(&self) -> ThreadId {
+        self.id
+    }
+
+    /// Blocks until the thread has exited and returns its exit code.
+    pub fn join(self) -> i32 {
+        loop {
+            let exited = without_interrupts(|| {
+                let mut guard = SCHEDULER.lock();
+                let scheduler = guard.as_mut()?;
+                let me = scheduler.current;
+                let thread = scheduler.threads.get_mut(&self.id)?;
+                if thread.state == State::Exited {
+                    return scheduler.threads.remove(&self.id);
+                }
+                thread.joiner = Some(me);
+                None
+            });
+            // a thread that is gone was already joined through another path
+            match exited {
+                Some(thread) => return thread.exit_code,
+                None if !exists(self.id) => return 0,
+                None => block_current(),
+            }
+        }
+    }
+}
+
+impl Drop for JoinHandle {
+    fn drop(&mut self) {
+        let exited = without_interrupts(|| {
+            let mut guard = SCHEDULER.lock();
+            let scheduler = guard.as_mut()?;
+            let thread = scheduler.threads.get_mut(&self.id)?;
+            if thread.state == State::Exited {
+                return scheduler.threads.remove(&self.id);
+            }
+            thread.detached = true;
+            None
+        });
+        drop(exited);
+    }
+}
+
+fn exists(id: ThreadId) -> bool {
+    without_interrupts(|| {
+        SCHEDULER
+            .lock()
+            .as_ref()
+            .is_some_and(|scheduler| scheduler.threads.contains_key(&id))
+    })
+}
+
+type ThreadMain = Box<dyn FnOnce() + Send>;
+
+extern "C" fn thread_entry(main: u64, _: u64) {
+    let main = unsafe { Box::from_raw(main as *mut ThreadMain) };
+    main();
+    exit()
+}
+
+/// Starts a kernel thread running `f` on a stack of `DEFAULT_STACK_SIZE` bytes.
+pub fn spawn(
+    name: &str,
+    priority: Priority,
+    f: impl FnOnce() + Send + 'static,
+) -> Result<JoinHandle> {
+    spawn_with_stack_size(name, priority, DEFAULT_STACK_SIZE, f)
+}
+
+/// Starts a kernel thread running `f` on a stack of at least `stack_size` bytes.
+pub fn spawn_with_stack_size(
+    name: &str,
+    priority: Priority,
+    stack_size: usize,
+    f: impl FnOnce() + Send + 'static,
+) -> Result<JoinHandle> {
+    spawn_in(name, priority, stack_size, None, None, true, f)
+}
+
+/// Starts a thread that runs `entry` in user mode, in `space`, with the stack
+/// below `stack_top`, as part of `process` if given. Faults in user mode end
+/// the thread.
+///
+/// The thread starts blocked, so the caller can finish setting up `process`
+/// before it runs; `unblock` starts it.
+pub fn spawn_user(
+    name: &str,
+    priority: Priority,
+    process: Option<Pid>,
+    space: Arc<AddressSpace>,
+    entry: VirtAddr,
+    stack_top: VirtAddr,
+) -> Result<JoinHandle> {
+    spawn_in(
+        name,
+        priority,
+        DEFAULT_STACK_SIZE,
+        Some(space),
+        process,
+        false,
+        move || unsafe { usermode::enter(entry, stack_top) },
+    )
+}
+
+fn spawn_in(
+    name: &str,
+    priority: Priority,
+    stack_size: usize,
+    address_space: Option<Arc<AddressSpace>>,
+    process: Option<Pid>,
+    ready: bool,
+    f: impl FnOnce() + Send + 'static,
+) -> Result<JoinHandle> {
+    let main = Box::into_raw(Box::new(Box::new(f) as ThreadMain));
+    let task = match ContextTask::with_stack_size(thread_entry, main as u64, 0, stack_size) {
+        Ok(task) => task,
+        Err(err) => {
+            drop(unsafe { Box::from_raw(main) });
+            return Err(err);
+        }
+    };
+    let id = ThreadId::new();
+    let mut thread = Thread {
+        name: name.into(),
+        priority,
+        state: if ready { State::Ready } else { State::Blocked },
+        task,
+        ticks: 0,
+        wakeup: false,
+        joiner: None,
+        detached: false,
+        address_space,
+        process,
+        killed: false,
+        exit_code: 0,
+    };
+
+    without_interrupts(|| {
+        let mut guard = SCHEDULER.lock();
+        let scheduler = guard.as_mut().expect("scheduler not initialized");
+        thread.task.ctx.cr3 = match &thread.address_space {
+            Some(space) => space.frame().start_address().as_u64(),
+            None => scheduler.kernel_cr3,
+        };
+        scheduler.threads.insert(id, thread);
+        if ready {
+            scheduler.make_ready(id);
+        }
+    });
+    Ok(JoinHandle { id })
+}
+
+pub fn current() -> Option<ThreadId> {
+    without_interrupts(|| SCHEDULER.lock().as_ref().map(|scheduler| scheduler.current))
+}
+
+/// Switches away from the current thread, leaving it in `state`.
+fn reschedule(state: State) {
+    without_interrupts(|| {
+        let switch = match SCHEDULER.lock().as_mut() {
+            Some(scheduler) => scheduler.switch(state),
+            None => None,
+        };
+        if let Some((current, next)) = switch {
+            // the lock is released, and interrupts stay off until we are back
+            unsafe { switch_task(&*next, &*current) };
+        }
+    });
+    reap();
+}
+
+/// Frees the stacks of detached threads that have exited.
+fn reap() {
+    let dead: Vec<Thread> = without_interrupts(|| {
+        let mut guard = SCHEDULER.lock();
+        let Some(scheduler) = guard.as_mut() else {
+            return Vec::new();
+        };
+        let current = scheduler.current;
+        let ids: Vec<ThreadId> = scheduler
+            .dead
+            .iter()
+            .copied()
+            .filter(|&id| id != current)
+            .collect();
+        scheduler.dead.retain(|&id| id == current);
+        ids.iter()
+            .filter_map(|id| scheduler.threads.remove(id))
+            .collect()
+    });
+    drop(dead);
+}
+
+/// Lets the other threads of the same or higher priority run.
+pub fn yield_now() {
+    reschedule(State::Ready);
+}
+
+/// Yields if a thread other than the idle thread is waiting to run. Returns
+/// whether it did.
+pub fn yield_if_others_ready() -> bool {
+    let others = without_interrupts(|| {
+        SCHEDULER.lock().as_ref().is_some_and(|scheduler| {
+            scheduler.ready[Priority::Idle as usize + 1..]
+                .iter()
+                .any(|queue| !queue.is_empty())
+        })
+    });
+    if others {
+        yield_now();
+    }
+    others
+}
+
+/// Blocks the current thread for at least `duration`, at the resolution of the
+/// tick rate, or until it is killed.
+pub fn sleep(duration: crate::time::Duration) {
+    let ticks = crate::time::duration_to_ticks(duration).saturating_add(1);
+    reschedule(State::Sleeping(interrupts::ticks().saturating_add(ticks)));
+}
+
+/// Blocks the current thread until `unblock` is called for it. Returns right
+/// away if `unblock` was called since the last time it blocked.
+pub fn block_current() {
+    let woken = without_interrupts(|| {
+        let mut guard = SCHEDULER.lock();
+        let Some(scheduler) = guard.as_mut() else {
+            return true;
+        };
+        let current = scheduler.current;
+        let thread = scheduler.threads.get_mut(&current).unwrap();
+        core::mem::take(&mut thread.wakeup)
+    });
+    if !woken {
+        reschedule(State::Blocked);
+    }
+}
+
+/// Makes a blocked thread runnable again.
+pub fn unblock(id: ThreadId) {
+    without_interrupts(|| {
+        let mut guard = SCHEDULER.lock();
+        let Some(scheduler) = guard.as_mut() else {
+            return;
+        };
+        scheduler.unblock(id);
+    });
+}
+
+/// Ends the current thread.
+pub fn exit() -> ! {
+    exit_with(0)
+}
+
+/// Ends the current thread with `code` for its joiner.
+pub fn exit_with(code: i32) -> ! {
+    x86_64::instructions::interrupts::disable();
+    let switch = {
+        let mut guard = SCHEDULER.lock();
+        let scheduler = guard.as_mut().expect("scheduler not initialized");
+        let current = scheduler.current;
+        let thread = scheduler.threads.get_mut(&current).unwrap();
+        thread.exit_code = code;
+        let (joiner, detached) = (thread.joiner, thread.detached);
+        if let Some(joiner) = joiner {
+            scheduler.unblock(joiner);
+        }
+        if detached {
+            scheduler.dead.push(current);
+        }
+        scheduler.switch(State::Exited)
+    };
+    if let Some((current, next)) = switch {
+        unsafe { switch_task(&*next, &*current) };
+    }
+    unreachable!("exited thread was resumed");
+}
+
+/// The address space of the current thread, if it is a user thread.
+pub fn address_space() -> Option<Arc<AddressSpace>> {
+    with_current(|thread| thread.address_space.clone())
+}
+
+/// The process the current thread belongs to.
+pub fn current_process() -> Option<Pid> {
+    with_current(|thread| thread.process)
+}
+
+/// Marks a thread to end before it next runs in user mode, and wakes it if it
+/// sleeps or is blocked, so interruptible waits notice.
+pub fn kill(id: ThreadId) {
+    without_interrupts(|| {
+        let mut guard = SCHEDULER.lock();
+        let Some(scheduler) = guard.as_mut() else {
+            return;
+        };
+        let Some(thread) = scheduler.threads.get_mut(&id) else {
+            return;
+        };
+        thread.killed = true;
+        if matches!(thread.state, State::Sleeping(_)) {
+            scheduler.make_ready(id);
+        } else {
+            scheduler.unblock(id);
+        }
+    });
+}
+
+/// Whether the current thread was killed.
+pub fn is_killed() -> bool {
+    with_current(|thread| Some(thread.killed)).unwrap_or(false)
+}
+
+fn with_current<T>(f: impl FnOnce(&Thread) -> Option<T>) -> Option<T> {
+    without_interrupts(|| {
+        let guard = SCHEDULER.lock();
+        let scheduler = guard.as_ref()?;
+        f(scheduler.threads.get(&scheduler.current)?)
+    })
+}
+
+pub fn set_priority(id: ThreadId, priority: Priority) {
+    without_interrupts(|| {
+        let mut guard = SCHEDULER.lock();
+        let Some(scheduler) = guard.as_mut() else {
+            return;
+        };
+        let Some(thread) = scheduler.threads.get_mut(&id) else {
+            return;
+        };
+        let previous = core::mem::replace(&mut thread.priority, priority);
+        if thread.state == State::Ready && previous != priority {
+            scheduler.ready[previous as usize].retain(|&ready| ready != id);
+            scheduler.ready[priority as usize].push_back(id);
+        }
+    });
+}
+
+/// The thread whose stack guard page contains `addr`. Called from the double
+/// fault handler, so it gives up if the scheduler is locked.
+pub fn stack_overflowed(addr: x86_64::VirtAddr) -> Option<(ThreadId, String)> {
+    if !stack::is_guard_page(addr) {
+        return None;
+    }
+    let guard = SCHEDULER.try_lock()?;
+    let (&id, thread) = guard.as_ref()?.threads.iter().find(|(_, thread)| {
+        thread
+            .task
+            .stack
+            .as_ref()
+            .is_some_and(|stack| stack.contains(addr))
+    })?;
+    Some((id, thread.name.clone()))
+}
+
+pub fn threads() -> Vec<ThreadInfo> {
+    without_interrupts(|| {
+        let guard = SCHEDULER.lock();
+        let Some(scheduler) = guard.as_ref() else {
+            return Vec::new();
+        };
+        scheduler
+            .threads
+            .iter()
+            .map(|(&id, thread)| ThreadInfo {
+                id,
+                name: thread.name.clone(),
+                priority: thread.priority,
+                state: thread.state,
+                ticks: thread.ticks,
+            })
+            .collect()
+    })
+}
+
+/// General purpose registers pushed by the timer interrupt entry, followed by
+/// the frame the CPU pushed.
+#[derive(Debug)]
+#[repr(C)]
+pub struct InterruptedRegisters {
+    pub r15: u64,
+    pub r14: u64,
+    pub r13: u64,
+    pub r12: u64,
+    pub r11: u64,
+    pub r10: u64,
+    pub r9: u64,
+    pub r8: u64,
+    pub rbp: u64,
+    pub rdi: u64,
+    pub rsi: u64,
+    pub rdx: u64,
+    pub rcx: u64,
+    pub rbx: u64,
+    pub rax: u64,
+    pub rip: u64,
+    pub cs: u64,
+    pub rflags: u64,
+    pub rsp: u64,
+    pub ss: u64,
+}
+
+/// Called from the timer interrupt. Accounts the tick to the running thread
+/// and, once its time slice is used up, switches threads by swapping the
+/// registers the interrupt returns to.
+pub(crate) fn preempt(registers: &mut InterruptedRegisters) {
+    let switch = {
+        // the lock is only taken with interrupts off, so this fails only if
+        // the interrupt arrived while the scheduler was being set up
+        let Some(mut guard) = SCHEDULER.try_lock() else {
+            return;
+        };
+        let Some(scheduler) = guard.as_mut() else {
+            return;
+        };
+        scheduler.wake_sleepers(interrupts::ticks());
+        let current = scheduler.current;
+        if let Some(thread) = scheduler.threads.get_mut(&current) {
+            thread.ticks += 1;
+        }
+        scheduler.slice_left = scheduler.slice_left.saturating_sub(1);
+        if scheduler.slice_left > 0 && (scheduler.aged || !scheduler.higher_priority_ready()) {
+            return;
+        }
+        scheduler.switch(State::Ready)
+    };
+
+    if let Some((current, next)) = switch {
+        unsafe {
+            save_interrupted(&mut *current, registers);
+            restore_interrupted(&*next, registers);
+        }
+    }
+}
+
+unsafe fn save_interrupted(context: &mut TaskContext, registers: &InterruptedRegisters) {
+    context.rax = registers.rax;
+    context.rbx = registers.rbx;
+    context.rcx = registers.rcx;
+    context.rdx = registers.rdx;
+    context.rdi = registers.rdi;
+    context.rsi = registers.rsi;
+    context.rbp = registers.rbp;
+    context.r8 = registers.r8;
+    context.r9 = registers.r9;
+    context.r10 = registers.r10;
+    context.r11 = registers.r11;
+    context.r12 = registers.r12;
+    context.r13 = registers.r13;
+    context.r14 = registers.r14;
+    context.r15 = registers.r15;
+    context.rip = registers.rip;
+    context.cs = registers.cs;
+    context.rflags = registers.rflags;
+    context.rsp = registers.rsp;
+    context.ss = registers.ss;
+    context.cr3 = Cr3::read().0.start_address().as_u64();
+    unsafe {
+        asm!("mov {:x}, fs", out(reg) context.fs, options(nomem, nostack));
+        asm!("mov {:x}, gs", out(reg) context.gs, options(nomem, nostack));
+        asm!("fxsave [{}]", in(reg) context.fxsave_area.as_mut_ptr(), options(nostack));
+    }
+}
+
+unsafe fn restore_interrupted(context: &TaskContext, registers: &mut InterruptedRegisters) {
+    unsafe {
+        asm!("fxrstor [{}]", in(reg) context.fxsave_area.as_ptr(), options(nostack));
+        if Cr3::read().0.start_address().as_u64() != context.cr3 {
+            asm!("mov cr3, {}", in(reg) context.cr3, options(nostack));
+        }
+        asm!("mov fs, {:x}", in(reg) context.fs, options(nomem, nostack));
+        asm!("mov gs, {:x}", in(reg) context.gs, options(nomem, nostack));
+    }
+    registers.rax = context.rax;
+    registers.rbx = context.rbx;
+    registers.rcx = context.rcx;
+    registers.rdx = context.rdx;
+    registers.rdi = context.rdi;
+    registers.rsi = context.rsi;
+    registers.rbp = context.rbp;
+    registers.r8 = context.r8;
+    registers.r9 = context.r9;
+    registers.r10 = context.r10;
+    registers.r11 = context.r11;
+    registers.r12 = context.r12;
+    registers.r13 = context.r13;
+    registers.r14 = context.r14;
+    registers.r15 = context.r15;
+    registers.rip = context.rip;
+    registers.cs = context.cs;
+    registers.rflags = context.rflags;
+    registers.rsp = context.rsp;
+    registers.ss = context.ss;
+}
+
+#[test_case]
+fn test_spawn_join() {
+    use alloc::sync::Arc;
+    use core::sync::atomic::AtomicUsize;
+
+    let counter = Arc::new(AtomicUsize::new(0));
+    let handles: Vec<JoinHandle> = (0..3)
+        .map(|_| {
+            let counter = counter.clone();
+            spawn("test", Priority::Normal, move || {
+                for _ in 0..3 {
+                    counter.fetch_add(1, Ordering::Relaxed);
+                    yield_now();
+                }
+            })
+            .unwrap()
+        })
+        .collect();
+    for handle in handles {
+        handle.join();
+    }
+    assert_eq!(counter.load(Ordering::Relaxed), 9);
+}
+
+#[test_case]
+fn test_preemption() {
+    use alloc::sync::Arc;
+    use core::sync::atomic::AtomicBool;
+
+    // the spawned thread never yields, so only the timer can switch back here
+    let stop = Arc::new(AtomicBool::new(false));
+    let spinner = {
+        let stop = stop.clone();
+        spawn("spinner", Priority::Normal, move || {
+            while !stop.load(Ordering::Relaxed) {
+                core::hint::spin_loop();
+            }
+        })
+        .unwrap()
+    };
+    yield_now();
+    stop.store(true, Ordering::Relaxed);
+    spinner.join();
+}
+
+#[test_case]
+fn test_low_priority_not_starved() {
+    use alloc::sync::Arc;
+    use core::sync::atomic::AtomicBool;
+
+    // the high priority thread spins on something only the low priority one
+    // can change, as it would on a spinlock the other holds
+    let released = Arc::new(AtomicBool::new(false));
+    let low = {
+        let released = released.clone();
+        spawn("low", Priority::Low, move || {
+            released.store(true, Ordering::Relaxed);
+        })
+        .unwrap()
+    };
+    let high = spawn("high", Priority::High, move || {
+        while !released.load(Ordering::Relaxed) {
+            core::hint::spin_loop();
+        }
+    })
+    .unwrap();
+    high.join();
+    low.join();
+}
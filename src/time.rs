@@ -0,0 +1,475 @@
+//! Time keeping.
+//!
+//! The timer interrupt is driven by the PIT, or by the local APIC timer once it
+//! has been calibrated. `Instant` reads the TSC when its frequency could be
+//! measured and otherwise counts timer interrupts. Deadlines are kept in a
+//! timer wheel that the timer interrupt advances.
+
+use crate::acpi::{self, AddressSpace};
+use crate::apic;
+use crate::error::{ErrorKind, Result};
+use crate::interrupts::{self, InterruptIndex};
+use crate::paging::{self, BootInfoFrameAllocator};
+use crate::sync::IrqSpinLock;
+use crate::{bail, debug};
+use alloc::vec::Vec;
+use core::{
+    future::Future,
+    ops::{Add, Sub},
+    pin::Pin,
+    sync::atomic::{AtomicU64, Ordering},
+    task::{Context, Poll, Waker},
+};
+use spin::Once;
+use x86_64::instructions::port::Port;
+use x86_64::structures::paging::OffsetPageTable;
+
+pub use core::time::Duration;
+
+/// Timer interrupts per second after `init`.
+pub const DEFAULT_TICK_RATE: u32 = 1000;
+
+const PIT_FREQUENCY: u64 = 1_193_182;
+const PIT_CHANNEL0: u16 = 0x40;
+const PIT_CHANNEL2: u16 = 0x42;
+const PIT_COMMAND: u16 = 0x43;
+/// Channel 0, low then high byte, mode 2 (rate generator).
+const PIT_CHANNEL0_RATE: u8 = 0x34;
+/// Channel 2, low then high byte, mode 0 (interrupt on terminal count).
+const PIT_CHANNEL2_ONESHOT: u8 = 0xb0;
+
+/// Bit 0 gates PIT channel 2, bit 1 connects it to the speaker and bit 5
+/// reads its output.
+const PORT_B: u16 = 0x61;
+const PORT_B_GATE: u8 = 0x01;
+const PORT_B_SPEAKER: u8 = 0x02;
+const PORT_B_OUT2: u8 = 0x20;
+
+const HPET_CAPABILITIES: usize = 0x000;
+const HPET_CONFIGURATION: usize = 0x010;
+const HPET_MAIN_COUNTER: usize = 0x0f0;
+const HPET_ENABLE: u64 = 0x01;
+/// Longest counter period the HPET specification allows, in femtoseconds.
+const HPET_MAX_PERIOD: u64 = 100_000_000;
+
+const CALIBRATION_MS: u64 = 10;
+const NANOS_PER_SEC: u64 = 1_000_000_000;
+
+/// Nanoseconds between timer interrupts; the PIT's power-on rate until `init` runs.
+static TICK_PERIOD: AtomicU64 = AtomicU64::new(0x10000 * NANOS_PER_SEC / PIT_FREQUENCY);
+
+/// Nanoseconds since boot, counted in timer interrupts.
+static TICK_NANOS: AtomicU64 = AtomicU64::new(0);
+
+static TSC: Once<TscClock> = Once::new();
+
+/// Counts per second of the local APIC timer, if it drives the timer interrupt.
+static APIC_TIMER_FREQUENCY: Once<u64> = Once::new();
+
+static TIMERS: IrqSpinLock<TimerWheel> = IrqSpinLock::new(TimerWheel::new());
+
+struct TscClock {
+    frequency: u64,
+    base_tsc: u64,
+    base_nanos: u64,
+}
+
+impl TscClock {
+    fn nanos(&self) -> u64 {
+        let elapsed = u128::from(rdtsc().wrapping_sub(self.base_tsc));
+        let nanos = elapsed * u128::from(NANOS_PER_SEC) / u128::from(self.frequency);
+        self.base_nanos + nanos as u64
+    }
+}
+
+fn has_tsc() -> bool {
+    let cpuid = unsafe { core::arch::x86_64::__cpuid(1) };
+    cpuid.edx & (1 << 4) != 0
+}
+
+fn rdtsc() -> u64 {
+    unsafe { core::arch::x86_64::_rdtsc() }
+}
+
+/// A clock to calibrate other counters against.
+enum Reference {
+    Pit,
+    Hpet { base: u64, period: u64 },
+}
+
+impl Reference {
+    /// Maps the HPET, if the ACPI tables describe one, and starts its counter.
+    fn hpet(mapper: &mut OffsetPageTable, allocator: &mut BootInfoFrameAllocator) -> Option<Self> {
+        let hpet = acpi::Hpet::find()?;
+        if hpet.address.space != AddressSpace::Memory {
+            return None;
+        }
+        let base = hpet.address.address;
+        paging::make_identity_mapping(mapper, allocator, base, 1).ok()?;
+
+        let read = |register: usize| unsafe {
+            core::ptr::read_volatile((base as usize + register) as *const u64)
+        };
+        let period = read(HPET_CAPABILITIES) >> 32;
+        if period == 0 || period > HPET_MAX_PERIOD {
+            return None;
+        }
+        let config = read(HPET_CONFIGURATION);
+        unsafe {
+            core::ptr::write_volatile(
+                (base as usize + HPET_CONFIGURATION) as *mut u64,
+                config | HPET_ENABLE,
+            )
+        };
+        Some(Reference::Hpet { base, period })
+    }
+
+    fn name(&self) -> &'static str {
+        match self {
+            Reference::Pit => "PIT",
+            Reference::Hpet { .. } => "HPET",
+        }
+    }
+
+    fn wait_ms(&self, ms: u64) {
+        match *self {
+            Reference::Pit => unsafe {
+                let count = PIT_FREQUENCY * ms / 1000;
+                let mut port_b = Port::<u8>::new(PORT_B);
+                let idle = port_b.read() & !(PORT_B_GATE | PORT_B_SPEAKER);
+                port_b.write(idle);
+                Port::new(PIT_COMMAND).write(PIT_CHANNEL2_ONESHOT);
+                let mut channel2 = Port::<u8>::new(PIT_CHANNEL2);
+                channel2.write(count as u8);
+                channel2.write((count >> 8) as u8);
+                // the count starts once the gate goes high
+                port_b.write(idle | PORT_B_GATE);
+                while port_b.read() & PORT_B_OUT2 == 0 {
+                    core::hint::spin_loop();
+                }
+                port_b.write(idle);
+            },
+            Reference::Hpet { base, period } => {
+                let counter = (base as usize + HPET_MAIN_COUNTER) as *const u64;
+                let ticks = ms * 1_000_000_000_000 / period;
+                let start = unsafe { core::ptr::read_volatile(counter) };
+                while unsafe { core::ptr::read_volatile(counter) }.wrapping_sub(start) < ticks {
+                    core::hint::spin_loop();
+                }
+            }
+        }
+    }
+
+    /// Returns how far `counter` advances per second.
+    fn calibrate(&self, counter: impl Fn() -> u64) -> u64 {
+        let start = counter();
+        self.wait_ms(CALIBRATION_MS);
+        let end = counter();
+        end.wrapping_sub(start) * 1000 / CALIBRATION_MS
+    }
+}
+
+/// Calibrates the TSC and the local APIC timer, moves the timer interrupt to
+/// the APIC timer when the APICs are in use, and sets the tick rate to
+/// `DEFAULT_TICK_RATE`. Must run with interrupts disabled.
+pub fn init(mapper: &mut OffsetPageTable, allocator: &mut BootInfoFrameAllocator) -> Result<()> {
+    let reference = Reference::hpet(mapper, allocator).unwrap_or(Reference::Pit);
+
+    if has_tsc() {
+        let frequency = reference.calibrate(rdtsc);
+        TSC.call_once(|| TscClock {
+            frequency,
+            base_tsc: rdtsc(),
+            base_nanos: TICK_NANOS.load(Ordering::Relaxed),
+        });
+        debug!("TSC: {} kHz ({})", frequency / 1000, reference.name());
+    }
+
+    if apic::is_enabled() {
+        apic::start_timer(u32::MAX, None);
+        let frequency = reference.calibrate(|| u64::from(u32::MAX - apic::timer_count()));
+        APIC_TIMER_FREQUENCY.call_once(|| frequency);
+        apic::set_irq_masked(InterruptIndex::Timer.irq(), true);
+        debug!(
+            "APIC timer: {} kHz ({})",
+            frequency / 1000,
+            reference.name()
+        );
+    }
+
+    set_tick_rate(DEFAULT_TICK_RATE)
+}
+
+/// Makes the timer interrupt fire `hz` times a second.
+pub fn set_tick_rate(hz: u32) -> Result<()> {
+    let hz = u64::from(hz);
+    if hz == 0 {
+        bail!(ErrorKind::InvalidArgument);
+    }
+
+    let period = match APIC_TIMER_FREQUENCY.get() {
+        Some(&frequency) => {
+            let count = u32::try_from(frequency / hz).map_err(ErrorKind::TryFromInt)?;
+            if count == 0 {
+                bail!(ErrorKind::InvalidArgument);
+            }
+            apic::start_timer(count, Some(InterruptIndex::Timer.as_u8()));
+            u64::from(count) * NANOS_PER_SEC / frequency
+        }
+        None => {
+            let divisor = PIT_FREQUENCY / hz;
+            if !(1..=0x10000).contains(&divisor) {
+                bail!(ErrorKind::InvalidArgument);
+            }
+            // a divisor of 0x10000 is written as 0
+            x86_64::instructions::interrupts::without_interrupts(|| unsafe {
+                Port::new(PIT_COMMAND).write(PIT_CHANNEL0_RATE);
+                let mut channel0 = Port::<u8>::new(PIT_CHANNEL0);
+                channel0.write(divisor as u8);
+                channel0.write((divisor >> 8) as u8);
+            });
+            divisor * NANOS_PER_SEC / PIT_FREQUENCY
+        }
+    };
+    TICK_PERIOD.store(period, Ordering::Relaxed);
+    Ok(())
+}
+
+/// Timer interrupts per second.
+pub fn tick_rate() -> u32 {
+    (NANOS_PER_SEC / TICK_PERIOD.load(Ordering::Relaxed)) as u32
+}
+
+/// Called by the timer interrupt handler after the tick count was bumped.
+pub(crate) fn tick() {
+    TICK_NANOS.fetch_add(TICK_PERIOD.load(Ordering::Relaxed), Ordering::Relaxed);
+    // the lock keeps interrupts disabled while held, so it is free here
+    if let Some(mut timers) = TIMERS.try_lock() {
+        timers.advance(interrupts::ticks());
+    }
+}
+
+/// A point on the monotonic clock, in nanoseconds since boot.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Instant(u64);
+
+impl Instant {
+    /// The instant the kernel booted.
+    pub const BOOT: Instant = Instant(0);
+
+    pub fn now() -> Self {
+        match TSC.get() {
+            Some(tsc) => Instant(tsc.nanos()),
+            None => Instant(TICK_NANOS.load(Ordering::Relaxed)),
+        }
+    }
+
+    /// Time since `earlier`, or zero if `earlier` is later than `self`.
+    pub fn duration_since(&self, earlier: Instant) -> Duration {
+        Duration::from_nanos(self.0.saturating_sub(earlier.0))
+    }
+
+    pub fn elapsed(&self) -> Duration {
+        Instant::now().duration_since(*self)
+    }
+
+    pub fn checked_add(&self, duration: Duration) -> Option<Instant> {
+        let nanos = u64::try_from(duration.as_nanos()).ok()?;
+        self.0.checked_add(nanos).map(Instant)
+    }
+}
+
+impl Add<Duration> for Instant {
+    type Output = Instant;
+
+    fn add(self, duration: Duration) -> Instant {
+        self.checked_add(duration)
+            .expect("overflow when adding duration to instant")
+    }
+}
+
+impl Sub<Instant> for Instant {
+    type Output = Duration;
+
+    fn sub(self, earlier: Instant) -> Duration {
+        self.duration_since(earlier)
+    }
+}
+
+/// Time since boot.
+pub fn uptime() -> Duration {
+    Instant::now().duration_since(Instant::BOOT)
+}
+
+/// Spins until `duration` has passed. Without a TSC this relies on timer
+/// interrupts, so interrupts must be enabled.
+pub fn busy_wait(duration: Duration) {
+    let end = Instant::now() + duration;
+    while Instant::now() < end {
+        core::hint::spin_loop();
+    }
+}
+
+/// Timer interrupts that cover at least `duration`.
+pub(crate) fn duration_to_ticks(duration: Duration) -> u64 {
+    let period = u128::from(TICK_PERIOD.load(Ordering::Relaxed));
+    u64::try_from(duration.as_nanos().div_ceil(period)).unwrap_or(u64::MAX)
+}
+
+const WHEEL_SLOTS: usize = 256;
+
+struct TimerEntry {
+    id: u64,
+    deadline: u64,
+    waker: Waker,
+}
+
+/// Wakers sorted into slots by the tick they are due at, modulo the number of
+/// slots, so each tick only looks at one slot.
+struct TimerWheel {
+    slots: [Vec<TimerEntry>; WHEEL_SLOTS],
+    /// The last tick that has been processed.
+    now: u64,
+    next_id: u64,
+}
+
+impl TimerWheel {
+    const fn new() -> Self {
+        Self {
+            slots: [const { Vec::new() }; WHEEL_SLOTS],
+            now: 0,
+            next_id: 0,
+        }
+    }
+
+    /// Schedules `waker` to be woken at tick `deadline`. Returns `None`,
+    /// without scheduling anything, if the deadline has already passed.
+    fn insert(&mut self, deadline: u64, waker: Waker) -> Option<u64> {
+        if deadline <= self.now {
+            return None;
+        }
+        let id = self.next_id;
+        self.next_id += 1;
+        self.slots[deadline as usize % WHEEL_SLOTS].push(TimerEntry {
+            id,
+            deadline,
+            waker,
+        });
+        Some(id)
+    }
+
+    fn remove(&mut self, id: u64, deadline: u64) {
+        let slot = &mut self.slots[deadline as usize % WHEEL_SLOTS];
+        if let Some(index) = slot.iter().position(|entry| entry.id == id) {
+            slot.swap_remove(index);
+        }
+    }
+
+    /// Wakes every timer due at or before tick `now`.
+    fn advance(&mut self, now: u64) {
+        if now <= self.now {
+            return;
+        }
+        // every slot is visited at most once, however many ticks were missed
+        let steps = (now - self.now).min(WHEEL_SLOTS as u64);
+        for tick in now - steps + 1..=now {
+            let slot = &mut self.slots[tick as usize % WHEEL_SLOTS];
+            let mut index = 0;
+            while index < slot.len() {
+                if slot[index].deadline <= now {
+                    slot.swap_remove(index).waker.wake();
+                } else {
+                    index += 1;
+                }
+            }
+        }
+        self.now = now;
+    }
+}
+
+/// Future returned by `sleep`.
+pub struct Sleep {
+    deadline: u64,
+    timer: Option<u64>,
+}
+
+/// Completes once `duration` has passed, at the resolution of the tick rate.
+pub fn sleep(duration: Duration) -> Sleep {
+    // the current tick is partly over, so wait for one more
+    let ticks = duration_to_ticks(duration).saturating_add(1);
+    Sleep {
+        deadline: interrupts::ticks().saturating_add(ticks),
+        timer: None,
+    }
+}
+
+impl Future for Sleep {
+    type Output = ();
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<()> {
+        let deadline = self.deadline;
+        let previous = self.timer.take();
+        let timer = {
+            let mut timers = TIMERS.lock();
+            if let Some(id) = previous {
+                timers.remove(id, deadline);
+            }
+            timers.insert(deadline, cx.waker().clone())
+        };
+        match timer {
+            Some(id) => {
+                self.timer = Some(id);
+                Poll::Pending
+            }
+            None => Poll::Ready(()),
+        }
+    }
+}
+
+impl Drop for Sleep {
+    fn drop(&mut self) {
+        if let Some(id) = self.timer {
+            TIMERS.lock().remove(id, self.deadline);
+        }
+    }
+}
+
+#[cfg(test)]
+use alloc::{sync::Arc, task::Wake};
+#[cfg(test)]
+use core::sync::atomic::AtomicUsize;
+
+#[cfg(test)]
+struct CountingWaker(AtomicUsize);
+
+#[cfg(test)]
+impl Wake for CountingWaker {
+    fn wake(self: Arc<Self>) {
+        self.0.fetch_add(1, Ordering::Relaxed);
+    }
+}
+
+#[test_case]
+fn test_timer_wheel() {
+    let counter = Arc::new(CountingWaker(AtomicUsize::new(0)));
+    let mut wheel = TimerWheel::new();
+    wheel.insert(3, Waker::from(counter.clone())).unwrap();
+    wheel.insert(3 + WHEEL_SLOTS as u64, Waker::from(counter.clone()));
+    let cancelled = wheel.insert(5, Waker::from(counter.clone())).unwrap();
+    wheel.remove(cancelled, 5);
+
+    wheel.advance(2);
+    assert_eq!(counter.0.load(Ordering::Relaxed), 0);
+    wheel.advance(10);
+    assert_eq!(counter.0.load(Ordering::Relaxed), 1);
+    wheel.advance(1000);
+    assert_eq!(counter.0.load(Ordering::Relaxed), 2);
+    assert!(wheel.insert(1000, Waker::from(counter.clone())).is_none());
+}
+
+#[test_case]
+fn test_busy_wait() {
+    let start = Instant::now();
+    busy_wait(Duration::from_millis(5));
+    assert!(start.elapsed() >= Duration::from_millis(5));
+}
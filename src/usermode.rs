@@ -0,0 +1,445 @@
+//! User mode (ring 3) and per-process address spaces.
+//!
+//! Every address space shares the kernel's level 4 entries, without user
+//! access, and has its own mappings in `USER_START..USER_END`, a level 4
+//! entry the kernel does not use. The bootloader maps the kernel in the lower
+//! half, so the kernel's mappings are shared at level 4 granularity rather
+//! than as an upper half; they all exist before the first process is created.
+
+use crate::error::{ErrorKind, Result};
+use crate::{bail, gdt, paging};
+use alloc::vec::Vec;
+use core::arch::asm;
+use spin::Mutex;
+use x86_64::registers::model_specific::{Efer, EferFlags};
+use x86_64::structures::paging::mapper::TranslateResult;
+use x86_64::structures::paging::page::PageRange;
+use x86_64::structures::paging::{
+    FrameAllocator, FrameDeallocator, Mapper, OffsetPageTable, Page, PageTable, PageTableFlags,
+    PhysFrame, Size4KiB, Translate,
+};
+use x86_64::VirtAddr;
+
+/// Start of the user part of every address space.
+pub const USER_START: u64 = 0x0000_0080_0000_0000;
+/// End of the user part, 512 GiB after `USER_START`.
+pub const USER_END: u64 = 0x0000_0100_0000_0000;
+/// Where the program break starts, halfway through the user part.
+pub const HEAP_START: u64 = USER_START + (1 << 38);
+/// Where anonymous mappings are placed, above the heap.
+pub const MMAP_START: u64 = USER_START + (3 << 37);
+
+const PAGE_SIZE: u64 = 4096;
+
+/// What user mode may do with mapped pages, besides reading them.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Protection {
+    pub write: bool,
+    pub execute: bool,
+}
+
+impl Protection {
+    pub const READ: Self = Self {
+        write: false,
+        execute: false,
+    };
+    pub const READ_WRITE: Self = Self {
+        write: true,
+        execute: false,
+    };
+    pub const READ_EXECUTE: Self = Self {
+        write: false,
+        execute: true,
+    };
+
+    fn flags(self) -> PageTableFlags {
+        let mut flags = PageTableFlags::PRESENT | PageTableFlags::USER_ACCESSIBLE;
+        if self.write {
+            flags |= PageTableFlags::WRITABLE;
+        }
+        // the bit is reserved unless no-execute support is enabled
+        if !self.execute && Efer::read().contains(EferFlags::NO_EXECUTE_ENABLE) {
+            flags |= PageTableFlags::NO_EXECUTE;
+        }
+        flags
+    }
+}
+
+/// Maps a zeroed frame at the unmapped `page`. The frame allocator is only
+/// locked for this page, so mapping many does not keep interrupts off long.
+fn map_page(mapper: &mut OffsetPageTable, page: Page, flags: PageTableFlags) -> Result<()> {
+    let frame = paging::get_frame_allocator()?
+        .allocate_frame()
+        .ok_or(ErrorKind::NoEnoughMemory)?;
+    unsafe {
+        let addr = paging::phys_to_virt(frame.start_address());
+        core::ptr::write_bytes(addr.as_mut_ptr::<u8>(), 0, PAGE_SIZE as usize);
+    }
+    let mut allocator = paging::get_frame_allocator()?;
+    // the page was not present, so no stale translation needs flushing
+    match unsafe { mapper.map_to(page, frame, flags, &mut *allocator) } {
+        Ok(flush) => flush.ignore(),
+        Err(err) => {
+            unsafe { allocator.deallocate_frame(frame) };
+            return Err(err.into());
+        }
+    }
+    Ok(())
+}
+
+/// Unmaps `page` and frees its frame.
+fn unmap_page(mapper: &mut OffsetPageTable, page: Page) {
+    if let Ok((frame, flush)) = mapper.unmap(page) {
+        flush.flush();
+        if let Ok(mut allocator) = paging::get_frame_allocator() {
+            unsafe { allocator.deallocate_frame(frame) };
+        }
+    }
+}
+
+/// Whether pages mapped with `old` and `new` allow the same access.
+fn same_protection(old: PageTableFlags, new: PageTableFlags) -> bool {
+    let access = PageTableFlags::WRITABLE | PageTableFlags::NO_EXECUTE;
+    old & access == new & access
+}
+
+fn user_l4_index() -> usize {
+    (USER_START >> 39) as usize & 0x1ff
+}
+
+/// A level 4 page table with the kernel's mappings and a private user part.
+#[derive(Debug)]
+pub struct AddressSpace {
+    l4: PhysFrame,
+    /// Held while the user part of the page tables is modified.
+    layout: Mutex<Layout>,
+}
+
+#[derive(Debug)]
+struct Layout {
+    brk: u64,
+    mmap_next: u64,
+}
+
+impl AddressSpace {
+    pub fn new() -> Result<Self> {
+        let mut kernel = paging::get_mapper()?;
+        let kernel_l4 = kernel.level_4_table();
+        if !kernel_l4[user_l4_index()].is_unused() {
+            bail!(ErrorKind::AlreadyAllocated);
+        }
+
+        let frame = paging::get_frame_allocator()?
+            .allocate_frame()
+            .ok_or(ErrorKind::NoEnoughMemory)?;
+        let space = AddressSpace {
+            l4: frame,
+            layout: Mutex::new(Layout {
+                brk: HEAP_START,
+                mmap_next: MMAP_START,
+            }),
+        };
+        // every entry is written before anything can fail, so dropping the
+        // space never walks stale entries
+        let table = unsafe { space.table() };
+        for (entry, kernel_entry) in table.iter_mut().zip(kernel_l4.iter()) {
+            entry.clone_from(kernel_entry);
+        }
+        Ok(space)
+    }
+
+    /// The level 4 table, through the physical memory mapping.
+    #[allow(clippy::mut_from_ref)]
+    unsafe fn table(&self) -> &mut PageTable {
+        let table = paging::phys_to_virt(self.l4.start_address());
+        unsafe { &mut *table.as_mut_ptr() }
+    }
+
+    fn mapper(&self) -> OffsetPageTable<'_> {
+        unsafe { OffsetPageTable::new(self.table(), paging::physical_memory_offset()) }
+    }
+
+    /// The frame to load into CR3.
+    pub fn frame(&self) -> PhysFrame {
+        self.l4
+    }
+
+    /// Maps zeroed user pages covering `size` bytes from `start`. Pages that
+    /// are already mapped are kept if they allow the same access, and fail
+    /// the call otherwise.
+    pub fn map(&self, start: VirtAddr, size: usize, protection: Protection) -> Result<()> {
+        let _layout = self.layout.lock();
+        self.map_locked(start, size, protection)
+    }
+
+    fn map_locked(&self, start: VirtAddr, size: usize, protection: Protection) -> Result<()> {
+        let end = start.as_u64().checked_add(size as u64);
+        if start.as_u64() < USER_START || end.is_none_or(|end| end > USER_END) {
+            bail!(ErrorKind::InvalidArgument);
+        }
+        let flags = protection.flags();
+
+        let first = Page::<Size4KiB>::containing_address(start);
+        let last = Page::<Size4KiB>::containing_address(start + size.max(1) as u64 - 1u64);
+        let mut mapper = self.mapper();
+        // runs of pages this call mapped, unmapped again if a later one fails
+        let mut mapped: Vec<PageRange> = Vec::new();
+        let result = Page::range_inclusive(first, last).try_for_each(|page| {
+            if let TranslateResult::Mapped { flags: old, .. } =
+                mapper.translate(page.start_address())
+            {
+                // combining them could make a page writable and executable
+                if !same_protection(old, flags) {
+                    bail!(ErrorKind::InvalidArgument);
+                }
+                return Ok(());
+            }
+            map_page(&mut mapper, page, flags)?;
+            match mapped.last_mut() {
+                Some(run) if run.end == page => run.end += 1,
+                _ => mapped.push(Page::range(page, page + 1)),
+            }
+            Ok(())
+        });
+        if result.is_err() {
+            for page in mapped.into_iter().flatten() {
+                unmap_page(&mut mapper, page);
+            }
+        }
+        result
+    }
+
+    /// Moves the program break by `increment` bytes and returns the old break.
+    /// Pages are mapped as the break grows and kept when it shrinks.
+    pub fn sbrk(&self, increment: i64) -> Result<VirtAddr> {
+        let mut layout = self.layout.lock();
+        let old = layout.brk;
+        let new = old
+            .checked_add_signed(increment)
+            .filter(|&brk| (HEAP_START..=MMAP_START).contains(&brk))
+            .ok_or(ErrorKind::NoEnoughMemory)?;
+        if new > old {
+            self.map_locked(
+                VirtAddr::new(old),
+                (new - old) as usize,
+                Protection::READ_WRITE,
+            )?;
+        }
+        layout.brk = new;
+        Ok(VirtAddr::new(old))
+    }
+
+    /// Maps `size` bytes of zeroed memory somewhere in the mapping area.
+    pub fn map_anonymous(&self, size: usize, protection: Protection) -> Result<VirtAddr> {
+        if size == 0 {
+            bail!(ErrorKind::InvalidArgument);
+        }
+        let mut layout = self.layout.lock();
+        let start = layout.mmap_next;
+        if size as u64 > USER_END - start {
+            bail!(ErrorKind::NoEnoughMemory);
+        }
+        let pages = (size as u64).div_ceil(PAGE_SIZE);
+        self.map_locked(VirtAddr::new(start), size, protection)?;
+        layout.mmap_next = start + pages * PAGE_SIZE;
+        Ok(VirtAddr::new(start))
+    }
+
+    /// Whether user mode can access `size` bytes from `start`, and write them
+    /// if `write` is set.
+    pub fn is_accessible(&self, start: VirtAddr, size: usize, write: bool) -> bool {
+        let Some(end) = start.as_u64().checked_add(size as u64) else {
+            return false;
+        };
+        if start.as_u64() < USER_START || end > USER_END {
+            return false;
+        }
+        if size == 0 {
+            return true;
+        }
+        let mapper = self.mapper();
+        let first = Page::<Size4KiB>::containing_address(start);
+        let last = Page::<Size4KiB>::containing_address(VirtAddr::new(end - 1));
+        Page::range_inclusive(first, last).all(|page| {
+            match mapper.translate(page.start_address()) {
+                TranslateResult::Mapped { flags, .. } => {
+                    flags.contains(PageTableFlags::USER_ACCESSIBLE)
+                        && (!write || flags.contains(PageTableFlags::WRITABLE))
+                }
+                _ => false,
+            }
+        })
+    }
+
+    /// Copies `data` to `addr`, which must already be mapped.
+    pub fn write(&self, addr: VirtAddr, data: &[u8]) -> Result<()> {
+        let mapper = self.mapper();
+        let mut offset = 0;
+        while offset < data.len() {
+            let target = addr + offset as u64;
+            let phys = mapper
+                .translate_addr(target)
+                .ok_or(ErrorKind::InvalidArgument)?;
+            let len = ((PAGE_SIZE - target.as_u64() % PAGE_SIZE) as usize).min(data.len() - offset);
+            unsafe {
+                core::ptr::copy_nonoverlapping(
+                    data[offset..].as_ptr(),
+                    paging::phys_to_virt(phys).as_mut_ptr::<u8>(),
+                    len,
+                );
+            }
+            offset += len;
+        }
+        Ok(())
+    }
+}
+
+impl Drop for AddressSpace {
+    fn drop(&mut self) {
+        let Ok(mut allocator) = paging::get_frame_allocator() else {
+            return;
+        };
+        let table_at = |entry: &x86_64::structures::paging::page_table::PageTableEntry| unsafe {
+            &mut *paging::phys_to_virt(entry.addr()).as_mut_ptr::<PageTable>()
+        };
+        let l4_entry = &mut unsafe { self.table() }[user_l4_index()];
+        if !l4_entry.is_unused() {
+            let l3 = table_at(l4_entry);
+            for l3_entry in l3.iter().filter(|entry| !entry.is_unused()) {
+                let l2 = table_at(l3_entry);
+                for l2_entry in l2.iter().filter(|entry| !entry.is_unused()) {
+                    let l1 = table_at(l2_entry);
+                    for frame in l1.iter().filter_map(|entry| entry.frame().ok()) {
+                        unsafe { allocator.deallocate_frame(frame) };
+                    }
+                    unsafe {
+                        allocator.deallocate_frame(PhysFrame::containing_address(l2_entry.addr()))
+                    };
+                }
+                unsafe {
+                    allocator.deallocate_frame(PhysFrame::containing_address(l3_entry.addr()))
+                };
+            }
+            unsafe { allocator.deallocate_frame(PhysFrame::containing_address(l4_entry.addr())) };
+        }
+        unsafe { allocator.deallocate_frame(self.l4) };
+    }
+}
+
+/// Drops to ring 3 and jumps to `entry` with `stack_top` as the stack. The
+/// current kernel stack becomes the one interrupts from user mode run on.
+///
+/// # Safety
+///
+/// The current address space must map `entry` and the stack for user access.
+pub unsafe fn enter(entry: VirtAddr, stack_top: VirtAddr) -> ! {
+    let selectors = gdt::selectors();
+    let code = u64::from(selectors.user_code_selector.0);
+    let data = u64::from(selectors.user_data_selector.0);
+    unsafe {
+        asm!(
+            "mov ds, {data:x}",
+            "mov es, {data:x}",
+            //
+            // stack frame for iret
+            "push {data}",
+            "push {stack}",
+            "push 0x202", // RFLAGS with interrupts enabled
+            "push {code}",
+            "push {entry}",
+            //
+            // leave nothing of the kernel's behind
+            "xor eax, eax",
+            "xor ebx, ebx",
+            "xor ecx, ecx",
+            "xor edx, edx",
+            "xor esi, esi",
+            "xor edi, edi",
+            "xor ebp, ebp",
+            "xor r8d, r8d",
+            "xor r9d, r9d",
+            "xor r10d, r10d",
+            "xor r11d, r11d",
+            "xor r12d, r12d",
+            "xor r13d, r13d",
+            "xor r14d, r14d",
+            "xor r15d, r15d",
+            "iretq",
+            data = in(reg) data,
+            stack = in(reg) stack_top.as_u64(),
+            code = in(reg) code,
+            entry = in(reg) entry.as_u64(),
+            options(noreturn)
+        );
+    }
+}
+
+#[test_case]
+fn test_map_rejects_other_protection() {
+    let space = AddressSpace::new().unwrap();
+    let code = VirtAddr::new(USER_START + 2 * PAGE_SIZE);
+    space.map(code, 1, Protection::READ_EXECUTE).unwrap();
+    let start = VirtAddr::new(USER_START);
+    let size = 4 * PAGE_SIZE as usize;
+    assert!(space.map(start, size, Protection::READ_WRITE).is_err());
+    // the pages mapped before the shared one are gone again
+    assert!(!space.is_accessible(start, 1, false));
+    assert!(space.is_accessible(code, 1, false));
+    assert!(!space.is_accessible(code, 1, true));
+    space.map(code, 1, Protection::READ_EXECUTE).unwrap();
+}
+
+#[test_case]
+fn test_user_mode_is_unprivileged() {
+    use crate::thread::{self, Priority};
+    use alloc::sync::Arc;
+
+    // `hlt` is privileged, so the thread is killed by a general protection fault
+    let space = AddressSpace::new().unwrap();
+    let code = VirtAddr::new(USER_START);
+    space.map(code, 1, Protection::READ_EXECUTE).unwrap();
+    space.write(code, &[0xf4]).unwrap();
+    let handle = thread::spawn_user(
+        "user test",
+        Priority::Normal,
+        None,
+        Arc::new(space),
+        code,
+        VirtAddr::new(USER_START + PAGE_SIZE),
+    )
+    .unwrap();
+    thread::unblock(handle.id());
+    assert_eq!(handle.join(), crate::process::EXIT_FAULT);
+}
+
+#[test_case]
+fn test_user_mode_cannot_touch_kernel_memory() {
+    use crate::thread::{self, Priority};
+    use alloc::sync::Arc;
+
+    static TARGET: core::sync::atomic::AtomicU64 = core::sync::atomic::AtomicU64::new(0);
+
+    // movabs rax, &TARGET; mov qword ptr [rax], 1
+    let mut code = alloc::vec![0x48, 0xb8];
+    code.extend_from_slice(&(&TARGET as *const _ as u64).to_le_bytes());
+    code.extend_from_slice(&[0x48, 0xc7, 0x00, 0x01, 0x00, 0x00, 0x00]);
+
+    let space = AddressSpace::new().unwrap();
+    let entry = VirtAddr::new(USER_START);
+    space
+        .map(entry, code.len(), Protection::READ_EXECUTE)
+        .unwrap();
+    space.write(entry, &code).unwrap();
+    let handle = thread::spawn_user(
+        "user test",
+        Priority::Normal,
+        None,
+        Arc::new(space),
+        entry,
+        VirtAddr::new(USER_START + PAGE_SIZE),
+    )
+    .unwrap();
+    thread::unblock(handle.id());
+    handle.join();
+    assert_eq!(TARGET.load(core::sync::atomic::Ordering::Relaxed), 0);
+}
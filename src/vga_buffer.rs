@@ -1,13 +1,33 @@
+use crate::ansi::{Action, Csi, Parser};
+use crate::cp437;
+use crate::font::FONT;
+use crate::framebuffer::{self, Rgb};
+use alloc::{boxed::Box, vec};
 use core::fmt;
 use spin::{Lazy, Mutex};
 use volatile::Volatile;
+use x86_64::instructions::port::Port;
 
+/// Set up on first use, which must come after the heap.
 pub static WRITER: Lazy<Mutex<Writer>> = Lazy::new(|| {
-    Mutex::new(Writer {
+    let blank = ScreenChar::blank(DEFAULT_COLOR);
+    let mut writer = Writer {
+        row_position: BUFFER_HEIGHT - 1,
         column_position: 0,
-        color_code: ColorCode::new(Color::Yellow, Color::Black),
-        buffer: unsafe { &mut *(0xb8000 as *mut Buffer) },
-    })
+        color_code: DEFAULT_COLOR,
+        display: Display::Text(unsafe { &mut *(0xb8000 as *mut Buffer) }),
+        rows: BUFFER_HEIGHT,
+        columns: BUFFER_WIDTH,
+        screen: vec![[blank; MAX_COLUMNS]; MAX_ROWS].into_boxed_slice(),
+        scroll_offset: 0,
+        parser: Parser::new(),
+        saved_position: (BUFFER_HEIGHT - 1, 0),
+        cursor_visible: true,
+        bold: false,
+        pointer: None,
+    };
+    writer.enable_cursor();
+    Mutex::new(writer)
 });
 
 #[allow(dead_code)]
@@ -40,6 +60,39 @@ impl ColorCode {
     const fn new(foreground: Color, background: Color) -> ColorCode {
         ColorCode((background as u8) << 4 | (foreground as u8))
     }
+
+    fn with_foreground(self, foreground: u8) -> ColorCode {
+        ColorCode(self.0 & 0xf0 | foreground & 0x0f)
+    }
+
+    fn with_background(self, background: u8) -> ColorCode {
+        ColorCode(self.0 & 0x0f | (background & 0x0f) << 4)
+    }
+}
+
+const DEFAULT_COLOR: ColorCode = ColorCode::new(Color::Yellow, Color::Black);
+
+/// VGA colors for the eight ANSI colors; setting bit 3 gives the bright variant.
+const ANSI_COLORS: [Color; 8] = [
+    Color::Black,
+    Color::Red,
+    Color::Green,
+    Color::Brown,
+    Color::Blue,
+    Color::Magenta,
+    Color::Cyan,
+    Color::LightGray,
+];
+
+fn ansi_color(index: u16, bright: bool) -> u8 {
+    ANSI_COLORS[usize::from(index & 7)] as u8 | if bright { 8 } else { 0 }
+}
+
+/// The VGA color closest to a 24-bit one: a channel counts when at least
+/// half on, and the color is bright when one is nearly full.
+fn rgb_color(r: u16, g: u16, b: u16) -> u8 {
+    let index = u16::from(r >= 128) | u16::from(g >= 128) << 1 | u16::from(b >= 128) << 2;
+    ansi_color(index, r.max(g).max(b) >= 192)
 }
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
@@ -49,104 +102,566 @@ struct ScreenChar {
     color_code: ColorCode,
 }
 
-const BUFFER_HEIGHT: usize = 25;
-const BUFFER_WIDTH: usize = 80;
+impl ScreenChar {
+    const fn blank(color_code: ColorCode) -> Self {
+        Self {
+            ascii_character: b' ',
+            color_code,
+        }
+    }
+}
+
+pub const BUFFER_HEIGHT: usize = 25;
+pub const BUFFER_WIDTH: usize = 80;
+
+/// Largest text grid the writer supports, enough for 1024x768 with an 8x16 font.
+const MAX_ROWS: usize = 48;
+const MAX_COLUMNS: usize = 128;
+
+/// Number of lines kept after they scroll off the top of the screen.
+const SCROLLBACK_LINES: usize = 200;
 
 #[repr(transparent)]
 struct Buffer {
     chars: [[Volatile<ScreenChar>; BUFFER_WIDTH]; BUFFER_HEIGHT],
 }
 
+type Line = [ScreenChar; MAX_COLUMNS];
+
+/// Lines that have scrolled off the top of the screen. Kept out of `Writer` so
+/// the buffer is statically allocated instead of being built on the stack.
+static SCROLLBACK: Mutex<Scrollback> =
+    Mutex::new(Scrollback::new(ScreenChar::blank(DEFAULT_COLOR)));
+
+/// Ring buffer of lines.
+struct Scrollback {
+    lines: [Line; SCROLLBACK_LINES],
+    head: usize,
+    len: usize,
+}
+
+impl Scrollback {
+    const fn new(blank: ScreenChar) -> Self {
+        Self {
+            lines: [[blank; MAX_COLUMNS]; SCROLLBACK_LINES],
+            head: 0,
+            len: 0,
+        }
+    }
+
+    fn push(&mut self, line: Line) {
+        self.lines[self.head] = line;
+        self.head = (self.head + 1) % SCROLLBACK_LINES;
+        self.len = usize::min(self.len + 1, SCROLLBACK_LINES);
+    }
+
+    /// Returns the `n`-th line counting back from the most recent one (`n >= 1`).
+    fn get(&self, n: usize) -> &Line {
+        assert!(1 <= n && n <= self.len);
+        &self.lines[(self.head + SCROLLBACK_LINES - n) % SCROLLBACK_LINES]
+    }
+
+    fn clear(&mut self) {
+        self.head = 0;
+        self.len = 0;
+    }
+}
+
+/// Where the text grid is shown.
+enum Display {
+    /// The VGA text buffer at 0xb8000.
+    Text(&'static mut Buffer),
+    /// Glyphs rendered into the linear framebuffer; remembers what each cell
+    /// shows and the cell the cursor was last drawn in, so unchanged cells
+    /// are not drawn again.
+    Framebuffer {
+        shown: Box<[Line]>,
+        cursor: Option<(usize, usize)>,
+    },
+}
+
+/// Palette used to render VGA colors in the framebuffer.
+const PALETTE: [Rgb; 16] = [
+    Rgb(0x00, 0x00, 0x00),
+    Rgb(0x00, 0x00, 0xaa),
+    Rgb(0x00, 0xaa, 0x00),
+    Rgb(0x00, 0xaa, 0xaa),
+    Rgb(0xaa, 0x00, 0x00),
+    Rgb(0xaa, 0x00, 0xaa),
+    Rgb(0xaa, 0x55, 0x00),
+    Rgb(0xaa, 0xaa, 0xaa),
+    Rgb(0x55, 0x55, 0x55),
+    Rgb(0x55, 0x55, 0xff),
+    Rgb(0x55, 0xff, 0x55),
+    Rgb(0x55, 0xff, 0xff),
+    Rgb(0xff, 0x55, 0x55),
+    Rgb(0xff, 0x55, 0xff),
+    Rgb(0xff, 0xff, 0x55),
+    Rgb(0xff, 0xff, 0xff),
+];
+
 pub struct Writer {
+    row_position: usize,
     column_position: usize,
     color_code: ColorCode,
-    buffer: &'static mut Buffer,
+    display: Display,
+    rows: usize,
+    columns: usize,
+    /// Live screen contents, kept so the view can be restored after scrolling
+    /// back. `MAX_ROWS` lines, on the heap as they are too large for the stack.
+    screen: Box<[Line]>,
+    /// How many lines the view is scrolled back from the live screen.
+    scroll_offset: usize,
+    parser: Parser,
+    /// Position stored by `ESC [ s` and restored by `ESC [ u`.
+    saved_position: (usize, usize),
+    cursor_visible: bool,
+    /// SGR bold, rendered as the bright variant of ANSI foreground colors.
+    bold: bool,
+    /// Cell highlighted as the mouse pointer.
+    pointer: Option<(usize, usize)>,
 }
 
 impl Writer {
+    /// Draws the CP437 glyph `byte`; control codes are handled by `execute_control`.
     fn write_byte(&mut self, byte: u8) {
+        if self.column_position >= self.columns {
+            self.new_line();
+        }
+
+        let row = self.row_position;
+        let col = self.column_position;
+
+        let color_code = self.color_code;
+        self.write_char(
+            row,
+            col,
+            ScreenChar {
+                ascii_character: byte,
+                color_code,
+            },
+        );
+        self.column_position += 1;
+    }
+
+    fn write_string(&mut self, s: &str) {
+        self.reset_view();
+        for c in s.chars() {
+            match self.parser.advance(c) {
+                Some(Action::Print(c)) => {
+                    self.write_byte(cp437::from_char(c).unwrap_or(cp437::REPLACEMENT))
+                }
+                Some(Action::Control(byte)) => self.execute_control(byte),
+                Some(Action::Csi(csi)) => self.execute_csi(&csi),
+                None => {}
+            }
+        }
+        self.update_cursor();
+    }
+
+    fn execute_control(&mut self, byte: u8) {
         match byte {
             b'\n' => self.new_line(),
-            byte => {
-                if self.column_position >= BUFFER_WIDTH {
-                    self.new_line();
-                }
+            b'\r' => self.column_position = 0,
+            b'\t' => {
+                let col = (self.column_position / 8 + 1) * 8;
+                self.column_position = usize::min(col, self.columns - 1);
+            }
+            0x08 => self.column_position = self.column_position.saturating_sub(1),
+            _ => {}
+        }
+    }
 
-                let row = BUFFER_HEIGHT - 1;
-                let col = self.column_position;
+    fn execute_csi(&mut self, csi: &Csi) {
+        let n = usize::from(csi.param(0, 1));
+        let row = self.row_position;
+        let col = usize::min(self.column_position, self.columns - 1);
+        match (csi.private, csi.final_byte) {
+            (false, b'A') => self.row_position = row.saturating_sub(n),
+            (false, b'B') => self.row_position = usize::min(row + n, self.rows - 1),
+            (false, b'C') => self.column_position = usize::min(col + n, self.columns - 1),
+            (false, b'D') => self.column_position = col.saturating_sub(n),
+            (false, b'E') => {
+                self.row_position = usize::min(row + n, self.rows - 1);
+                self.column_position = 0;
+            }
+            (false, b'F') => {
+                self.row_position = row.saturating_sub(n);
+                self.column_position = 0;
+            }
+            (false, b'G') => self.column_position = usize::min(n - 1, self.columns - 1),
+            (false, b'H' | b'f') => {
+                let col = usize::from(csi.param(1, 1));
+                self.row_position = usize::min(n - 1, self.rows - 1);
+                self.column_position = usize::min(col - 1, self.columns - 1);
+            }
+            (false, b'J') => self.erase_display(csi.param(0, 0)),
+            (false, b'K') => self.erase_line(csi.param(0, 0)),
+            (false, b'm') => self.select_graphic_rendition(csi),
+            (false, b's') => self.saved_position = (row, self.column_position),
+            (false, b'u') => (self.row_position, self.column_position) = self.saved_position,
+            (true, b'h') if csi.param(0, 0) == 25 => self.cursor_visible = true,
+            (true, b'l') if csi.param(0, 0) == 25 => self.cursor_visible = false,
+            _ => {}
+        }
+    }
 
-                let color_code = self.color_code;
-                self.buffer.chars[row][col].write(ScreenChar {
-                    ascii_character: byte,
-                    color_code,
-                });
-                self.column_position += 1;
+    /// `ESC [ n J`: 0 erases to the end of the screen, 1 to the start, 2 and 3 all of it.
+    fn erase_display(&mut self, mode: u16) {
+        let row = self.row_position;
+        match mode {
+            0 => {
+                self.erase_line(0);
+                for row in row + 1..self.rows {
+                    self.clear_row(row);
+                }
+            }
+            1 => {
+                self.erase_line(1);
+                for row in 0..row {
+                    self.clear_row(row);
+                }
+            }
+            2 | 3 => {
+                if mode == 3 {
+                    SCROLLBACK.lock().clear();
+                }
+                for row in 0..self.rows {
+                    self.clear_row(row);
+                }
             }
+            _ => {}
         }
     }
 
-    fn write_string(&mut self, s: &str) {
-        for byte in s.bytes() {
-            match byte {
-                // printable ASCII byte or newline
-                0x20..=0x7e | b'\n' => self.write_byte(byte),
-                // not part of printable ASCII range
-                _ => self.write_byte(0xfe),
+    /// `ESC [ n K`: 0 erases to the end of the line, 1 to the start, 2 the whole line.
+    fn erase_line(&mut self, mode: u16) {
+        let row = self.row_position;
+        let col = usize::min(self.column_position, self.columns - 1);
+        let columns = match mode {
+            0 => col..self.columns,
+            1 => 0..col + 1,
+            2 => 0..self.columns,
+            _ => return,
+        };
+        let blank = ScreenChar::blank(self.color_code);
+        for col in columns {
+            self.write_char(row, col, blank);
+        }
+    }
+
+    fn select_graphic_rendition(&mut self, csi: &Csi) {
+        let params = csi.params();
+        if params.is_empty() {
+            self.color_code = DEFAULT_COLOR;
+            self.bold = false;
+            return;
+        }
+
+        let mut params = params.iter().copied();
+        while let Some(param) = params.next() {
+            let color = self.color_code;
+            self.color_code = match param {
+                0 => {
+                    self.bold = false;
+                    DEFAULT_COLOR
+                }
+                1 => {
+                    self.bold = true;
+                    ColorCode(color.0 | 0x08)
+                }
+                22 => {
+                    self.bold = false;
+                    ColorCode(color.0 & !0x08)
+                }
+                30..=37 => color.with_foreground(ansi_color(param - 30, self.bold)),
+                39 => color.with_foreground(DEFAULT_COLOR.0),
+                40..=47 => color.with_background(ansi_color(param - 40, false)),
+                49 => color.with_background(DEFAULT_COLOR.0 >> 4),
+                90..=97 => color.with_foreground(ansi_color(param - 90, true)),
+                100..=107 => color.with_background(ansi_color(param - 100, true)),
+                // `38;5;n` and `48;5;n` pick from 256 colors, of which only the
+                // first 16 map onto VGA; `38;2;r;g;b` and `48;2;r;g;b` are
+                // approximated
+                38 | 48 => {
+                    let value = match params.next() {
+                        Some(5) => params
+                            .next()
+                            .filter(|&index| index < 16)
+                            .map(|index| ansi_color(index, index >= 8)),
+                        Some(2) => match (params.next(), params.next(), params.next()) {
+                            (Some(r), Some(g), Some(b)) => Some(rgb_color(r, g, b)),
+                            _ => None,
+                        },
+                        _ => None,
+                    };
+                    match value {
+                        Some(value) if param == 38 => color.with_foreground(value),
+                        Some(value) => color.with_background(value),
+                        None => color,
+                    }
+                }
+                _ => color,
+            };
+        }
+    }
+
+    fn write_char(&mut self, row: usize, col: usize, screen_char: ScreenChar) {
+        self.screen[row][col] = screen_char;
+        if self.scroll_offset == 0 {
+            self.draw(row, col, screen_char);
+        }
+    }
+
+    fn draw(&mut self, row: usize, col: usize, mut screen_char: ScreenChar) {
+        if self.pointer == Some((row, col)) {
+            let color = screen_char.color_code.0;
+            screen_char.color_code = ColorCode(color.rotate_right(4));
+        }
+        match &mut self.display {
+            Display::Text(buffer) => buffer.chars[row][col].write(screen_char),
+            Display::Framebuffer { shown, cursor } => {
+                if *cursor == Some((row, col)) {
+                    *cursor = None;
+                } else if shown[row][col] == screen_char {
+                    return;
+                }
+                shown[row][col] = screen_char;
+                draw_cell(row, col, screen_char, false);
             }
         }
     }
 
+    /// Returns the character shown at `row`, `col`.
+    #[cfg(test)]
+    fn displayed(&self, row: usize, col: usize) -> ScreenChar {
+        match &self.display {
+            Display::Text(buffer) => buffer.chars[row][col].read(),
+            Display::Framebuffer { .. } => self.screen[row][col],
+        }
+    }
+
+    /// Moves the console to the framebuffer, sizing the text grid to fit it.
+    fn use_framebuffer(&mut self) {
+        let Some((width, height)) = framebuffer::with_framebuffer(|fb| (fb.width(), fb.height()))
+        else {
+            return;
+        };
+        // what the cleared framebuffer shows
+        let black = ScreenChar::blank(ColorCode(0));
+        self.display = Display::Framebuffer {
+            shown: vec![[black; MAX_COLUMNS]; MAX_ROWS].into_boxed_slice(),
+            cursor: None,
+        };
+        self.rows = usize::min(height / FONT.height(), MAX_ROWS);
+        self.columns = usize::min(width / FONT.width(), MAX_COLUMNS);
+        framebuffer::with_framebuffer(|fb| fb.fill_rect(0, 0, width, height, PALETTE[0]));
+        self.clear_screen();
+    }
+
+    /// Moves the output position to `row`, `col`; later writes continue from there.
+    pub fn set_position(&mut self, row: usize, col: usize) {
+        assert!(row < self.rows && col < self.columns);
+        self.row_position = row;
+        self.column_position = col;
+        self.update_cursor();
+    }
+
+    pub fn position(&self) -> (usize, usize) {
+        (self.row_position, self.column_position)
+    }
+
+    pub fn set_color(&mut self, foreground: Color, background: Color) {
+        self.color_code = ColorCode::new(foreground, background);
+    }
+
     fn backspace(&mut self) {
         if self.column_position <= 1 {
             return;
         }
 
-        let row = BUFFER_HEIGHT - 1;
+        self.reset_view();
 
-        let blank = ScreenChar {
-            ascii_character: b' ',
-            color_code: self.color_code,
-        };
+        let row = self.row_position;
+        let blank = ScreenChar::blank(self.color_code);
 
-        self.buffer.chars[row][self.column_position - 1].write(blank);
+        self.write_char(row, self.column_position - 1, blank);
         self.column_position -= 1;
+        self.update_cursor();
     }
 
     fn new_line(&mut self) {
-        for row in 1..BUFFER_HEIGHT {
-            for col in 0..BUFFER_WIDTH {
-                let character = self.buffer.chars[row][col].read();
-                self.buffer.chars[row - 1][col].write(character);
-            }
-        }
-        self.clear_row(BUFFER_HEIGHT - 1);
         self.column_position = 0;
+        if self.row_position < self.rows - 1 {
+            self.row_position += 1;
+            return;
+        }
+
+        SCROLLBACK.lock().push(self.screen[0]);
+        self.screen.copy_within(1..self.rows, 0);
+        self.screen[self.rows - 1] = [ScreenChar::blank(self.color_code); MAX_COLUMNS];
+        self.redraw();
     }
 
     fn clear_row(&mut self, row: usize) {
-        let blank = ScreenChar {
-            ascii_character: b' ',
-            color_code: self.color_code,
-        };
-        for col in 0..BUFFER_WIDTH {
-            self.buffer.chars[row][col].write(blank);
+        let blank = ScreenChar::blank(self.color_code);
+        for col in 0..self.columns {
+            self.write_char(row, col, blank);
         }
     }
 
     fn clear_screen(&mut self) {
-        let blank = ScreenChar {
-            ascii_character: b' ',
-            color_code: self.color_code,
-        };
+        self.scroll_offset = 0;
+        SCROLLBACK.lock().clear();
+        for row in 0..self.rows {
+            self.clear_row(row);
+        }
+        self.set_position(0, 0);
+    }
+
+    fn scroll_up(&mut self, lines: usize) {
+        let offset = usize::min(self.scroll_offset + lines, SCROLLBACK.lock().len);
+        if offset != self.scroll_offset {
+            self.scroll_offset = offset;
+            self.redraw();
+        }
+    }
+
+    fn scroll_down(&mut self, lines: usize) {
+        let offset = self.scroll_offset.saturating_sub(lines);
+        if offset != self.scroll_offset {
+            self.scroll_offset = offset;
+            self.redraw();
+        }
+    }
+
+    /// Jumps back to the live screen if the view is scrolled back.
+    fn reset_view(&mut self) {
+        if self.scroll_offset != 0 {
+            self.scroll_offset = 0;
+            self.redraw();
+        }
+    }
+
+    /// Moves the mouse pointer highlight to `pointer`.
+    fn set_pointer(&mut self, pointer: Option<(usize, usize)>) {
+        let pointer = pointer.filter(|&(row, col)| row < self.rows && col < self.columns);
+        let old = core::mem::replace(&mut self.pointer, pointer);
+        if old == pointer {
+            return;
+        }
+        for (row, col) in [old, pointer].into_iter().flatten() {
+            let screen_char = self.visible_char(row, col);
+            self.draw(row, col, screen_char);
+        }
+    }
+
+    /// Returns the character at `row`, `col` of the view at the current scroll offset.
+    fn visible_char(&self, row: usize, col: usize) -> ScreenChar {
+        let offset = self.scroll_offset;
+        if row >= offset {
+            self.screen[row - offset][col]
+        } else {
+            SCROLLBACK.lock().get(offset - row)[col]
+        }
+    }
+
+    /// Copies the lines visible at the current scroll offset to the display.
+    fn redraw(&mut self) {
+        let offset = self.scroll_offset;
+        let scrollback = SCROLLBACK.lock();
+        for row in 0..self.rows {
+            let line = if row >= offset {
+                self.screen[row - offset]
+            } else {
+                *scrollback.get(offset - row)
+            };
+            for (col, screen_char) in line[..self.columns].iter().enumerate() {
+                self.draw(row, col, *screen_char);
+            }
+        }
+        drop(scrollback);
+        self.update_cursor();
+    }
+
+    fn enable_cursor(&mut self) {
+        if !matches!(self.display, Display::Text(_)) {
+            return;
+        }
+        // cursor shape: underline on scanlines 14-15
+        crtc_write(0x0a, (crtc_read(0x0a) & 0xc0) | 14);
+        crtc_write(0x0b, (crtc_read(0x0b) & 0xe0) | 15);
+        self.update_cursor();
+    }
+
+    fn update_cursor(&mut self) {
+        // hide the cursor while looking at the scrollback or when asked to
+        let visible = self.scroll_offset == 0 && self.cursor_visible;
+        let row = self.row_position;
+        let col = usize::min(self.column_position, self.columns - 1);
+
+        match &mut self.display {
+            Display::Text(_) => {
+                if !visible {
+                    crtc_write(0x0a, crtc_read(0x0a) | 0x20);
+                    return;
+                }
+                crtc_write(0x0a, crtc_read(0x0a) & !0x20);
 
-        for row in 0..BUFFER_HEIGHT {
-            for col in 0..BUFFER_WIDTH {
-                self.buffer.chars[row][col].write(blank);
+                let pos = (row * self.columns + col) as u16;
+                crtc_write(0x0f, (pos & 0xff) as u8);
+                crtc_write(0x0e, (pos >> 8) as u8);
+            }
+            Display::Framebuffer { shown, cursor } => {
+                let new_cursor = if visible { Some((row, col)) } else { None };
+                if *cursor == new_cursor {
+                    return;
+                }
+                if let Some((old_row, old_col)) = cursor.take() {
+                    shown[old_row][old_col] = self.screen[old_row][old_col];
+                    draw_cell(old_row, old_col, self.screen[old_row][old_col], false);
+                }
+                if let Some((row, col)) = new_cursor {
+                    shown[row][col] = self.screen[row][col];
+                    draw_cell(row, col, self.screen[row][col], true);
+                }
+                *cursor = new_cursor;
             }
         }
     }
 }
 
+/// Renders one text cell into the framebuffer, underlined if it holds the cursor.
+fn draw_cell(row: usize, col: usize, screen_char: ScreenChar, cursor: bool) {
+    let color = screen_char.color_code.0;
+    let fg = PALETTE[usize::from(color & 0x0f)];
+    let bg = PALETTE[usize::from(color >> 4)];
+    let (x, y) = (col * FONT.width(), row * FONT.height());
+    framebuffer::with_framebuffer(|fb| {
+        fb.draw_glyph(x, y, usize::from(screen_char.ascii_character), fg, bg);
+        if cursor {
+            fb.fill_rect(x, y + FONT.height() - 2, FONT.width(), 2, fg);
+        }
+    });
+}
+
+const CRTC_ADDRESS_PORT: u16 = 0x3d4;
+const CRTC_DATA_PORT: u16 = 0x3d5;
+
+fn crtc_read(index: u8) -> u8 {
+    unsafe {
+        Port::new(CRTC_ADDRESS_PORT).write(index);
+        Port::new(CRTC_DATA_PORT).read()
+    }
+}
+
+fn crtc_write(index: u8, value: u8) {
+    unsafe {
+        Port::new(CRTC_ADDRESS_PORT).write(index);
+        Port::new(CRTC_DATA_PORT).write(value);
+    }
+}
+
 impl fmt::Write for Writer {
     fn write_str(&mut self, s: &str) -> fmt::Result {
         self.write_string(s);
@@ -162,6 +677,36 @@ pub fn clear_screen() {
     WRITER.lock().clear_screen();
 }
 
+pub fn scroll_up(lines: usize) {
+    WRITER.lock().scroll_up(lines);
+}
+
+pub fn scroll_down(lines: usize) {
+    WRITER.lock().scroll_down(lines);
+}
+
+pub fn set_position(row: usize, col: usize) {
+    WRITER.lock().set_position(row, col);
+}
+
+/// Highlights the cell at `(row, col)` as the mouse pointer, or hides it.
+pub fn set_pointer(pointer: Option<(usize, usize)>) {
+    use x86_64::instructions::interrupts;
+    interrupts::without_interrupts(|| WRITER.lock().set_pointer(pointer));
+}
+
+/// Switches console output to the framebuffer set up by `framebuffer::init`.
+pub fn use_framebuffer() {
+    use x86_64::instructions::interrupts;
+    interrupts::without_interrupts(|| WRITER.lock().use_framebuffer());
+}
+
+/// Returns the size of the text grid as `(rows, columns)`.
+pub fn size() -> (usize, usize) {
+    let writer = WRITER.lock();
+    (writer.rows, writer.columns)
+}
+
 #[macro_export]
 macro_rules! print {
     ($($arg:tt)*) => ($crate::vga_buffer::_print(format_args!($($arg)*)));
@@ -173,6 +718,22 @@ macro_rules! println {
     ($($arg:tt)*) => ($crate::print!("{}\n", format_args!($($arg)*)));
 }
 
+/// Prints with the given foreground and background colors.
+#[macro_export]
+macro_rules! print_colored {
+    ($fg:expr, $bg:expr, $($arg:tt)*) => (
+        $crate::vga_buffer::_print_colored($fg, $bg, format_args!($($arg)*))
+    );
+}
+
+/// Prints at the given row and column, leaving the output position after the text.
+#[macro_export]
+macro_rules! print_at {
+    ($row:expr, $col:expr, $($arg:tt)*) => (
+        $crate::vga_buffer::_print_at($row, $col, format_args!($($arg)*))
+    );
+}
+
 #[doc(hidden)]
 pub fn _print(args: fmt::Arguments) {
     use core::fmt::Write;
@@ -182,6 +743,43 @@ pub fn _print(args: fmt::Arguments) {
     });
 }
 
+/// Like `_print`, but gives up instead of spinning if the writer is busy, so it
+/// is safe to call from interrupt handlers.
+#[doc(hidden)]
+pub fn _try_print(args: fmt::Arguments) {
+    use core::fmt::Write;
+    use x86_64::instructions::interrupts;
+    interrupts::without_interrupts(|| {
+        if let Some(mut writer) = WRITER.try_lock() {
+            writer.write_fmt(args).unwrap();
+        }
+    });
+}
+
+#[doc(hidden)]
+pub fn _print_colored(foreground: Color, background: Color, args: fmt::Arguments) {
+    use core::fmt::Write;
+    use x86_64::instructions::interrupts;
+    interrupts::without_interrupts(|| {
+        let mut writer = WRITER.lock();
+        let color_code = writer.color_code;
+        writer.set_color(foreground, background);
+        writer.write_fmt(args).unwrap();
+        writer.color_code = color_code;
+    });
+}
+
+#[doc(hidden)]
+pub fn _print_at(row: usize, col: usize, args: fmt::Arguments) {
+    use core::fmt::Write;
+    use x86_64::instructions::interrupts;
+    interrupts::without_interrupts(|| {
+        let mut writer = WRITER.lock();
+        writer.set_position(row, col);
+        writer.write_fmt(args).unwrap();
+    });
+}
+
 #[test_case]
 fn test_println_simple() {
     println!("test_println_simple output");
@@ -204,8 +802,101 @@ fn test_println_output() {
         let mut writer = WRITER.lock();
         writeln!(writer, "\n{}", s).expect("writeln failed");
         for (i, c) in s.chars().enumerate() {
-            let screen_char = writer.buffer.chars[BUFFER_HEIGHT - 2][i].read();
+            let screen_char = writer.displayed(writer.rows - 2, i);
             assert_eq!(char::from(screen_char.ascii_character), c);
         }
     });
 }
+
+#[test_case]
+fn test_print_colored() {
+    use x86_64::instructions::interrupts;
+
+    print_colored!(Color::Red, Color::Blue, "\nx");
+    interrupts::without_interrupts(|| {
+        let writer = WRITER.lock();
+        let (row, col) = writer.position();
+        let screen_char = writer.displayed(row, col - 1);
+        assert_eq!(
+            screen_char.color_code,
+            ColorCode::new(Color::Red, Color::Blue)
+        );
+        assert_ne!(writer.color_code, screen_char.color_code);
+    });
+}
+
+#[test_case]
+fn test_scrollback() {
+    use core::fmt::Write;
+    use x86_64::instructions::interrupts;
+
+    interrupts::without_interrupts(|| {
+        let mut writer = WRITER.lock();
+        let rows = writer.rows;
+        writer.set_position(rows - 1, 0);
+        writeln!(writer, "scrolled away").expect("writeln failed");
+        for _ in 0..rows - 1 {
+            writeln!(writer).expect("writeln failed");
+        }
+
+        writer.scroll_up(1);
+        let screen_char = writer.displayed(0, 0);
+        assert_eq!(char::from(screen_char.ascii_character), 's');
+
+        writer.scroll_down(1);
+        let screen_char = writer.displayed(0, 0);
+        assert_eq!(char::from(screen_char.ascii_character), ' ');
+    });
+}
+
+#[test_case]
+fn test_ansi_color() {
+    use x86_64::instructions::interrupts;
+
+    print!("\n\x1b[31;44mx\x1b[0my");
+    interrupts::without_interrupts(|| {
+        let writer = WRITER.lock();
+        let (row, col) = writer.position();
+        let colored = writer.displayed(row, col - 2);
+        assert_eq!(colored.color_code, ColorCode::new(Color::Red, Color::Blue));
+        let reset = writer.displayed(row, col - 1);
+        assert_eq!(reset.color_code, DEFAULT_COLOR);
+    });
+
+    // all three components are consumed, so the background still applies
+    print!("\n\x1b[38;2;0;0;170;41mx\x1b[0m");
+    interrupts::without_interrupts(|| {
+        let writer = WRITER.lock();
+        let (row, col) = writer.position();
+        let colored = writer.displayed(row, col - 1);
+        assert_eq!(colored.color_code, ColorCode::new(Color::Blue, Color::Red));
+    });
+}
+
+#[test_case]
+fn test_ansi_cursor_movement() {
+    use x86_64::instructions::interrupts;
+
+    print!("\n\x1b[3;5Hz\x1b[2D\x1b[1Aw");
+    interrupts::without_interrupts(|| {
+        let writer = WRITER.lock();
+        assert_eq!(writer.displayed(2, 4).ascii_character, b'z');
+        assert_eq!(writer.displayed(1, 3).ascii_character, b'w');
+        assert_eq!(writer.position(), (1, 4));
+    });
+}
+
+#[test_case]
+fn test_print_cp437() {
+    use x86_64::instructions::interrupts;
+
+    print!("\né─漢");
+    interrupts::without_interrupts(|| {
+        let writer = WRITER.lock();
+        let (row, _) = writer.position();
+        let glyphs = [0x82, 0xc4, cp437::REPLACEMENT];
+        for (col, glyph) in glyphs.into_iter().enumerate() {
+            assert_eq!(writer.displayed(row, col).ascii_character, glyph);
+        }
+    });
+}
@@ -0,0 +1,49 @@
+# Copies standard input to standard output until the end of the data.
+#
+#   as --64 -o cat.o cat.s
+#   ld -T link.ld -z max-page-size=4096 --build-id=none -o cat cat.o
+
+.intel_syntax noprefix
+
+.equ SYS_READ, 0
+.equ SYS_WRITE, 1
+.equ SYS_EXIT, 4
+
+.equ BUFFER_SIZE, 4096
+
+.section .text
+.global _start
+_start:
+.Lread:
+    mov eax, SYS_READ
+    xor edi, edi
+    lea rsi, [rip + buffer]
+    mov edx, BUFFER_SIZE
+    syscall
+    test rax, rax
+    js .Lfail
+    jz .Ldone
+
+    # the pipe or console takes all of it, or fails
+    mov rdx, rax
+    mov eax, SYS_WRITE
+    mov edi, 1
+    lea rsi, [rip + buffer]
+    syscall
+    test rax, rax
+    js .Lfail
+    jmp .Lread
+
+.Ldone:
+    mov eax, SYS_EXIT
+    xor edi, edi
+    syscall
+
+.Lfail:
+    mov eax, SYS_EXIT
+    mov edi, 1
+    syscall
+
+.section .bss
+buffer:
+    .skip BUFFER_SIZE
@@ -0,0 +1,72 @@
+# Prints its arguments separated by spaces, like the shell's `echo`.
+#
+#   as --64 -o echo.o echo.s
+#   ld -T link.ld -z max-page-size=4096 --build-id=none -o echo echo.o
+
+.intel_syntax noprefix
+
+.equ SYS_WRITE, 1
+.equ SYS_EXIT, 4
+
+.section .text
+.global _start
+_start:
+    mov r12, [rsp]              # argc
+    lea r13, [rsp + 8]          # argv
+    mov r14, 1                  # skip the program name
+    jmp .Lcheck
+
+.Lnext:
+    cmp r14, 1
+    je .Lword
+    mov eax, SYS_WRITE
+    mov edi, 1
+    lea rsi, [rip + space]
+    mov edx, 1
+    syscall
+
+.Lword:
+    mov rsi, [r13 + r14 * 8]
+    xor edx, edx
+.Llength:
+    cmp byte ptr [rsi + rdx], 0
+    je .Lwrite
+    inc rdx
+    jmp .Llength
+.Lwrite:
+    mov eax, SYS_WRITE
+    mov edi, 1
+    syscall
+    test rax, rax
+    js .Lfail
+    add [rip + written], rax
+    inc r14
+
+.Lcheck:
+    cmp r14, r12
+    jb .Lnext
+
+    mov eax, SYS_WRITE
+    mov edi, 1
+    lea rsi, [rip + newline]
+    mov edx, 1
+    syscall
+
+    mov eax, SYS_EXIT
+    xor edi, edi
+    syscall
+
+.Lfail:
+    mov eax, SYS_EXIT
+    mov edi, 1
+    syscall
+
+.section .rodata
+space:
+    .ascii " "
+newline:
+    .ascii "\n"
+
+.section .bss
+written:
+    .quad 0